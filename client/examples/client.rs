@@ -1,4 +1,5 @@
-use rusty_redis_client::Response;
+use rusty_redis_client::{Command, Response, SetOptions};
+use tokio_stream::StreamExt;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
@@ -21,10 +22,14 @@ async fn main() -> Result<(), anyhow::Error> {
         println!("SET 4321 0");
         print_response(response)?;
 
-        let response = conn.keys().await?;
+        let response = conn.keys(None).await?;
         println!("KEYS");
         print_response(response)?;
 
+        let response = conn.keys(Some("4*")).await?;
+        println!("KEYS 4*");
+        print_response(response)?;
+
         let response = conn.delete("1234".to_string()).await?;
         println!("DEL 1234");
         print_response(response)?;
@@ -33,7 +38,7 @@ async fn main() -> Result<(), anyhow::Error> {
         println!("GET 1234");
         print_response(response)?;
 
-        let response = conn.keys().await?;
+        let response = conn.keys(None).await?;
         println!("KEYS");
         print_response(response)?;
 
@@ -41,6 +46,37 @@ async fn main() -> Result<(), anyhow::Error> {
         println!("GET 4321");
         print_response(response)?;
 
+        let mut pipeline = conn.pipeline();
+        for i in 0..5 {
+            pipeline.add(Command::SET(format!("pipelined:{i}"), format!("value {i}").into_bytes(), SetOptions::new()));
+        }
+        pipeline.add(Command::KEYS(Some("pipelined:*".to_string())));
+        println!("PIPELINE SET x5, KEYS pipelined:*");
+        for response in pipeline.execute().await? {
+            print_response(response)?;
+        }
+
+        let response = conn.hset("user:1".to_string(), vec![
+            (b"name".to_vec(), b"Ann".to_vec()),
+            (b"visits".to_vec(), b"1".to_vec()),
+        ]).await?;
+        println!("HSET user:1 name Ann visits 1");
+        print_response(response)?;
+
+        let response = conn.hincrby("user:1".to_string(), b"visits".to_vec(), 1).await?;
+        println!("HINCRBY user:1 visits 1");
+        print_response(response)?;
+
+        let response = conn.hgetall("user:1".to_string()).await?;
+        println!("HGETALL user:1");
+        print_response(response)?;
+
+        println!("SCAN MATCH pipelined:*");
+        let mut keys = std::pin::pin!(conn.scan(Some("pipelined:*"), None));
+        while let Some(key) = keys.next().await {
+            println!("{}", key?);
+        }
+
     } else {
         eprintln!("Failed to connect to server");
     }
@@ -52,10 +88,20 @@ fn print_response(response: Response) -> Result<(), anyhow::Error> {
         Response::Empty => println!("Empty Response"),
         Response::Error(s) => println!("{s}"),
         Response::String(s) => println!("{s}"),
-        Response::Int(_) => todo!(),
+        Response::Int(i) => println!("{i}"),
         Response::Float(_) => todo!(),
         Response::Array(list) => list.iter().for_each(|s| println!("{s}")),
         Response::Data(data) => println!("{}", std::str::from_utf8(data.as_slice()).unwrap()),
+        Response::Multi(responses) | Response::Push(responses) => {
+            for response in responses {
+                print_response(response)?;
+            }
+        },
+        Response::Map(pairs) => {
+            for (field, value) in pairs {
+                println!("{}: {}", String::from_utf8_lossy(&field), String::from_utf8_lossy(&value));
+            }
+        },
     };
     Ok(())
 }
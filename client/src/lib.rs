@@ -1,25 +1,41 @@
-use std::io::{ErrorKind, Error};
+use std::fmt::{self, Display};
+use std::io::Error;
 use std::{str, io};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio_stream::Stream;
+use anyhow::anyhow;
+use async_stream::try_stream;
 
-pub use rusty_redis_core::command::Command;
+mod subscriber;
+
+pub use subscriber::Subscriber;
+pub use rusty_redis_core::command::{Command, Expiry, ScoreBound, SetCondition, SetOptions, DEFAULT_SCAN_COUNT};
+pub use rusty_redis_core::pattern::glob_match;
+pub use rusty_redis_core::pubsub::{Message, Push};
 pub use rusty_redis_core::response::Response;
-use rusty_redis_core::BUF_MAX;
+use rusty_redis_core::frame::{encode_frame, FrameReader};
 
 pub struct RedisClientConnection<T>
 where
     T: AsyncReadExt + AsyncWriteExt + Unpin,
 {
     stream: T,
+    reader: FrameReader,
 }
 
 impl<T> RedisClientConnection<T>
 where
     T: AsyncReadExt + AsyncWriteExt + Unpin,
 {
-    fn new(stream: T) -> Self {
-        RedisClientConnection { stream }
+    pub fn new(stream: T) -> Self {
+        RedisClientConnection { stream, reader: FrameReader::default() }
+    }
+
+    /// Limit the size of responses this connection will accept.
+    pub fn with_max_frame_size(stream: T, max_frame_size: usize) -> Self {
+        RedisClientConnection { stream, reader: FrameReader::new(max_frame_size) }
     }
 }
 
@@ -30,7 +46,7 @@ pub async fn connect(url: &str) -> Result<RedisClientConnection<TcpStream>, io::
 impl RedisClientConnection<TcpStream> {
     async fn connect(url: &str) -> Result<Self, Error> {
         let stream = TcpStream::connect(url).await?;
-        Ok(RedisClientConnection{stream})
+        Ok(RedisClientConnection::new(stream))
     }
 }
 
@@ -44,42 +60,627 @@ where
         self.read_response().await
     }
 
-    /// Return a full list all keys present on the cache server.
-    pub async fn keys(&mut self) -> Result<Response, anyhow::Error> {
-        self.write_command(Command::KEYS).await?;
+    /// Return every key on the cache server matching the glob `pattern`, or all of them without one.
+    pub async fn keys(&mut self, pattern: Option<&str>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::KEYS(pattern.map(String::from))).await?;
         self.read_response().await
     }
 
     /// Store a key->value in the cache.
     pub async fn set(&mut self, key: String, value: Vec<u8>, ttl: u64) -> Result<Response, anyhow::Error> {
-        self.write_command(Command::SET(key, value, ttl)).await?;
+        self.set_with(key, value, SetOptions::ttl(ttl)).await
+    }
+
+    /// Store a key->value with conditions and expiry built up on `options`,
+    /// e.g. `SetOptions::new().nx().px(30_000)` to take a lock that expires.
+    ///
+    /// Replies with empty when a condition stops the write, or the previous value when `get` is set.
+    pub async fn set_with(&mut self, key: String, value: Vec<u8>, options: SetOptions) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SET(key, value, options)).await?;
         self.read_response().await
     }
 
     /// Remove an item from cache with the given key.
     pub async fn delete(&mut self, key: String) -> Result<Response, anyhow::Error> {
-        self.write_command(Command::DELETE(key)).await?;
+        self.delete_keys(vec![key]).await
+    }
+
+    /// Remove every one of `keys`, replying with how many existed.
+    pub async fn delete_keys(&mut self, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::DELETE(keys)).await?;
         self.read_response().await
     }
 
-    async fn read_response(&mut self) -> Result<Response, anyhow::Error> {
-        let mut buffer = [0u8; BUF_MAX];
-        let b = self.stream.read(&mut buffer).await?;
-        if b == 0 {
-            return Err(io::Error::new(ErrorKind::UnexpectedEof, "Stream read 0 bytes").into())
+    /// Like `delete_keys`, but the server frees the values in the background.
+    pub async fn unlink(&mut self, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::UNLINK(keys)).await?;
+        self.read_response().await
+    }
+
+    /// How many of `keys` exist, a key given twice is counted twice.
+    pub async fn exists(&mut self, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::EXISTS(keys)).await?;
+        self.read_response().await
+    }
+
+    /// The value of each of `keys` in one round trip,
+    /// None for a missing key or one that is not a string.
+    pub async fn mget(&mut self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>, anyhow::Error> {
+        self.write_command(Command::MGET(keys)).await?;
+        match self.read_response().await? {
+            Response::Multi(values) => values.into_iter()
+                .map(|value| match value {
+                    Response::Data(data) => Ok(Some(data)),
+                    Response::Empty => Ok(None),
+                    other => Err(anyhow!("Unexpected MGET value {:?}", other)),
+                })
+                .collect(),
+            Response::Error(e) => Err(anyhow!(e)),
+            other => Err(anyhow!("Unexpected MGET response {:?}", other)),
         }
-        Ok(Response::deserialize(&buffer)?)
+    }
+
+    /// Store every key->value pair at once, without expiry.
+    pub async fn mset(&mut self, pairs: Vec<(String, Vec<u8>)>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::MSET(pairs)).await?;
+        self.read_response().await
+    }
+
+    /// Store every pair only if none of the keys exist. Replies 1 if they were stored, otherwise 0.
+    pub async fn msetnx(&mut self, pairs: Vec<(String, Vec<u8>)>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::MSETNX(pairs)).await?;
+        self.read_response().await
+    }
+
+    /// Write a snapshot of the cache to disk, returning once it has been saved.
+    pub async fn save(&mut self) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SAVE).await?;
+        self.read_response().await
+    }
+
+    /// Start writing a snapshot of the cache to disk in the background.
+    pub async fn bgsave(&mut self) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::BGSAVE).await?;
+        self.read_response().await
+    }
+
+    /// Compact the server's append only file in the background.
+    pub async fn bgrewriteaof(&mut self) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::BGREWRITEAOF).await?;
+        self.read_response().await
+    }
+
+    /// Read server settings, returned as an array of name and value pairs.
+    pub async fn config_get(&mut self, pattern: &str) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::CONFIGGET(pattern.to_string())).await?;
+        self.read_response().await
+    }
+
+    /// Change a server setting that can be adjusted at runtime.
+    pub async fn config_set(&mut self, name: &str, value: &str) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::CONFIGSET(name.to_string(), value.to_string())).await?;
+        self.read_response().await
+    }
+
+    /// Push values onto the head of a list, returning its new length.
+    pub async fn lpush(&mut self, key: String, values: Vec<Vec<u8>>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::LPUSH(key, values)).await?;
+        self.read_response().await
+    }
+
+    /// Push values onto the tail of a list, returning its new length.
+    pub async fn rpush(&mut self, key: String, values: Vec<Vec<u8>>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::RPUSH(key, values)).await?;
+        self.read_response().await
+    }
+
+    /// Pop a value from the head of a list, or up to `count` values as a `Response::Multi`.
+    pub async fn lpop(&mut self, key: String, count: Option<u64>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::LPOP(key, count)).await?;
+        self.read_response().await
+    }
+
+    /// Pop a value from the tail of a list, or up to `count` values as a `Response::Multi`.
+    pub async fn rpop(&mut self, key: String, count: Option<u64>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::RPOP(key, count)).await?;
+        self.read_response().await
+    }
+
+    /// Values between two indexes inclusive, negative indexes count back from the tail.
+    pub async fn lrange(&mut self, key: String, start: i64, stop: i64) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::LRANGE(key, start, stop)).await?;
+        self.read_response().await
+    }
+
+    pub async fn llen(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::LLEN(key)).await?;
+        self.read_response().await
+    }
+
+    pub async fn lindex(&mut self, key: String, index: i64) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::LINDEX(key, index)).await?;
+        self.read_response().await
+    }
+
+    /// Trim a list down to the values between two indexes inclusive.
+    pub async fn ltrim(&mut self, key: String, start: i64, stop: i64) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::LTRIM(key, start, stop)).await?;
+        self.read_response().await
+    }
+
+    /// Set fields of a hash, returning how many of them are new.
+    pub async fn hset(&mut self, key: String, pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::HSET(key, pairs)).await?;
+        self.read_response().await
+    }
+
+    pub async fn hget(&mut self, key: String, field: Vec<u8>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::HGET(key, field)).await?;
+        self.read_response().await
+    }
+
+    /// Remove fields from a hash, returning how many existed.
+    pub async fn hdel(&mut self, key: String, fields: Vec<Vec<u8>>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::HDEL(key, fields)).await?;
+        self.read_response().await
+    }
+
+    /// Every field and value of a hash as a `Response::Map`.
+    pub async fn hgetall(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::HGETALL(key)).await?;
+        self.read_response().await
+    }
+
+    /// Add to the integer held in a hash field, returning the new value.
+    pub async fn hincrby(&mut self, key: String, field: Vec<u8>, increment: i64) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::HINCRBY(key, field, increment)).await?;
+        self.read_response().await
+    }
+
+    pub async fn hkeys(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::HKEYS(key)).await?;
+        self.read_response().await
+    }
+
+    pub async fn hlen(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::HLEN(key)).await?;
+        self.read_response().await
+    }
+
+    /// Add members to a set, returning how many were not already in it.
+    pub async fn sadd(&mut self, key: String, members: Vec<Vec<u8>>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SADD(key, members)).await?;
+        self.read_response().await
+    }
+
+    /// Remove members from a set, returning how many were in it.
+    pub async fn srem(&mut self, key: String, members: Vec<Vec<u8>>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SREM(key, members)).await?;
+        self.read_response().await
+    }
+
+    /// 1 if `member` is in the set, otherwise 0.
+    pub async fn sismember(&mut self, key: String, member: Vec<u8>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SISMEMBER(key, member)).await?;
+        self.read_response().await
+    }
+
+    pub async fn smembers(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SMEMBERS(key)).await?;
+        self.read_response().await
+    }
+
+    pub async fn scard(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SCARD(key)).await?;
+        self.read_response().await
+    }
+
+    /// Members found in every one of the sets.
+    pub async fn sinter(&mut self, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SINTER(keys)).await?;
+        self.read_response().await
+    }
+
+    /// Members found in any of the sets.
+    pub async fn sunion(&mut self, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SUNION(keys)).await?;
+        self.read_response().await
+    }
+
+    /// Members of the first set that are in none of the others.
+    pub async fn sdiff(&mut self, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SDIFF(keys)).await?;
+        self.read_response().await
+    }
+
+    /// Store the intersection of the sets in `destination`, returning its size.
+    pub async fn sinterstore(&mut self, destination: String, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SINTERSTORE(destination, keys)).await?;
+        self.read_response().await
+    }
+
+    /// Store the union of the sets in `destination`, returning its size.
+    pub async fn sunionstore(&mut self, destination: String, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SUNIONSTORE(destination, keys)).await?;
+        self.read_response().await
+    }
+
+    /// Store the difference of the sets in `destination`, returning its size.
+    pub async fn sdiffstore(&mut self, destination: String, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SDIFFSTORE(destination, keys)).await?;
+        self.read_response().await
+    }
+
+    /// Add members with their scores to a sorted set, or update their scores.
+    /// Returns how many members are new.
+    pub async fn zadd(&mut self, key: String, entries: Vec<(f64, Vec<u8>)>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::ZADD(key, entries)).await?;
+        self.read_response().await
+    }
+
+    /// Remove members from a sorted set, returning how many were in it.
+    pub async fn zrem(&mut self, key: String, members: Vec<Vec<u8>>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::ZREM(key, members)).await?;
+        self.read_response().await
+    }
+
+    pub async fn zscore(&mut self, key: String, member: Vec<u8>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::ZSCORE(key, member)).await?;
+        self.read_response().await
+    }
+
+    /// Zero based rank of a member, ordered from the lowest score.
+    pub async fn zrank(&mut self, key: String, member: Vec<u8>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::ZRANK(key, member)).await?;
+        self.read_response().await
+    }
+
+    /// Members between two ranks inclusive. With scores they come back as a `Response::Map`.
+    pub async fn zrange(&mut self, key: String, start: i64, stop: i64, with_scores: bool) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::ZRANGE(key, start, stop, with_scores)).await?;
+        self.read_response().await
+    }
+
+    /// Members with a score between `min` and `max`. With scores they come back as a `Response::Map`.
+    pub async fn zrange_by_score(&mut self, key: String, min: ScoreBound, max: ScoreBound, with_scores: bool) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::ZRANGEBYSCORE(key, min, max, with_scores)).await?;
+        self.read_response().await
+    }
+
+    /// Add to the score of a member, returning the new score.
+    pub async fn zincrby(&mut self, key: String, increment: f64, member: Vec<u8>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::ZINCRBY(key, increment, member)).await?;
+        self.read_response().await
+    }
+
+    pub async fn zcard(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::ZCARD(key)).await?;
+        self.read_response().await
+    }
+
+    /// Add one to the integer at `key`, returning the new value.
+    pub async fn incr(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::INCR(key)).await?;
+        self.read_response().await
+    }
+
+    pub async fn decr(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::DECR(key)).await?;
+        self.read_response().await
+    }
+
+    pub async fn incr_by(&mut self, key: String, increment: i64) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::INCRBY(key, increment)).await?;
+        self.read_response().await
+    }
+
+    pub async fn decr_by(&mut self, key: String, decrement: i64) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::DECRBY(key, decrement)).await?;
+        self.read_response().await
+    }
+
+    /// Add `increment` to the number at `key`, the new value comes back as data.
+    pub async fn incr_by_float(&mut self, key: String, increment: f64) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::INCRBYFLOAT(key, increment)).await?;
+        self.read_response().await
+    }
+
+    /// Time left before `key` expires, rounded up to the second.
+    /// None if the key does not exist, `Some(None)` if it never expires.
+    pub async fn ttl(&mut self, key: String) -> Result<Option<Option<Duration>>, anyhow::Error> {
+        self.write_command(Command::TTL(key)).await?;
+        parse_ttl(self.read_response().await?, Duration::from_secs)
+    }
+
+    /// Time left before `key` expires, to the millisecond.
+    /// None if the key does not exist, `Some(None)` if it never expires.
+    pub async fn pttl(&mut self, key: String) -> Result<Option<Option<Duration>>, anyhow::Error> {
+        self.write_command(Command::PTTL(key)).await?;
+        parse_ttl(self.read_response().await?, Duration::from_millis)
+    }
+
+    /// Expire `key` after `ttl`, a whole number of seconds, see `pexpire` for anything finer.
+    /// Replies 1 if the key exists, otherwise 0.
+    pub async fn expire(&mut self, key: String, ttl: Duration) -> Result<Response, anyhow::Error> {
+        if ttl.subsec_nanos() != 0 {
+            return Err(anyhow!("EXPIRE takes whole seconds, use pexpire for {:?}", ttl));
+        }
+        self.write_command(Command::EXPIRE(key, ttl.as_secs().try_into()?)).await?;
+        self.read_response().await
+    }
+
+    pub async fn pexpire(&mut self, key: String, ttl: Duration) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::PEXPIRE(key, ttl.as_millis().try_into()?)).await?;
+        self.read_response().await
+    }
+
+    /// Expire `key` at the second `at` falls in, a time already past deletes it.
+    pub async fn expire_at(&mut self, key: String, at: SystemTime) -> Result<Response, anyhow::Error> {
+        let at = at.duration_since(UNIX_EPOCH)?.as_secs().try_into()?;
+        self.write_command(Command::EXPIREAT(key, at)).await?;
+        self.read_response().await
+    }
+
+    pub async fn pexpire_at(&mut self, key: String, at: SystemTime) -> Result<Response, anyhow::Error> {
+        let at = at.duration_since(UNIX_EPOCH)?.as_millis().try_into()?;
+        self.write_command(Command::PEXPIREAT(key, at)).await?;
+        self.read_response().await
+    }
+
+    /// Remove the expiry from `key`. Replies 1 if it had one, otherwise 0.
+    pub async fn persist(&mut self, key: String) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::PERSIST(key)).await?;
+        self.read_response().await
+    }
+
+    /// Walk the keyspace with SCAN, yielding keys matching `pattern` as each batch arrives.
+    ///
+    /// A key may be yielded more than once if the server resizes its table mid scan.
+    pub fn scan<'a>(&'a mut self, pattern: Option<&'a str>, count: Option<u64>) -> impl Stream<Item = Result<String, anyhow::Error>> + 'a {
+        let count = count.unwrap_or(DEFAULT_SCAN_COUNT);
+        try_stream! {
+            let mut cursor = 0u64;
+            loop {
+                self.write_command(Command::SCAN(cursor, pattern.map(String::from), count, None)).await?;
+                let (next, keys) = parse_scan(self.read_response().await?)?;
+                for key in keys {
+                    yield key;
+                }
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+        }
+    }
+
+    /// Send `message` to `channel`, replying with how many subscribers received it.
+    pub async fn publish(&mut self, channel: String, message: Vec<u8>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::PUBLISH(channel, message)).await?;
+        self.read_response().await
+    }
+
+    /// Run a Rhai script on the server, it sees `keys` as `KEYS` and `args` as `ARGV`.
+    pub async fn eval(&mut self, script: &str, keys: Vec<String>, args: Vec<Vec<u8>>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::EVAL(script.to_string(), keys, args)).await?;
+        self.read_response().await
+    }
+
+    /// Run a script already cached on the server by the SHA1 of its source.
+    pub async fn evalsha(&mut self, sha: &str, keys: Vec<String>, args: Vec<Vec<u8>>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::EVALSHA(sha.to_string(), keys, args)).await?;
+        self.read_response().await
+    }
+
+    /// Cache a script on the server without running it, returning the SHA1 to pass to `evalsha`.
+    pub async fn script_load(&mut self, script: &str) -> Result<String, anyhow::Error> {
+        self.write_command(Command::SCRIPTLOAD(script.to_string())).await?;
+        match self.read_response().await? {
+            Response::Data(sha) => Ok(String::from_utf8(sha)?),
+            Response::String(sha) => Ok(sha),
+            Response::Error(e) => Err(anyhow!(e)),
+            other => Err(anyhow!("Unexpected SCRIPT LOAD response {:?}", other)),
+        }
+    }
+
+    /// Stop the script running on the server, as long as it hasn't written anything yet.
+    pub async fn script_kill(&mut self) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::SCRIPTKILL).await?;
+        self.read_response().await
+    }
+
+    /// Queue up several commands to be sent in a single round trip.
+    pub fn pipeline(&mut self) -> Pipeline<'_, T> {
+        Pipeline { conn: self, commands: vec![] }
+    }
+
+    /// Queue up several commands to be run atomically with MULTI/EXEC.
+    pub fn transaction(&mut self) -> Transaction<'_, T> {
+        Transaction { conn: self, commands: vec![] }
+    }
+
+    /// Make the next transaction fail with [`WatchedKeyModified`] if any of `keys` is written before it runs.
+    pub async fn watch(&mut self, keys: Vec<String>) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::WATCH(keys)).await?;
+        self.read_response().await
+    }
+
+    pub async fn unwatch(&mut self) -> Result<Response, anyhow::Error> {
+        self.write_command(Command::UNWATCH).await?;
+        self.read_response().await
+    }
+
+    async fn read_response(&mut self) -> Result<Response, anyhow::Error> {
+        let frame = self.reader.read_frame(&mut self.stream).await?;
+        Response::deserialize(&frame)
     }
 
     async fn write_command(&mut self, cmd: Command) -> io::Result<()> {
-        self.stream.write_all(cmd.encode().as_slice()).await?;
+        self.stream.write_all(&encode_frame(&cmd.encode())).await?;
         Ok(())
     }
 }
 
+/// Split a SCAN response into the next cursor and the batch of keys.
+fn parse_scan(response: Response) -> Result<(u64, Vec<String>), anyhow::Error> {
+    match response {
+        Response::Multi(parts) => match parts.as_slice() {
+            [Response::Data(cursor), Response::Array(keys)] => {
+                let cursor = str::from_utf8(cursor)?.parse::<u64>()?;
+                Ok((cursor, keys.to_vec()))
+            },
+            _ => Err(anyhow!("Unexpected SCAN response {:?}", parts)),
+        },
+        Response::Error(e) => Err(anyhow!(e)),
+        other => Err(anyhow!("Unexpected SCAN response {:?}", other)),
+    }
+}
+
+/// The remaining time from a TTL or PTTL response, None for a key that does not exist
+/// (-2) and `Some(None)` for one that never expires (-1).
+fn parse_ttl(response: Response, unit: fn(u64) -> Duration) -> Result<Option<Option<Duration>>, anyhow::Error> {
+    match response {
+        Response::Int(-2) => Ok(None),
+        Response::Int(-1) => Ok(Some(None)),
+        Response::Int(ttl) if ttl >= 0 => Ok(Some(Some(unit(ttl as u64)))),
+        Response::Error(e) => Err(anyhow!(e)),
+        other => Err(anyhow!("Unexpected TTL response {:?}", other)),
+    }
+}
+
+/// A batch of commands written to the server back to back,
+/// without waiting for a response in between.
+pub struct Pipeline<'a, T>
+where
+    T: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    conn: &'a mut RedisClientConnection<T>,
+    commands: Vec<Command>,
+}
+
+impl<'a, T> Pipeline<'a, T>
+where
+    T: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    /// Queue a command to be sent when the pipeline is executed.
+    pub fn add(&mut self, cmd: Command) -> &mut Self {
+        self.commands.push(cmd);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Send every queued command and collect their responses, in the order the commands were added.
+    pub async fn execute(self) -> Result<Vec<Response>, anyhow::Error> {
+        let conn = self.conn;
+        let request = self.commands.iter().fold(Vec::new(), |mut acc, cmd| {
+            acc.extend_from_slice(&encode_frame(&cmd.encode()));
+            acc
+        });
+
+        // Responses are read while the request is still being written, otherwise
+        // a large pipeline could fill both socket buffers and stall.
+        let (mut rd, mut wr) = tokio::io::split(&mut conn.stream);
+        let reader = &mut conn.reader;
+        let write = async {
+            wr.write_all(&request).await?;
+            wr.flush().await?;
+            Ok::<(), anyhow::Error>(())
+        };
+        let read = async {
+            let mut responses = Vec::with_capacity(self.commands.len());
+            for _ in 0..self.commands.len() {
+                let frame = reader.read_frame(&mut rd).await?;
+                responses.push(Response::deserialize(&frame)?);
+            }
+            Ok::<Vec<Response>, anyhow::Error>(responses)
+        };
+        let (_, responses) = tokio::try_join!(write, read)?;
+        Ok(responses)
+    }
+}
+
+/// Commands sent between MULTI and EXEC, the server runs them all without
+/// any other client's commands in between.
+pub struct Transaction<'a, T>
+where
+    T: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    conn: &'a mut RedisClientConnection<T>,
+    commands: Vec<Command>,
+}
+
+impl<'a, T> Transaction<'a, T>
+where
+    T: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    /// Queue a command to be run when the transaction is executed.
+    pub fn add(&mut self, cmd: Command) -> &mut Self {
+        self.commands.push(cmd);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Run the queued commands and collect their responses, in the order the commands were added.
+    /// Fails with [`WatchedKeyModified`] when a watched key changed, nothing was run and it is safe to retry.
+    pub async fn execute(self) -> Result<Vec<Response>, anyhow::Error> {
+        let mut pipeline = self.conn.pipeline();
+        pipeline.add(Command::MULTI);
+        for cmd in self.commands {
+            pipeline.add(cmd);
+        }
+        pipeline.add(Command::EXEC);
+        let mut responses = pipeline.execute().await?;
+        match responses.pop() {
+            Some(Response::Multi(results)) => Ok(results),
+            Some(Response::Empty) => Err(WatchedKeyModified.into()),
+            // EXECABORT says little on its own, the reason is in the reply to the command that failed to queue.
+            Some(Response::Error(e)) => {
+                let reason = responses.into_iter().find_map(|response| match response {
+                    Response::Error(reason) => Some(reason),
+                    _ => None,
+                });
+                match reason {
+                    Some(reason) => Err(anyhow!("{}: {}", e, reason)),
+                    None => Err(anyhow!(e)),
+                }
+            },
+            other => Err(anyhow!("Unexpected EXEC response {:?}", other)),
+        }
+    }
+}
+
+/// A transaction was not run because a key it WATCHed was written first.
+#[derive(Debug)]
+pub struct WatchedKeyModified;
+
+impl Display for WatchedKeyModified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "watched key modified")
+    }
+}
+
+impl std::error::Error for WatchedKeyModified {}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use super::*;
+    use tokio_stream::StreamExt;
     use tokio_test::io::Builder;
 
     #[tokio::test]
@@ -90,9 +691,9 @@ mod tests {
         let expected_message = "Hello there!";
         let mut response: Vec<u8> = vec![];
         response.extend_from_slice(&2u32.to_le_bytes());
-        response.append(&mut (expected_message.as_bytes().len() as u32).to_le_bytes().to_vec());
+        response.append(&mut (expected_message.len() as u32).to_le_bytes().to_vec());
         response.append(&mut expected_message.as_bytes().to_vec());
-        handle.read(response.as_slice());
+        handle.read(&encode_frame(&response));
 
         let mut conn = RedisClientConnection::new(mock);
         let n = conn
@@ -103,7 +704,184 @@ mod tests {
         assert!(matches!(n, Response::String(..)));
         match n {
             Response::String(s) => assert_eq!(expected_message, s),
-            _ => assert!(false)
+            _ => panic!("Expected Response::String")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_large_response() {
+        let mut builder = Builder::new();
+        let (mock, mut handle) = builder.build_with_handle();
+
+        let expected = vec![b'z'; 16 * 1024];
+        let frame = encode_frame(&Response::Data(expected.clone()).serialize());
+        frame.chunks(333).for_each(|chunk| { handle.read(chunk); });
+
+        let mut conn = RedisClientConnection::new(mock);
+        let n = conn
+            .read_response()
+            .await
+            .expect("Failed to read mock buffer");
+
+        assert_eq!(Response::Data(expected), n);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        let commands = vec![
+            Command::SET("a".to_string(), b"1".to_vec(), SetOptions::new()),
+            Command::GET("a".to_string()),
+            Command::DELETE(vec!["a".to_string()]),
+        ];
+        let request = commands.iter().fold(Vec::new(), |mut acc, cmd| {
+            acc.extend_from_slice(&encode_frame(&cmd.encode()));
+            acc
+        });
+        let expected = vec![
+            Response::String("OK".to_string()),
+            Response::Data(b"1".to_vec()),
+            Response::String("OK".to_string()),
+        ];
+        let responses = expected.iter().fold(Vec::new(), |mut acc, r| {
+            acc.extend_from_slice(&encode_frame(&r.serialize()));
+            acc
+        });
+
+        let mock = Builder::new()
+            .write(&request)
+            .read(&responses)
+            .build();
+
+        let mut conn = RedisClientConnection::new(mock);
+        let mut pipeline = conn.pipeline();
+        for cmd in commands {
+            pipeline.add(cmd);
         }
+        assert_eq!(3, pipeline.len());
+        let actual = pipeline.execute().await.expect("Failed to execute pipeline");
+
+        assert_eq!(expected, actual);
+    }
+
+    #[tokio::test]
+    async fn test_hgetall() {
+        let expected = Response::Map(vec![(b"name".to_vec(), b"ann".to_vec()), (b"bin".to_vec(), vec![0, 255])]);
+        let mock = Builder::new()
+            .write(&encode_frame(&Command::HGETALL("user:1".to_string()).encode()))
+            .read(&encode_frame(&expected.serialize()))
+            .build();
+
+        let mut conn = RedisClientConnection::new(mock);
+        let actual = conn.hgetall("user:1".to_string()).await.expect("Failed to read hash");
+        assert_eq!(expected, actual);
+    }
+
+    #[tokio::test]
+    async fn test_keys() {
+        let keys = Response::Array(Arc::new(vec!["session:1".to_string(), "session:2".to_string()]));
+        let mock = Builder::new()
+            .write(&encode_frame(&Command::KEYS(Some("session:*".to_string())).encode()))
+            .read(&encode_frame(&keys.serialize()))
+            .write(&encode_frame(&Command::KEYS(None).encode()))
+            .read(&encode_frame(&Response::Array(Arc::new(vec![])).serialize()))
+            .build();
+        let mut conn = RedisClientConnection::new(mock);
+        assert_eq!(keys, conn.keys(Some("session:*")).await.unwrap());
+        assert_eq!(Response::Array(Arc::new(vec![])), conn.keys(None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let pages = [
+            (Command::SCAN(0, Some("k*".to_string()), 2, None), Response::Multi(vec![
+                Response::Data(b"6".to_vec()),
+                Response::Array(Arc::new(vec!["k1".to_string(), "k2".to_string()])),
+            ])),
+            (Command::SCAN(6, Some("k*".to_string()), 2, None), Response::Multi(vec![
+                Response::Data(b"0".to_vec()),
+                Response::Array(Arc::new(vec!["k3".to_string()])),
+            ])),
+        ];
+        let mut builder = Builder::new();
+        for (cmd, response) in &pages {
+            builder.write(&encode_frame(&cmd.encode()));
+            builder.read(&encode_frame(&response.serialize()));
+        }
+        let mut conn = RedisClientConnection::new(builder.build());
+
+        let keys: Vec<String> = conn.scan(Some("k*"), Some(2))
+            .collect::<Result<_, _>>()
+            .await
+            .expect("Failed to scan");
+        assert_eq!(vec!["k1", "k2", "k3"], keys);
+    }
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let mock = Builder::new()
+            .write(&encode_frame(&Command::PTTL("k".to_string()).encode()))
+            .read(&encode_frame(&Response::Int(1500).serialize()))
+            .write(&encode_frame(&Command::TTL("missing".to_string()).encode()))
+            .read(&encode_frame(&Response::Int(-2).serialize()))
+            .write(&encode_frame(&Command::TTL("forever".to_string()).encode()))
+            .read(&encode_frame(&Response::Int(-1).serialize()))
+            .build();
+        let mut conn = RedisClientConnection::new(mock);
+        assert_eq!(Some(Some(Duration::from_millis(1500))), conn.pttl("k".to_string()).await.unwrap());
+        assert_eq!(None, conn.ttl("missing".to_string()).await.unwrap());
+        assert_eq!(Some(None), conn.ttl("forever".to_string()).await.unwrap());
+        assert!(conn.expire("k".to_string(), Duration::from_millis(500)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mget() {
+        let keys = vec!["a".to_string(), "missing".to_string()];
+        let mock = Builder::new()
+            .write(&encode_frame(&Command::MGET(keys.clone()).encode()))
+            .read(&encode_frame(&Response::Multi(vec![Response::Data(b"1".to_vec()), Response::Empty]).serialize()))
+            .build();
+        let mut conn = RedisClientConnection::new(mock);
+        assert_eq!(vec![Some(b"1".to_vec()), None], conn.mget(keys).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_scripts() {
+        let script = "redis.call(\"INCR\", KEYS[0])";
+        let sha = "8fc8d6b9b0a4a2ba10cfdbc5a6e2fde3e2c0c1d5";
+        let mock = Builder::new()
+            .write(&encode_frame(&Command::SCRIPTLOAD(script.to_string()).encode()))
+            .read(&encode_frame(&Response::Data(sha.as_bytes().to_vec()).serialize()))
+            .write(&encode_frame(&Command::EVALSHA(sha.to_string(), vec!["n".to_string()], vec![]).encode()))
+            .read(&encode_frame(&Response::Int(1).serialize()))
+            .build();
+        let mut conn = RedisClientConnection::new(mock);
+        assert_eq!(sha, conn.script_load(script).await.unwrap());
+        assert_eq!(Response::Int(1), conn.evalsha(sha, vec!["n".to_string()], vec![]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let frames = |parts: Vec<Vec<u8>>| parts.iter().flat_map(|part| encode_frame(part)).collect::<Vec<u8>>();
+        let request = frames(vec![Command::MULTI.encode(), Command::INCR("n".to_string()).encode(), Command::EXEC.encode()]);
+        let queued = |exec: Response| frames(vec![
+            Response::String("OK".to_string()).serialize(),
+            Response::String("QUEUED".to_string()).serialize(),
+            exec.serialize(),
+        ]);
+        let mock = Builder::new()
+            .write(&request)
+            .read(&queued(Response::Multi(vec![Response::Int(1)])))
+            .write(&request)
+            .read(&queued(Response::Empty))
+            .build();
+        let mut conn = RedisClientConnection::new(mock);
+
+        let mut transaction = conn.transaction();
+        transaction.add(Command::INCR("n".to_string()));
+        assert_eq!(vec![Response::Int(1)], transaction.execute().await.unwrap());
+
+        let mut transaction = conn.transaction();
+        transaction.add(Command::INCR("n".to_string()));
+        assert!(transaction.execute().await.unwrap_err().is::<WatchedKeyModified>());
     }
 }
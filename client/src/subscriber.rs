@@ -0,0 +1,162 @@
+use std::collections::{HashSet, VecDeque};
+
+use anyhow::anyhow;
+use async_stream::try_stream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_stream::Stream;
+
+use rusty_redis_core::command::Command;
+use rusty_redis_core::pubsub::{Message, Push};
+
+use super::RedisClientConnection;
+
+/// A connection given over to receiving published messages.
+///
+/// Created with `RedisClientConnection::into_subscriber`, messages to the
+/// channels and patterns subscribed to are read with `messages`.
+pub struct Subscriber<T>
+where
+    T: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    conn: RedisClientConnection<T>,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    /// Messages that arrived while waiting for a subscription to be confirmed.
+    pending: VecDeque<Message>,
+}
+
+impl<T> RedisClientConnection<T>
+where
+    T: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    /// Use this connection to subscribe to channels, it can't send other commands while subscribed.
+    pub fn into_subscriber(self) -> Subscriber<T> {
+        Subscriber { conn: self, channels: HashSet::new(), patterns: HashSet::new(), pending: VecDeque::new() }
+    }
+}
+
+impl<T> Subscriber<T>
+where
+    T: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    /// Subscribe to `channels`, returning once the server has confirmed every one.
+    pub async fn subscribe(&mut self, channels: Vec<String>) -> Result<(), anyhow::Error> {
+        let expected = channels.len();
+        self.conn.write_command(Command::SUBSCRIBE(channels)).await?;
+        self.confirm(expected).await
+    }
+
+    /// Subscribe to every channel matching the glob `patterns`.
+    pub async fn psubscribe(&mut self, patterns: Vec<String>) -> Result<(), anyhow::Error> {
+        let expected = patterns.len();
+        self.conn.write_command(Command::PSUBSCRIBE(patterns)).await?;
+        self.confirm(expected).await
+    }
+
+    /// Unsubscribe from `channels`, or from every channel when empty.
+    pub async fn unsubscribe(&mut self, channels: Vec<String>) -> Result<(), anyhow::Error> {
+        let expected = if channels.is_empty() { self.channels.len().max(1) } else { channels.len() };
+        self.conn.write_command(Command::UNSUBSCRIBE(channels)).await?;
+        self.confirm(expected).await
+    }
+
+    /// Unsubscribe from `patterns`, or from every pattern when empty.
+    pub async fn punsubscribe(&mut self, patterns: Vec<String>) -> Result<(), anyhow::Error> {
+        let expected = if patterns.is_empty() { self.patterns.len().max(1) } else { patterns.len() };
+        self.conn.write_command(Command::PUNSUBSCRIBE(patterns)).await?;
+        self.confirm(expected).await
+    }
+
+    /// Channels currently subscribed to, in no particular order.
+    pub fn channels(&self) -> impl Iterator<Item = &str> {
+        self.channels.iter().map(String::as_str)
+    }
+
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.patterns.iter().map(String::as_str)
+    }
+
+    /// Yield each message published to the subscribed channels and patterns as it arrives.
+    pub fn messages(&mut self) -> impl Stream<Item = Result<Message, anyhow::Error>> + '_ {
+        try_stream! {
+            loop {
+                if let Some(message) = self.pending.pop_front() {
+                    yield message;
+                    continue;
+                }
+                match self.read_push().await? {
+                    Push::Message(message) => yield message,
+                    // Confirmations are only expected here if the subscription changed mid stream.
+                    Push::Subscription { .. } => {},
+                }
+            }
+        }
+    }
+
+    /// Hand back the connection once every subscription has been dropped.
+    pub fn into_connection(self) -> Result<RedisClientConnection<T>, anyhow::Error> {
+        if !self.channels.is_empty() || !self.patterns.is_empty() {
+            return Err(anyhow!("Still subscribed to {} channels and {} patterns", self.channels.len(), self.patterns.len()));
+        }
+        Ok(self.conn)
+    }
+
+    /// Wait for `expected` subscription changes to be confirmed, holding on to any messages in between.
+    async fn confirm(&mut self, mut expected: usize) -> Result<(), anyhow::Error> {
+        while expected > 0 {
+            match self.read_push().await? {
+                Push::Message(message) => self.pending.push_back(message),
+                Push::Subscription { .. } => expected -= 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the next push, keeping track of which channels and patterns are subscribed to.
+    async fn read_push(&mut self) -> Result<Push, anyhow::Error> {
+        let push = Push::from_response(self.conn.read_response().await?)?;
+        if let Push::Subscription { kind, name: Some(name), .. } = &push {
+            match kind.as_str() {
+                "subscribe" => { self.channels.insert(name.clone()); },
+                "unsubscribe" => { self.channels.remove(name); },
+                "psubscribe" => { self.patterns.insert(name.clone()); },
+                "punsubscribe" => { self.patterns.remove(name); },
+                _ => {},
+            }
+        }
+        Ok(push)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_redis_core::frame::encode_frame;
+    use tokio_stream::StreamExt;
+    use tokio_test::io::Builder;
+
+    use super::*;
+
+    fn push(push: Push) -> Vec<u8> {
+        encode_frame(&push.into_response().serialize())
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let message = |payload: &[u8]| Message { channel: "news".to_string(), pattern: None, payload: payload.to_vec() };
+        let mut confirmed = push(Push::Message(message(b"early")));
+        confirmed.extend_from_slice(&push(Push::Subscription { kind: "subscribe".to_string(), name: Some("news".to_string()), count: 1 }));
+        let mock = Builder::new()
+            .write(&encode_frame(&Command::SUBSCRIBE(vec!["news".to_string()]).encode()))
+            .read(&confirmed)
+            .read(&push(Push::Message(message(b"later"))))
+            .build();
+
+        let mut subscriber = RedisClientConnection::new(mock).into_subscriber();
+        subscriber.subscribe(vec!["news".to_string()]).await.unwrap();
+        assert_eq!(vec!["news"], subscriber.channels().collect::<Vec<_>>());
+
+        let messages: Vec<_> = subscriber.messages().take(2).collect().await;
+        let payloads: Vec<_> = messages.into_iter().map(|message| message.unwrap().payload).collect();
+        assert_eq!(vec![b"early".to_vec(), b"later".to_vec()], payloads);
+    }
+}
@@ -4,20 +4,297 @@ use std::io::{ErrorKind, Error};
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Command {
     GET(String),
-    KEYS,
-    SET(String, Vec<u8>, u64),
-    DELETE(String)
+    /// An optional glob pattern, all keys are returned without one.
+    KEYS(Option<String>),
+    SET(String, Vec<u8>, SetOptions),
+    DELETE(Vec<String>),
+    HELLO(Option<u32>),
+    PING(Option<String>),
+    SAVE,
+    BGSAVE,
+    BGREWRITEAOF,
+    CONFIGGET(String),
+    CONFIGSET(String, String),
+    /// Cursor, MATCH pattern, COUNT and TYPE.
+    SCAN(u64, Option<String>, u64, Option<String>),
+    LPUSH(String, Vec<Vec<u8>>),
+    RPUSH(String, Vec<Vec<u8>>),
+    /// Pops a single value, or up to the given count.
+    LPOP(String, Option<u64>),
+    RPOP(String, Option<u64>),
+    LRANGE(String, i64, i64),
+    LLEN(String),
+    LINDEX(String, i64),
+    LTRIM(String, i64, i64),
+    /// Field and value pairs to set.
+    HSET(String, Vec<FieldValue>),
+    HGET(String, Vec<u8>),
+    HDEL(String, Vec<Vec<u8>>),
+    HGETALL(String),
+    HINCRBY(String, Vec<u8>, i64),
+    HKEYS(String),
+    HLEN(String),
+    SADD(String, Vec<Vec<u8>>),
+    SREM(String, Vec<Vec<u8>>),
+    SISMEMBER(String, Vec<u8>),
+    SMEMBERS(String),
+    SCARD(String),
+    SINTER(Vec<String>),
+    SUNION(Vec<String>),
+    SDIFF(Vec<String>),
+    /// Destination followed by the keys to combine.
+    SINTERSTORE(String, Vec<String>),
+    SUNIONSTORE(String, Vec<String>),
+    SDIFFSTORE(String, Vec<String>),
+    /// Score and member pairs to add.
+    ZADD(String, Vec<(f64, Vec<u8>)>),
+    ZREM(String, Vec<Vec<u8>>),
+    ZSCORE(String, Vec<u8>),
+    ZRANK(String, Vec<u8>),
+    /// Start, stop and whether to include scores.
+    ZRANGE(String, i64, i64, bool),
+    /// Min, max and whether to include scores.
+    ZRANGEBYSCORE(String, ScoreBound, ScoreBound, bool),
+    /// Increment and member.
+    ZINCRBY(String, f64, Vec<u8>),
+    ZCARD(String),
+    INCR(String),
+    DECR(String),
+    INCRBY(String, i64),
+    DECRBY(String, i64),
+    INCRBYFLOAT(String, f64),
+    TTL(String),
+    PTTL(String),
+    /// Seconds from now, zero or negative deletes the key.
+    EXPIRE(String, i64),
+    /// Milliseconds from now.
+    PEXPIRE(String, i64),
+    /// Seconds since the unix epoch.
+    EXPIREAT(String, i64),
+    /// Milliseconds since the unix epoch.
+    PEXPIREAT(String, i64),
+    PERSIST(String),
+    MGET(Vec<String>),
+    MSET(Vec<KeyValue>),
+    MSETNX(Vec<KeyValue>),
+    EXISTS(Vec<String>),
+    UNLINK(Vec<String>),
+    SUBSCRIBE(Vec<String>),
+    /// Every subscribed channel when empty.
+    UNSUBSCRIBE(Vec<String>),
+    /// Glob patterns matched against the channel a message is published to.
+    PSUBSCRIBE(Vec<String>),
+    /// Every subscribed pattern when empty.
+    PUNSUBSCRIBE(Vec<String>),
+    /// Channel and message.
+    PUBLISH(String, Vec<u8>),
+    MULTI,
+    EXEC,
+    DISCARD,
+    WATCH(Vec<String>),
+    UNWATCH,
+    /// Script source, KEYS and ARGV.
+    EVAL(String, Vec<String>, Vec<Vec<u8>>),
+    /// SHA1 of a script loaded earlier, KEYS and ARGV.
+    EVALSHA(String, Vec<String>, Vec<Vec<u8>>),
+    SCRIPTLOAD(String),
+    SCRIPTEXISTS(Vec<String>),
+    SCRIPTFLUSH,
+    SCRIPTKILL,
+    /// Seconds since the key was last read or written.
+    OBJECTIDLETIME(String),
+    /// Logarithmic access counter used by the LFU eviction policies.
+    OBJECTFREQ(String),
+}
+
+/// One end of a ZRANGEBYSCORE range, written as `1.5`, `(1.5` when exclusive, `-inf` or `+inf`.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ScoreBound {
+    pub fn parse(arg: &[u8]) -> Result<Self, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "min or max is not a float");
+        match arg.strip_prefix(b"(") {
+            Some(score) => Ok(ScoreBound::Exclusive(parse_score(score).map_err(|_| invalid())?)),
+            None => Ok(ScoreBound::Inclusive(parse_score(arg).map_err(|_| invalid())?)),
+        }
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            ScoreBound::Inclusive(score) => format_score(*score),
+            ScoreBound::Exclusive(score) => format!("({}", format_score(*score)),
+        }
+    }
+}
+
+/// The conditions and expiry a SET is written with, e.g. `SetOptions::new().nx().px(5000)`.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct SetOptions {
+    pub condition: Option<SetCondition>,
+    pub expiry: Option<Expiry>,
+    /// Reply with the value the key held before the SET.
+    pub get: bool,
+}
+
+/// NX and XX, only write when the key is missing or when it already exists.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum SetCondition {
+    NotExists,
+    Exists,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Expiry {
+    /// Milliseconds from now, set by EX and PX.
+    In(u64),
+    /// Milliseconds since the unix epoch, set by EXAT and PXAT.
+    At(u64),
+    /// KEEPTTL, leave whatever TTL the key already has.
+    Keep,
+}
+
+impl SetOptions {
+    pub fn new() -> Self {
+        SetOptions::default()
+    }
+
+    /// Expire `ttl` milliseconds from now, 0 meaning never as SET has always taken it.
+    pub fn ttl(ttl: u64) -> Self {
+        let expiry = (ttl != 0).then_some(Expiry::In(ttl));
+        SetOptions { expiry, ..SetOptions::default() }
+    }
+
+    pub fn nx(mut self) -> Self {
+        self.condition = Some(SetCondition::NotExists);
+        self
+    }
+
+    pub fn xx(mut self) -> Self {
+        self.condition = Some(SetCondition::Exists);
+        self
+    }
+
+    pub fn get(mut self) -> Self {
+        self.get = true;
+        self
+    }
+
+    pub fn keep_ttl(mut self) -> Self {
+        self.expiry = Some(Expiry::Keep);
+        self
+    }
+
+    pub fn ex(mut self, seconds: u64) -> Self {
+        self.expiry = Some(Expiry::In(seconds.saturating_mul(1000)));
+        self
+    }
+
+    pub fn px(mut self, millis: u64) -> Self {
+        self.expiry = Some(Expiry::In(millis));
+        self
+    }
+
+    pub fn exat(mut self, unix_seconds: u64) -> Self {
+        self.expiry = Some(Expiry::At(unix_seconds.saturating_mul(1000)));
+        self
+    }
+
+    pub fn pxat(mut self, unix_millis: u64) -> Self {
+        self.expiry = Some(Expiry::At(unix_millis));
+        self
+    }
+
+    /// Every option after the relative TTL as ([LNV][V])*, the TTL is sent on its own
+    /// so a SET written before options existed still parses.
+    fn encode(&self) -> Vec<Vec<u8>> {
+        let mut options = vec![];
+        match self.condition {
+            Some(SetCondition::NotExists) => options.push(b"nx".to_vec()),
+            Some(SetCondition::Exists) => options.push(b"xx".to_vec()),
+            None => {},
+        }
+        if self.get {
+            options.push(b"get".to_vec());
+        }
+        match self.expiry {
+            Some(Expiry::At(at)) => {
+                options.push(b"pxat".to_vec());
+                options.push(at.to_le_bytes().to_vec());
+            },
+            Some(Expiry::Keep) => options.push(b"keepttl".to_vec()),
+            Some(Expiry::In(_)) | None => {},
+        }
+        options
+    }
+
+    fn parse(ttl: u64, args: &mut VecDeque<Vec<u8>>) -> Result<Self, Error> {
+        let mut options = SetOptions::ttl(ttl);
+        while let Some(option) = args.pop_front() {
+            match option.as_slice() {
+                b"nx" => options.condition = Some(SetCondition::NotExists),
+                b"xx" => options.condition = Some(SetCondition::Exists),
+                b"get" => options.get = true,
+                b"keepttl" => options.expiry = Some(Expiry::Keep),
+                b"pxat" => options.expiry = Some(Expiry::At(next_u64(args, "Expected expiry after pxat")?)),
+                _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid set option encountered")),
+            }
+        }
+        Ok(options)
+    }
+
+    /// `NX|XX`, `GET` and one of `EX|PX|EXAT|PXAT amount` or `KEEPTTL`, in any order.
+    fn parse_resp(args: &mut VecDeque<Vec<u8>>) -> Result<Self, Error> {
+        let syntax_error = || Error::new(ErrorKind::InvalidData, "syntax error");
+        let mut options = SetOptions::new();
+        while let Some(option) = args.pop_front() {
+            let option = option.to_ascii_lowercase();
+            match option.as_slice() {
+                b"nx" if options.condition != Some(SetCondition::Exists) => options.condition = Some(SetCondition::NotExists),
+                b"xx" if options.condition != Some(SetCondition::NotExists) => options.condition = Some(SetCondition::Exists),
+                b"nx" | b"xx" => return Err(syntax_error()),
+                b"get" => options.get = true,
+                _ if options.expiry.is_some() => return Err(syntax_error()),
+                b"keepttl" => options.expiry = Some(Expiry::Keep),
+                b"ex" | b"px" | b"exat" | b"pxat" => {
+                    let amount = parse_int::<u64>(&args.pop_front().ok_or_else(syntax_error)?)?;
+                    let invalid = || Error::new(ErrorKind::InvalidData, "invalid expire time in 'set' command");
+                    if amount == 0 {
+                        return Err(invalid());
+                    }
+                    let millis = match option.as_slice() {
+                        b"ex" | b"exat" => amount.checked_mul(1000).ok_or_else(invalid)?,
+                        _ => amount,
+                    };
+                    options.expiry = Some(match option.as_slice() {
+                        b"ex" | b"px" => Expiry::In(millis),
+                        _ => Expiry::At(millis),
+                    });
+                },
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(options)
+    }
 }
 
 impl Command {
     pub fn encode(&self) -> Vec<u8> {
         let mut command: Vec<u8> = vec![];
         match self {
-            Command::KEYS => {
-                // [NumV][LNV][V]
-                command.extend_from_slice(&1u32.to_le_bytes());
+            Command::KEYS(pattern) => {
+                // [NumV][LNV][V]([LNV][V])
+                let num_v = if pattern.is_some() { 2u32 } else { 1u32 };
+                command.extend_from_slice(&num_v.to_le_bytes());
                 command.extend_from_slice(&4u32.to_le_bytes());
                 command.extend_from_slice(b"keys");
+                if let Some(pattern) = pattern {
+                    command.extend_from_slice(&(pattern.len() as u32).to_le_bytes());
+                    command.extend_from_slice(pattern.as_bytes());
+                }
             },
             Command::GET(key) => {
                 // [NumV][LNV][V][LNV][V]
@@ -27,42 +304,365 @@ impl Command {
                 command.extend_from_slice(&(key.len() as u32).to_le_bytes());
                 command.extend_from_slice(key.as_bytes());
             },
-            Command::SET(key, value, ttl) => {
-                // [NumV][LNV][V][LNV][V][LNV][V]
-                command.extend_from_slice(&4u32.to_le_bytes());
+            Command::SET(key, value, options) => {
+                // [NumV][LNV][V][LNV][V][LNV][V][LNV][V]([LNV][V])*
+                let ttl = match options.expiry {
+                    Some(Expiry::In(ttl)) => ttl,
+                    _ => 0,
+                };
+                let options = options.encode();
+                command.extend_from_slice(&(4 + options.len() as u32).to_le_bytes());
                 command.extend_from_slice(&3u32.to_le_bytes());
                 command.extend_from_slice(b"set");
                 command.extend_from_slice(&(key.len() as u32).to_le_bytes());
                 command.extend_from_slice(key.as_bytes());
                 command.extend_from_slice(&(value.len() as u32).to_le_bytes());
-                command.extend_from_slice(&value);
+                command.extend_from_slice(value);
                 command.extend_from_slice(&8u32.to_le_bytes());
                 command.extend_from_slice(&ttl.to_le_bytes());
+                for option in options {
+                    command.extend_from_slice(&(option.len() as u32).to_le_bytes());
+                    command.extend_from_slice(&option);
+                }
             },
-            Command::DELETE(key) => {
-                // [NumV][LNV][V][LNV][V]
-                command.extend_from_slice(&2u32.to_le_bytes());
+            Command::DELETE(keys) | Command::MGET(keys) | Command::EXISTS(keys) | Command::UNLINK(keys) | Command::WATCH(keys)
+                | Command::SUBSCRIBE(keys) | Command::UNSUBSCRIBE(keys) | Command::PSUBSCRIBE(keys) | Command::PUNSUBSCRIBE(keys) => {
+                // [NumV][LNV][V]([LNV][V])*
+                let name: &[u8] = match self {
+                    Command::DELETE(_) => b"del",
+                    Command::MGET(_) => b"mget",
+                    Command::EXISTS(_) => b"exists",
+                    Command::UNLINK(_) => b"unlink",
+                    Command::WATCH(_) => b"watch",
+                    Command::SUBSCRIBE(_) => b"subscribe",
+                    Command::UNSUBSCRIBE(_) => b"unsubscribe",
+                    Command::PSUBSCRIBE(_) => b"psubscribe",
+                    _ => b"punsubscribe",
+                };
+                let mut args = vec![name];
+                args.extend(keys.iter().map(String::as_bytes));
+                encode_args(&mut command, &args);
+            },
+            Command::HELLO(version) => {
+                // [NumV][LNV][V]([LNV][V])
+                let num_v = if version.is_some() { 2u32 } else { 1u32 };
+                command.extend_from_slice(&num_v.to_le_bytes());
+                command.extend_from_slice(&5u32.to_le_bytes());
+                command.extend_from_slice(b"hello");
+                if let Some(version) = version {
+                    command.extend_from_slice(&4u32.to_le_bytes());
+                    command.extend_from_slice(&version.to_le_bytes());
+                }
+            },
+            Command::PING(msg) => {
+                // [NumV][LNV][V]([LNV][V])
+                let num_v = if msg.is_some() { 2u32 } else { 1u32 };
+                command.extend_from_slice(&num_v.to_le_bytes());
+                command.extend_from_slice(&4u32.to_le_bytes());
+                command.extend_from_slice(b"ping");
+                if let Some(msg) = msg {
+                    command.extend_from_slice(&(msg.len() as u32).to_le_bytes());
+                    command.extend_from_slice(msg.as_bytes());
+                }
+            },
+            Command::SAVE => {
+                // [NumV][LNV][V]
+                command.extend_from_slice(&1u32.to_le_bytes());
+                command.extend_from_slice(&4u32.to_le_bytes());
+                command.extend_from_slice(b"save");
+            },
+            Command::BGSAVE => {
+                // [NumV][LNV][V]
+                command.extend_from_slice(&1u32.to_le_bytes());
+                command.extend_from_slice(&6u32.to_le_bytes());
+                command.extend_from_slice(b"bgsave");
+            },
+            Command::BGREWRITEAOF => {
+                // [NumV][LNV][V]
+                command.extend_from_slice(&1u32.to_le_bytes());
+                command.extend_from_slice(&12u32.to_le_bytes());
+                command.extend_from_slice(b"bgrewriteaof");
+            },
+            Command::CONFIGGET(pattern) => {
+                // [NumV][LNV][V][LNV][V][LNV][V]
                 command.extend_from_slice(&3u32.to_le_bytes());
-                command.extend_from_slice(b"del");
-                command.extend_from_slice(&(key.len() as u32).to_le_bytes());
-                command.extend_from_slice(key.as_bytes());
+                command.extend_from_slice(&6u32.to_le_bytes());
+                command.extend_from_slice(b"config");
+                command.extend_from_slice(&3u32.to_le_bytes());
+                command.extend_from_slice(b"get");
+                command.extend_from_slice(&(pattern.len() as u32).to_le_bytes());
+                command.extend_from_slice(pattern.as_bytes());
+            },
+            Command::CONFIGSET(name, value) => {
+                // [NumV][LNV][V][LNV][V][LNV][V][LNV][V]
+                command.extend_from_slice(&4u32.to_le_bytes());
+                command.extend_from_slice(&6u32.to_le_bytes());
+                command.extend_from_slice(b"config");
+                command.extend_from_slice(&3u32.to_le_bytes());
+                command.extend_from_slice(b"set");
+                command.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                command.extend_from_slice(name.as_bytes());
+                command.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                command.extend_from_slice(value.as_bytes());
+            },
+            Command::SCAN(cursor, pattern, count, kind) => {
+                // [NumV][LNV][V][LNV][V][LNV][V]([LNV][V][LNV][V])([LNV][V][LNV][V])
+                let num_v = 3 + 2 * (pattern.is_some() as u32 + kind.is_some() as u32);
+                command.extend_from_slice(&num_v.to_le_bytes());
+                command.extend_from_slice(&4u32.to_le_bytes());
+                command.extend_from_slice(b"scan");
+                command.extend_from_slice(&8u32.to_le_bytes());
+                command.extend_from_slice(&cursor.to_le_bytes());
+                command.extend_from_slice(&8u32.to_le_bytes());
+                command.extend_from_slice(&count.to_le_bytes());
+                if let Some(pattern) = pattern {
+                    command.extend_from_slice(&5u32.to_le_bytes());
+                    command.extend_from_slice(b"match");
+                    command.extend_from_slice(&(pattern.len() as u32).to_le_bytes());
+                    command.extend_from_slice(pattern.as_bytes());
+                }
+                if let Some(kind) = kind {
+                    command.extend_from_slice(&4u32.to_le_bytes());
+                    command.extend_from_slice(b"type");
+                    command.extend_from_slice(&(kind.len() as u32).to_le_bytes());
+                    command.extend_from_slice(kind.as_bytes());
+                }
+            },
+            Command::LPUSH(key, values) | Command::RPUSH(key, values) => {
+                let name: &[u8] = if matches!(self, Command::LPUSH(..)) { b"lpush" } else { b"rpush" };
+                let mut args = vec![name, key.as_bytes()];
+                args.extend(values.iter().map(Vec::as_slice));
+                encode_args(&mut command, &args);
+            },
+            Command::LPOP(key, count) | Command::RPOP(key, count) => {
+                let name: &[u8] = if matches!(self, Command::LPOP(..)) { b"lpop" } else { b"rpop" };
+                let count = count.map(|c| c.to_string());
+                let mut args = vec![name, key.as_bytes()];
+                args.extend(count.as_ref().map(String::as_bytes));
+                encode_args(&mut command, &args);
+            },
+            Command::LRANGE(key, start, stop) => {
+                encode_args(&mut command, &[b"lrange", key.as_bytes(), start.to_string().as_bytes(), stop.to_string().as_bytes()]);
+            },
+            Command::LLEN(key) => encode_args(&mut command, &[b"llen", key.as_bytes()]),
+            Command::LINDEX(key, index) => {
+                encode_args(&mut command, &[b"lindex", key.as_bytes(), index.to_string().as_bytes()]);
+            },
+            Command::LTRIM(key, start, stop) => {
+                encode_args(&mut command, &[b"ltrim", key.as_bytes(), start.to_string().as_bytes(), stop.to_string().as_bytes()]);
+            },
+            Command::HSET(key, pairs) => {
+                let mut args = vec![b"hset".as_slice(), key.as_bytes()];
+                args.extend(pairs.iter().flat_map(|(field, value)| [field.as_slice(), value.as_slice()]));
+                encode_args(&mut command, &args);
+            },
+            Command::HGET(key, field) => encode_args(&mut command, &[b"hget", key.as_bytes(), field]),
+            Command::HDEL(key, fields) => {
+                let mut args = vec![b"hdel".as_slice(), key.as_bytes()];
+                args.extend(fields.iter().map(Vec::as_slice));
+                encode_args(&mut command, &args);
+            },
+            Command::HGETALL(key) => encode_args(&mut command, &[b"hgetall", key.as_bytes()]),
+            Command::HINCRBY(key, field, increment) => {
+                encode_args(&mut command, &[b"hincrby", key.as_bytes(), field, increment.to_string().as_bytes()]);
+            },
+            Command::HKEYS(key) => encode_args(&mut command, &[b"hkeys", key.as_bytes()]),
+            Command::HLEN(key) => encode_args(&mut command, &[b"hlen", key.as_bytes()]),
+            Command::SADD(key, members) | Command::SREM(key, members) => {
+                let name: &[u8] = if matches!(self, Command::SADD(..)) { b"sadd" } else { b"srem" };
+                let mut args = vec![name, key.as_bytes()];
+                args.extend(members.iter().map(Vec::as_slice));
+                encode_args(&mut command, &args);
+            },
+            Command::SISMEMBER(key, member) => encode_args(&mut command, &[b"sismember", key.as_bytes(), member]),
+            Command::SMEMBERS(key) => encode_args(&mut command, &[b"smembers", key.as_bytes()]),
+            Command::SCARD(key) => encode_args(&mut command, &[b"scard", key.as_bytes()]),
+            Command::SINTER(keys) | Command::SUNION(keys) | Command::SDIFF(keys) => {
+                let name: &[u8] = match self {
+                    Command::SINTER(_) => b"sinter",
+                    Command::SUNION(_) => b"sunion",
+                    _ => b"sdiff",
+                };
+                let mut args = vec![name];
+                args.extend(keys.iter().map(String::as_bytes));
+                encode_args(&mut command, &args);
+            },
+            Command::SINTERSTORE(destination, keys) | Command::SUNIONSTORE(destination, keys) | Command::SDIFFSTORE(destination, keys) => {
+                let name: &[u8] = match self {
+                    Command::SINTERSTORE(..) => b"sinterstore",
+                    Command::SUNIONSTORE(..) => b"sunionstore",
+                    _ => b"sdiffstore",
+                };
+                let mut args = vec![name, destination.as_bytes()];
+                args.extend(keys.iter().map(String::as_bytes));
+                encode_args(&mut command, &args);
+            },
+            Command::ZADD(key, entries) => {
+                let scores: Vec<String> = entries.iter().map(|(score, _)| format_score(*score)).collect();
+                let mut args = vec![b"zadd".as_slice(), key.as_bytes()];
+                for (score, (_, member)) in scores.iter().zip(entries) {
+                    args.extend([score.as_bytes(), member.as_slice()]);
+                }
+                encode_args(&mut command, &args);
+            },
+            Command::ZREM(key, members) => {
+                let mut args = vec![b"zrem".as_slice(), key.as_bytes()];
+                args.extend(members.iter().map(Vec::as_slice));
+                encode_args(&mut command, &args);
+            },
+            Command::ZSCORE(key, member) => encode_args(&mut command, &[b"zscore", key.as_bytes(), member]),
+            Command::ZRANK(key, member) => encode_args(&mut command, &[b"zrank", key.as_bytes(), member]),
+            Command::ZRANGE(key, start, stop, with_scores) => {
+                let (start, stop) = (start.to_string(), stop.to_string());
+                let mut args = vec![b"zrange".as_slice(), key.as_bytes(), start.as_bytes(), stop.as_bytes()];
+                if *with_scores {
+                    args.push(b"withscores");
+                }
+                encode_args(&mut command, &args);
+            },
+            Command::ZRANGEBYSCORE(key, min, max, with_scores) => {
+                let (min, max) = (min.encode(), max.encode());
+                let mut args = vec![b"zrangebyscore".as_slice(), key.as_bytes(), min.as_bytes(), max.as_bytes()];
+                if *with_scores {
+                    args.push(b"withscores");
+                }
+                encode_args(&mut command, &args);
+            },
+            Command::ZINCRBY(key, increment, member) => {
+                encode_args(&mut command, &[b"zincrby", key.as_bytes(), format_score(*increment).as_bytes(), member]);
+            },
+            Command::ZCARD(key) => encode_args(&mut command, &[b"zcard", key.as_bytes()]),
+            Command::INCR(key) => encode_args(&mut command, &[b"incr", key.as_bytes()]),
+            Command::DECR(key) => encode_args(&mut command, &[b"decr", key.as_bytes()]),
+            Command::INCRBY(key, increment) => encode_args(&mut command, &[b"incrby", key.as_bytes(), increment.to_string().as_bytes()]),
+            Command::DECRBY(key, decrement) => encode_args(&mut command, &[b"decrby", key.as_bytes(), decrement.to_string().as_bytes()]),
+            Command::INCRBYFLOAT(key, increment) => {
+                encode_args(&mut command, &[b"incrbyfloat", key.as_bytes(), format_score(*increment).as_bytes()]);
+            },
+            Command::TTL(key) => encode_args(&mut command, &[b"ttl", key.as_bytes()]),
+            Command::PTTL(key) => encode_args(&mut command, &[b"pttl", key.as_bytes()]),
+            Command::EXPIRE(key, seconds) => encode_args(&mut command, &[b"expire", key.as_bytes(), seconds.to_string().as_bytes()]),
+            Command::PEXPIRE(key, millis) => encode_args(&mut command, &[b"pexpire", key.as_bytes(), millis.to_string().as_bytes()]),
+            Command::EXPIREAT(key, seconds) => encode_args(&mut command, &[b"expireat", key.as_bytes(), seconds.to_string().as_bytes()]),
+            Command::PEXPIREAT(key, millis) => encode_args(&mut command, &[b"pexpireat", key.as_bytes(), millis.to_string().as_bytes()]),
+            Command::PERSIST(key) => encode_args(&mut command, &[b"persist", key.as_bytes()]),
+            Command::PUBLISH(channel, message) => encode_args(&mut command, &[b"publish", channel.as_bytes(), message]),
+            Command::MULTI => encode_args(&mut command, &[b"multi"]),
+            Command::EXEC => encode_args(&mut command, &[b"exec"]),
+            Command::DISCARD => encode_args(&mut command, &[b"discard"]),
+            Command::UNWATCH => encode_args(&mut command, &[b"unwatch"]),
+            Command::EVAL(script, keys, args) | Command::EVALSHA(script, keys, args) => {
+                let name: &[u8] = if matches!(self, Command::EVAL(..)) { b"eval" } else { b"evalsha" };
+                let num_keys = keys.len().to_string();
+                let mut values = vec![name, script.as_bytes(), num_keys.as_bytes()];
+                values.extend(keys.iter().map(String::as_bytes));
+                values.extend(args.iter().map(Vec::as_slice));
+                encode_args(&mut command, &values);
+            },
+            Command::SCRIPTLOAD(script) => encode_args(&mut command, &[b"script", b"load", script.as_bytes()]),
+            Command::SCRIPTEXISTS(shas) => {
+                let mut values: Vec<&[u8]> = vec![b"script", b"exists"];
+                values.extend(shas.iter().map(String::as_bytes));
+                encode_args(&mut command, &values);
+            },
+            Command::SCRIPTFLUSH => encode_args(&mut command, &[b"script", b"flush"]),
+            Command::SCRIPTKILL => encode_args(&mut command, &[b"script", b"kill"]),
+            Command::OBJECTIDLETIME(key) => encode_args(&mut command, &[b"object", b"idletime", key.as_bytes()]),
+            Command::OBJECTFREQ(key) => encode_args(&mut command, &[b"object", b"freq", key.as_bytes()]),
+            Command::MSET(pairs) | Command::MSETNX(pairs) => {
+                let name: &[u8] = if matches!(self, Command::MSET(_)) { b"mset" } else { b"msetnx" };
+                let mut args = vec![name];
+                args.extend(pairs.iter().flat_map(|(key, value)| [key.as_bytes(), value.as_slice()]));
+                encode_args(&mut command, &args);
             },
         }
     command
     }
 
+    /// The keys a command reads or writes, in the order given. Commands that don't act on keys,
+    /// or only on ones matched by a pattern as KEYS and SCAN do, have none.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::GET(key) | Command::SET(key, ..)
+            | Command::LPUSH(key, _) | Command::RPUSH(key, _) | Command::LPOP(key, _) | Command::RPOP(key, _)
+            | Command::LRANGE(key, ..) | Command::LLEN(key) | Command::LINDEX(key, _) | Command::LTRIM(key, ..)
+            | Command::HSET(key, _) | Command::HGET(key, _) | Command::HDEL(key, _) | Command::HGETALL(key)
+            | Command::HINCRBY(key, ..) | Command::HKEYS(key) | Command::HLEN(key)
+            | Command::SADD(key, _) | Command::SREM(key, _) | Command::SISMEMBER(key, _) | Command::SMEMBERS(key)
+            | Command::SCARD(key)
+            | Command::ZADD(key, _) | Command::ZREM(key, _) | Command::ZSCORE(key, _) | Command::ZRANK(key, _)
+            | Command::ZRANGE(key, ..) | Command::ZRANGEBYSCORE(key, ..) | Command::ZINCRBY(key, ..) | Command::ZCARD(key)
+            | Command::INCR(key) | Command::DECR(key) | Command::INCRBY(key, _) | Command::DECRBY(key, _)
+            | Command::INCRBYFLOAT(key, _)
+            | Command::TTL(key) | Command::PTTL(key) | Command::EXPIRE(key, _) | Command::PEXPIRE(key, _)
+            | Command::EXPIREAT(key, _) | Command::PEXPIREAT(key, _) | Command::PERSIST(key)
+            | Command::OBJECTIDLETIME(key) | Command::OBJECTFREQ(key) => vec![key.as_str()],
+            Command::DELETE(keys) | Command::UNLINK(keys) | Command::EXISTS(keys) | Command::MGET(keys)
+            | Command::SINTER(keys) | Command::SUNION(keys) | Command::SDIFF(keys)
+            | Command::WATCH(keys) | Command::EVAL(_, keys, _) | Command::EVALSHA(_, keys, _) => {
+                keys.iter().map(String::as_str).collect()
+            },
+            Command::SINTERSTORE(destination, keys) | Command::SUNIONSTORE(destination, keys)
+            | Command::SDIFFSTORE(destination, keys) => {
+                std::iter::once(destination.as_str()).chain(keys.iter().map(String::as_str)).collect()
+            },
+            Command::MSET(pairs) | Command::MSETNX(pairs) => pairs.iter().map(|(key, _)| key.as_str()).collect(),
+            Command::KEYS(_) | Command::SCAN(..) | Command::HELLO(_) | Command::PING(_)
+            | Command::SAVE | Command::BGSAVE | Command::BGREWRITEAOF | Command::CONFIGGET(_) | Command::CONFIGSET(..)
+            | Command::SUBSCRIBE(_) | Command::UNSUBSCRIBE(_) | Command::PSUBSCRIBE(_) | Command::PUNSUBSCRIBE(_)
+            | Command::PUBLISH(..) | Command::MULTI | Command::EXEC | Command::DISCARD | Command::UNWATCH
+            | Command::SCRIPTLOAD(_) | Command::SCRIPTEXISTS(_) | Command::SCRIPTFLUSH | Command::SCRIPTKILL => vec![],
+        }
+    }
+
+    /// Split an encoded command back into its individual values.
+    ///
+    /// [NumV][LNV][V][LNV][V][LNV][V]
+    /// [cmd_len][str_len][st][str_len][st][str_len][st]
+    pub fn decode(frame: &[u8]) -> Result<VecDeque<Vec<u8>>, Error> {
+        let truncated = || Error::new(ErrorKind::InvalidData, "Command frame is truncated");
+
+        let len_buf: [u8; 4] = frame.get(0..4).ok_or_else(truncated)?.try_into().unwrap();
+        let cmd_len = u32::from_le_bytes(len_buf) as usize;
+
+        let mut strs = VecDeque::new();
+        let mut remaining = &frame[4..];
+        for _ in 0..cmd_len {
+            let len_buf: [u8; 4] = remaining.get(0..4).ok_or_else(truncated)?.try_into().unwrap();
+            let str_len = u32::from_le_bytes(len_buf) as usize;
+            // TODO Can we use slices instead of allocating a new vec for each command?
+            let st = remaining.get(4..str_len + 4).ok_or_else(truncated)?.to_vec();
+            strs.push_back(st);
+            remaining = &remaining[str_len + 4..]
+        }
+        if !remaining.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "Unexpected trailing bytes in command frame"));
+        }
+
+        Ok(strs)
+    }
+
     pub fn parse(mut cmd_str: VecDeque<Vec<u8>>) -> anyhow::Result<Self> {
         let cmd = cmd_str.pop_front();
         if let Some(cmd) = cmd {
             match String::from_utf8(cmd.to_vec())?.as_str() {
-                "keys" => Ok(Command::KEYS),
+                "keys" => {
+                    let pattern = cmd_str.pop_front().map(String::from_utf8).transpose()?;
+                    Ok(Command::KEYS(pattern))
+                },
+                "save" => Ok(Command::SAVE),
+                "bgsave" => Ok(Command::BGSAVE),
+                "bgrewriteaof" => Ok(Command::BGREWRITEAOF),
                 "get" => {
                     let key_bytes = cmd_str.pop_front().ok_or(Error::new(ErrorKind::UnexpectedEof, "Expected cache key after get"))?;
                     Ok(Command::GET(String::from_utf8(key_bytes)?.to_string()))
                 }
                 "del" => {
-                    let key_bytes = cmd_str.pop_front().ok_or(Error::new(ErrorKind::UnexpectedEof, "Expected cache key after del"))?;
-                    Ok(Command::DELETE(String::from_utf8(key_bytes)?.to_string()))
+                    if cmd_str.is_empty() {
+                        return Err(Error::new(ErrorKind::UnexpectedEof, "Expected cache key after del").into());
+                    }
+                    let keys = cmd_str.drain(..).map(String::from_utf8).collect::<Result<_, _>>()?;
+                    Ok(Command::DELETE(keys))
                 },
                 "set" => {
                     let key_bytes = cmd_str.pop_front()
@@ -73,12 +673,644 @@ impl Command {
                         .ok_or(Error::new(ErrorKind::UnexpectedEof, "Expected ttl after byte value"))?;
                     let ttl = u64::from_le_bytes(ttl_bytes.try_into()
                         .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid TTL value encountered"))?);
-                    Ok(Command::SET(String::from_utf8(key_bytes)?.to_string(), value_bytes, ttl))
+                    let options = SetOptions::parse(ttl, &mut cmd_str)?;
+                    Ok(Command::SET(String::from_utf8(key_bytes)?.to_string(), value_bytes, options))
                 }
-                _s => Err(Error::new(ErrorKind::Unsupported, format!("unsupported command: {}", _s)).into()),
+                "hello" => {
+                    let version = cmd_str.pop_front()
+                        .map(|v| v.try_into()
+                            .map(u32::from_le_bytes)
+                            .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid protocol version encountered")))
+                        .transpose()?;
+                    Ok(Command::HELLO(version))
+                },
+                "ping" => {
+                    let msg = cmd_str.pop_front().map(String::from_utf8).transpose()?;
+                    Ok(Command::PING(msg))
+                },
+                "config" => Command::parse_config(cmd_str, "config"),
+                "scan" => {
+                    let cursor = next_u64(&mut cmd_str, "Expected cursor after scan")?;
+                    let count = next_u64(&mut cmd_str, "Expected count after cursor")?;
+                    let mut pattern = None;
+                    let mut kind = None;
+                    while let Some(option) = cmd_str.pop_front() {
+                        let value = cmd_str.pop_front()
+                            .ok_or(Error::new(ErrorKind::UnexpectedEof, "Expected value after scan option"))?;
+                        match option.as_slice() {
+                            b"match" => pattern = Some(String::from_utf8(value)?),
+                            b"type" => kind = Some(String::from_utf8(value)?),
+                            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid scan option encountered").into()),
+                        }
+                    }
+                    Ok(Command::SCAN(cursor, pattern, count, kind))
+                },
+                // Newer commands send every value as text, the same as RESP clients do.
+                _s => {
+                    cmd_str.push_front(_s.as_bytes().to_vec());
+                    Command::parse_resp(cmd_str)
+                },
             }
         } else {
             Err(Error::new(ErrorKind::UnexpectedEof, "Failed to parse command").into())
         }
     }
+
+    /// Parse a command sent by a RESP client such as redis-cli.
+    ///
+    /// Command names are case insensitive and all arguments arrive as text,
+    /// e.g. `SET key value PX 5000`.
+    pub fn parse_resp(mut args: VecDeque<Vec<u8>>) -> anyhow::Result<Self> {
+        let name = args.pop_front()
+            .ok_or(Error::new(ErrorKind::UnexpectedEof, "Failed to parse command"))?;
+        let name = String::from_utf8(name)?.to_ascii_lowercase();
+        let cmd = match name.as_str() {
+            "keys" => Command::KEYS(args.pop_front().map(String::from_utf8).transpose()?),
+            "get" => Command::GET(next_string(&mut args, &name)?),
+            "del" => Command::DELETE(remaining_keys(&mut args, &name)?),
+            "set" => {
+                let key = next_string(&mut args, &name)?;
+                let value = next_arg(&mut args, &name)?;
+                Command::SET(key, value, SetOptions::parse_resp(&mut args)?)
+            },
+            "hello" => {
+                let version = match args.pop_front() {
+                    Some(v) => Some(String::from_utf8(v)?.parse::<u32>()
+                        .map_err(|_| Error::new(ErrorKind::InvalidData, "Protocol version is not an integer or out of range"))?),
+                    None => None,
+                };
+                // AUTH and SETNAME options are accepted but not acted upon.
+                args.clear();
+                Command::HELLO(version)
+            },
+            "ping" => Command::PING(args.pop_front().map(String::from_utf8).transpose()?),
+            "save" => Command::SAVE,
+            "bgsave" => Command::BGSAVE,
+            "bgrewriteaof" => Command::BGREWRITEAOF,
+            "config" => return Command::parse_config(args, &name),
+            "script" => return Command::parse_script(args, &name),
+            "object" => return Command::parse_object(args, &name),
+            "scan" => {
+                let cursor = next_string(&mut args, &name)?.parse::<u64>()
+                    .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid cursor"))?;
+                let mut pattern = None;
+                let mut count = DEFAULT_SCAN_COUNT;
+                let mut kind = None;
+                while let Some(option) = args.pop_front() {
+                    let value = next_string(&mut args, &name)?;
+                    match String::from_utf8(option)?.to_ascii_lowercase().as_str() {
+                        "match" => pattern = Some(value),
+                        "count" => {
+                            count = value.parse::<u64>()
+                                .map_err(|_| Error::new(ErrorKind::InvalidData, "value is not an integer or out of range"))?;
+                            if count == 0 {
+                                return Err(Error::new(ErrorKind::InvalidData, "syntax error").into());
+                            }
+                        },
+                        "type" => kind = Some(value),
+                        _ => return Err(Error::new(ErrorKind::InvalidData, "syntax error").into()),
+                    }
+                }
+                Command::SCAN(cursor, pattern, count, kind)
+            },
+            "lpush" => Command::LPUSH(next_string(&mut args, &name)?, remaining_args(&mut args, &name)?),
+            "rpush" => Command::RPUSH(next_string(&mut args, &name)?, remaining_args(&mut args, &name)?),
+            "lpop" => Command::LPOP(next_string(&mut args, &name)?, optional_count(&mut args)?),
+            "rpop" => Command::RPOP(next_string(&mut args, &name)?, optional_count(&mut args)?),
+            "lrange" => Command::LRANGE(next_string(&mut args, &name)?, next_int(&mut args, &name)?, next_int(&mut args, &name)?),
+            "llen" => Command::LLEN(next_string(&mut args, &name)?),
+            "lindex" => Command::LINDEX(next_string(&mut args, &name)?, next_int(&mut args, &name)?),
+            "ltrim" => Command::LTRIM(next_string(&mut args, &name)?, next_int(&mut args, &name)?, next_int(&mut args, &name)?),
+            "hset" => Command::HSET(next_string(&mut args, &name)?, field_value_pairs(&mut args, &name)?),
+            "hget" => Command::HGET(next_string(&mut args, &name)?, next_arg(&mut args, &name)?),
+            "hdel" => Command::HDEL(next_string(&mut args, &name)?, remaining_args(&mut args, &name)?),
+            "hgetall" => Command::HGETALL(next_string(&mut args, &name)?),
+            "hincrby" => Command::HINCRBY(next_string(&mut args, &name)?, next_arg(&mut args, &name)?, next_int(&mut args, &name)?),
+            "hkeys" => Command::HKEYS(next_string(&mut args, &name)?),
+            "hlen" => Command::HLEN(next_string(&mut args, &name)?),
+            "sadd" => Command::SADD(next_string(&mut args, &name)?, remaining_args(&mut args, &name)?),
+            "srem" => Command::SREM(next_string(&mut args, &name)?, remaining_args(&mut args, &name)?),
+            "sismember" => Command::SISMEMBER(next_string(&mut args, &name)?, next_arg(&mut args, &name)?),
+            "smembers" => Command::SMEMBERS(next_string(&mut args, &name)?),
+            "scard" => Command::SCARD(next_string(&mut args, &name)?),
+            "sinter" => Command::SINTER(remaining_keys(&mut args, &name)?),
+            "sunion" => Command::SUNION(remaining_keys(&mut args, &name)?),
+            "sdiff" => Command::SDIFF(remaining_keys(&mut args, &name)?),
+            "sinterstore" => Command::SINTERSTORE(next_string(&mut args, &name)?, remaining_keys(&mut args, &name)?),
+            "sunionstore" => Command::SUNIONSTORE(next_string(&mut args, &name)?, remaining_keys(&mut args, &name)?),
+            "sdiffstore" => Command::SDIFFSTORE(next_string(&mut args, &name)?, remaining_keys(&mut args, &name)?),
+            "zadd" => {
+                let key = next_string(&mut args, &name)?;
+                let entries = field_value_pairs(&mut args, &name)?.into_iter()
+                    .map(|(score, member)| Ok((parse_score(&score)?, member)))
+                    .collect::<Result<_, Error>>()?;
+                Command::ZADD(key, entries)
+            },
+            "zrem" => Command::ZREM(next_string(&mut args, &name)?, remaining_args(&mut args, &name)?),
+            "zscore" => Command::ZSCORE(next_string(&mut args, &name)?, next_arg(&mut args, &name)?),
+            "zrank" => Command::ZRANK(next_string(&mut args, &name)?, next_arg(&mut args, &name)?),
+            "zrange" => Command::ZRANGE(next_string(&mut args, &name)?, next_int(&mut args, &name)?, next_int(&mut args, &name)?, with_scores(&mut args)?),
+            "zrangebyscore" => {
+                let key = next_string(&mut args, &name)?;
+                let min = ScoreBound::parse(&next_arg(&mut args, &name)?)?;
+                let max = ScoreBound::parse(&next_arg(&mut args, &name)?)?;
+                Command::ZRANGEBYSCORE(key, min, max, with_scores(&mut args)?)
+            },
+            "zincrby" => Command::ZINCRBY(next_string(&mut args, &name)?, parse_score(&next_arg(&mut args, &name)?)?, next_arg(&mut args, &name)?),
+            "zcard" => Command::ZCARD(next_string(&mut args, &name)?),
+            "incr" => Command::INCR(next_string(&mut args, &name)?),
+            "decr" => Command::DECR(next_string(&mut args, &name)?),
+            "incrby" => Command::INCRBY(next_string(&mut args, &name)?, next_int(&mut args, &name)?),
+            "decrby" => Command::DECRBY(next_string(&mut args, &name)?, next_int(&mut args, &name)?),
+            "incrbyfloat" => {
+                let key = next_string(&mut args, &name)?;
+                let increment = parse_score(&next_arg(&mut args, &name)?)
+                    .ok().filter(|increment| increment.is_finite())
+                    .ok_or(Error::new(ErrorKind::InvalidData, "value is not a valid float"))?;
+                Command::INCRBYFLOAT(key, increment)
+            },
+            "ttl" => Command::TTL(next_string(&mut args, &name)?),
+            "pttl" => Command::PTTL(next_string(&mut args, &name)?),
+            "expire" => Command::EXPIRE(next_string(&mut args, &name)?, next_int(&mut args, &name)?),
+            "pexpire" => Command::PEXPIRE(next_string(&mut args, &name)?, next_int(&mut args, &name)?),
+            "expireat" => Command::EXPIREAT(next_string(&mut args, &name)?, next_int(&mut args, &name)?),
+            "pexpireat" => Command::PEXPIREAT(next_string(&mut args, &name)?, next_int(&mut args, &name)?),
+            "persist" => Command::PERSIST(next_string(&mut args, &name)?),
+            "mget" => Command::MGET(remaining_keys(&mut args, &name)?),
+            "mset" => Command::MSET(key_value_pairs(&mut args, &name)?),
+            "msetnx" => Command::MSETNX(key_value_pairs(&mut args, &name)?),
+            "exists" => Command::EXISTS(remaining_keys(&mut args, &name)?),
+            "unlink" => Command::UNLINK(remaining_keys(&mut args, &name)?),
+            "subscribe" => Command::SUBSCRIBE(remaining_keys(&mut args, &name)?),
+            "unsubscribe" => Command::UNSUBSCRIBE(args.drain(..).map(String::from_utf8).collect::<Result<_, _>>()?),
+            "psubscribe" => Command::PSUBSCRIBE(remaining_keys(&mut args, &name)?),
+            "punsubscribe" => Command::PUNSUBSCRIBE(args.drain(..).map(String::from_utf8).collect::<Result<_, _>>()?),
+            "publish" => Command::PUBLISH(next_string(&mut args, &name)?, next_arg(&mut args, &name)?),
+            "multi" => Command::MULTI,
+            "exec" => Command::EXEC,
+            "discard" => Command::DISCARD,
+            "watch" => Command::WATCH(remaining_keys(&mut args, &name)?),
+            "unwatch" => Command::UNWATCH,
+            "eval" | "evalsha" => {
+                let script = next_string(&mut args, &name)?;
+                let num_keys = usize::try_from(next_int(&mut args, &name)?)
+                    .map_err(|_| Error::new(ErrorKind::InvalidData, "Number of keys can't be negative"))?;
+                if num_keys > args.len() {
+                    return Err(Error::new(ErrorKind::InvalidData, "Number of keys can't be greater than number of args").into());
+                }
+                let keys = args.drain(..num_keys).map(String::from_utf8).collect::<Result<_, _>>()?;
+                let script_args = args.drain(..).collect();
+                if name == "eval" {
+                    Command::EVAL(script, keys, script_args)
+                } else {
+                    Command::EVALSHA(script, keys, script_args)
+                }
+            },
+            _s => return Err(Error::new(ErrorKind::Unsupported, format!("unknown command '{}'", _s)).into()),
+        };
+        if !args.is_empty() {
+            return Err(wrong_arity(&name).into());
+        }
+        Ok(cmd)
+    }
+
+    /// `SCRIPT LOAD source`, `SCRIPT EXISTS sha1 [sha1 ...]`, `SCRIPT FLUSH` or `SCRIPT KILL`.
+    fn parse_script(mut args: VecDeque<Vec<u8>>, name: &str) -> anyhow::Result<Self> {
+        let subcommand = next_string(&mut args, name)?.to_ascii_lowercase();
+        let cmd = match subcommand.as_str() {
+            "load" => Command::SCRIPTLOAD(next_string(&mut args, name)?),
+            "exists" => Command::SCRIPTEXISTS(remaining_keys(&mut args, name)?),
+            // The ASYNC and SYNC modes make no difference here.
+            "flush" => {
+                args.pop_front();
+                Command::SCRIPTFLUSH
+            },
+            "kill" => Command::SCRIPTKILL,
+            _s => return Err(Error::new(ErrorKind::Unsupported, format!("unknown subcommand '{}' for 'script'", _s)).into()),
+        };
+        if !args.is_empty() {
+            return Err(wrong_arity(name).into());
+        }
+        Ok(cmd)
+    }
+
+    /// `OBJECT IDLETIME key` or `OBJECT FREQ key`.
+    fn parse_object(mut args: VecDeque<Vec<u8>>, name: &str) -> anyhow::Result<Self> {
+        let subcommand = next_string(&mut args, name)?.to_ascii_lowercase();
+        let cmd = match subcommand.as_str() {
+            "idletime" => Command::OBJECTIDLETIME(next_string(&mut args, name)?),
+            "freq" => Command::OBJECTFREQ(next_string(&mut args, name)?),
+            _s => return Err(Error::new(ErrorKind::Unsupported, format!("unknown subcommand '{}' for 'object'", _s)).into()),
+        };
+        if !args.is_empty() {
+            return Err(wrong_arity(name).into());
+        }
+        Ok(cmd)
+    }
+
+    /// `CONFIG GET pattern` or `CONFIG SET name value`, the subcommand is case insensitive.
+    fn parse_config(mut args: VecDeque<Vec<u8>>, name: &str) -> anyhow::Result<Self> {
+        let subcommand = next_string(&mut args, name)?.to_ascii_lowercase();
+        let cmd = match subcommand.as_str() {
+            "get" => Command::CONFIGGET(next_string(&mut args, name)?),
+            "set" => Command::CONFIGSET(next_string(&mut args, name)?, next_string(&mut args, name)?),
+            _s => return Err(Error::new(ErrorKind::Unsupported, format!("unknown subcommand '{}' for 'config'", _s)).into()),
+        };
+        if !args.is_empty() {
+            return Err(wrong_arity(name).into());
+        }
+        Ok(cmd)
+    }
+}
+
+/// Keys SCAN looks at per call when no COUNT is given.
+pub const DEFAULT_SCAN_COUNT: u64 = 10;
+
+/// A hash field along with its value.
+pub type FieldValue = (Vec<u8>, Vec<u8>);
+
+/// A key along with the string to store at it.
+pub type KeyValue = (String, Vec<u8>);
+
+fn wrong_arity(name: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("wrong number of arguments for '{}' command", name))
+}
+
+fn next_arg(args: &mut VecDeque<Vec<u8>>, name: &str) -> Result<Vec<u8>, Error> {
+    args.pop_front().ok_or_else(|| wrong_arity(name))
+}
+
+fn next_u64(args: &mut VecDeque<Vec<u8>>, msg: &str) -> Result<u64, Error> {
+    let bytes = args.pop_front().ok_or(Error::new(ErrorKind::UnexpectedEof, msg.to_string()))?;
+    bytes.try_into()
+        .map(u64::from_le_bytes)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid integer value encountered"))
+}
+
+fn next_string(args: &mut VecDeque<Vec<u8>>, name: &str) -> anyhow::Result<String> {
+    Ok(String::from_utf8(next_arg(args, name)?)?)
+}
+
+fn parse_int<T: std::str::FromStr>(arg: &[u8]) -> Result<T, Error> {
+    std::str::from_utf8(arg).ok()
+        .and_then(|s| s.parse::<T>().ok())
+        .ok_or(Error::new(ErrorKind::InvalidData, "value is not an integer or out of range"))
+}
+
+fn next_int(args: &mut VecDeque<Vec<u8>>, name: &str) -> Result<i64, Error> {
+    parse_int(&next_arg(args, name)?)
+}
+
+fn optional_count(args: &mut VecDeque<Vec<u8>>) -> Result<Option<u64>, Error> {
+    args.pop_front().map(|count| parse_int(&count)).transpose()
+}
+
+/// Every argument left, of which there must be at least one.
+fn remaining_args(args: &mut VecDeque<Vec<u8>>, name: &str) -> Result<Vec<Vec<u8>>, Error> {
+    if args.is_empty() {
+        return Err(wrong_arity(name));
+    }
+    Ok(args.drain(..).collect())
+}
+
+/// A sorted set score, `inf` and `-inf` included but never NaN.
+fn parse_score(arg: &[u8]) -> Result<f64, Error> {
+    std::str::from_utf8(arg).ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or(Error::new(ErrorKind::InvalidData, "value is not a valid float"))
+}
+
+/// Scores are sent in the shortest form that parses back to the same value, switching
+/// to exponent notation for very large or small ones so 1e300 isn't 301 digits long.
+pub fn format_score(score: f64) -> String {
+    let exponent = if score == 0.0 { 0.0 } else { score.abs().log10().floor() };
+    if (-5.0..17.0).contains(&exponent) {
+        score.to_string()
+    } else {
+        format!("{:e}", score)
+    }
+}
+
+/// The optional WITHSCORES flag ending a range command.
+fn with_scores(args: &mut VecDeque<Vec<u8>>) -> Result<bool, Error> {
+    match args.pop_front() {
+        Some(flag) if flag.eq_ignore_ascii_case(b"withscores") => Ok(true),
+        Some(_) => Err(Error::new(ErrorKind::InvalidData, "syntax error")),
+        None => Ok(false),
+    }
+}
+
+/// Every argument left as a key, of which there must be at least one.
+fn remaining_keys(args: &mut VecDeque<Vec<u8>>, name: &str) -> anyhow::Result<Vec<String>> {
+    Ok(remaining_args(args, name)?.into_iter().map(String::from_utf8).collect::<Result<_, _>>()?)
+}
+
+/// Every argument left as field and value pairs, of which there must be at least one.
+fn field_value_pairs(args: &mut VecDeque<Vec<u8>>, name: &str) -> Result<Vec<FieldValue>, Error> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(wrong_arity(name));
+    }
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    while let (Some(field), Some(value)) = (args.pop_front(), args.pop_front()) {
+        pairs.push((field, value));
+    }
+    Ok(pairs)
+}
+
+/// Every argument left as key and value pairs, of which there must be at least one.
+fn key_value_pairs(args: &mut VecDeque<Vec<u8>>, name: &str) -> anyhow::Result<Vec<KeyValue>> {
+    field_value_pairs(args, name)?.into_iter()
+        .map(|(key, value)| Ok((String::from_utf8(key)?, value)))
+        .collect()
+}
+
+/// [NumV]([LNV][V])*, for commands that send every value as text like RESP clients do.
+fn encode_args(command: &mut Vec<u8>, args: &[&[u8]]) {
+    command.extend_from_slice(&(args.len() as u32).to_le_bytes());
+    for arg in args {
+        command.extend_from_slice(&(arg.len() as u32).to_le_bytes());
+        command.extend_from_slice(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> VecDeque<Vec<u8>> {
+        values.iter().map(|v| v.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_parse_resp() {
+        assert_eq!(Command::KEYS(Some("*".to_string())), Command::parse_resp(args(&["KEYS", "*"])).unwrap());
+        assert_eq!(Command::KEYS(Some("session:*".to_string())), Command::parse_resp(args(&["keys", "session:*"])).unwrap());
+        assert_eq!(Command::GET("k".to_string()), Command::parse_resp(args(&["get", "k"])).unwrap());
+        assert_eq!(Command::DELETE(vec!["k".to_string()]), Command::parse_resp(args(&["Del", "k"])).unwrap());
+        assert_eq!(Command::MSET(vec![("a".to_string(), b"1".to_vec()), ("b".to_string(), b"2".to_vec())]),
+            Command::parse_resp(args(&["MSET", "a", "1", "b", "2"])).unwrap());
+        assert_eq!(Command::SET("k".to_string(), b"v".to_vec(), SetOptions::new()), Command::parse_resp(args(&["SET", "k", "v"])).unwrap());
+        assert_eq!(Command::SET("k".to_string(), b"v".to_vec(), SetOptions::ttl(5000)), Command::parse_resp(args(&["SET", "k", "v", "EX", "5"])).unwrap());
+        assert_eq!(Command::SET("k".to_string(), b"v".to_vec(), SetOptions::ttl(250)), Command::parse_resp(args(&["SET", "k", "v", "px", "250"])).unwrap());
+        assert_eq!(Command::SET("k".to_string(), b"v".to_vec(), SetOptions::new().nx().get().pxat(1_700_000_000_000)),
+            Command::parse_resp(args(&["SET", "k", "v", "get", "EXAT", "1700000000", "NX"])).unwrap());
+        assert_eq!(Command::SET("k".to_string(), b"v".to_vec(), SetOptions::new().xx().keep_ttl()),
+            Command::parse_resp(args(&["SET", "k", "v", "XX", "KEEPTTL"])).unwrap());
+        assert_eq!(Command::HELLO(Some(3)), Command::parse_resp(args(&["HELLO", "3"])).unwrap());
+        assert_eq!(Command::PING(None), Command::parse_resp(args(&["PING"])).unwrap());
+        assert_eq!(Command::SCAN(0, None, DEFAULT_SCAN_COUNT, None), Command::parse_resp(args(&["SCAN", "0"])).unwrap());
+        assert_eq!(Command::SCAN(12, Some("a*".to_string()), 100, Some("string".to_string())),
+            Command::parse_resp(args(&["scan", "12", "MATCH", "a*", "count", "100", "TYPE", "string"])).unwrap());
+        assert_eq!(Command::LPUSH("l".to_string(), vec![b"a".to_vec(), b"b".to_vec()]), Command::parse_resp(args(&["LPUSH", "l", "a", "b"])).unwrap());
+        assert_eq!(Command::LPOP("l".to_string(), None), Command::parse_resp(args(&["lpop", "l"])).unwrap());
+        assert_eq!(Command::RPOP("l".to_string(), Some(2)), Command::parse_resp(args(&["RPOP", "l", "2"])).unwrap());
+        assert_eq!(Command::LRANGE("l".to_string(), 0, -1), Command::parse_resp(args(&["LRANGE", "l", "0", "-1"])).unwrap());
+        assert_eq!(Command::HSET("h".to_string(), vec![(b"f".to_vec(), b"1".to_vec()), (b"g".to_vec(), b"2".to_vec())]),
+            Command::parse_resp(args(&["HSET", "h", "f", "1", "g", "2"])).unwrap());
+        assert_eq!(Command::HINCRBY("h".to_string(), b"f".to_vec(), -5), Command::parse_resp(args(&["hincrby", "h", "f", "-5"])).unwrap());
+        assert_eq!(Command::SINTER(vec!["a".to_string(), "b".to_string()]), Command::parse_resp(args(&["SINTER", "a", "b"])).unwrap());
+        assert_eq!(Command::SDIFFSTORE("d".to_string(), vec!["a".to_string()]), Command::parse_resp(args(&["sdiffstore", "d", "a"])).unwrap());
+        assert_eq!(Command::ZADD("z".to_string(), vec![(1.5, b"a".to_vec()), (f64::NEG_INFINITY, b"b".to_vec())]),
+            Command::parse_resp(args(&["ZADD", "z", "1.5", "a", "-inf", "b"])).unwrap());
+        assert_eq!(Command::ZRANGE("z".to_string(), 0, -1, true), Command::parse_resp(args(&["zrange", "z", "0", "-1", "WITHSCORES"])).unwrap());
+        assert_eq!(Command::ZRANGEBYSCORE("z".to_string(), ScoreBound::Exclusive(1.0), ScoreBound::Inclusive(f64::INFINITY), false),
+            Command::parse_resp(args(&["ZRANGEBYSCORE", "z", "(1", "+inf"])).unwrap());
+        assert_eq!(Command::DECRBY("n".to_string(), -3), Command::parse_resp(args(&["DECRBY", "n", "-3"])).unwrap());
+        assert_eq!(Command::INCRBYFLOAT("n".to_string(), 0.1), Command::parse_resp(args(&["incrbyfloat", "n", "0.1"])).unwrap());
+        assert_eq!(Command::EXPIRE("k".to_string(), -1), Command::parse_resp(args(&["EXPIRE", "k", "-1"])).unwrap());
+        assert_eq!(Command::PERSIST("k".to_string()), Command::parse_resp(args(&["persist", "k"])).unwrap());
+        assert_eq!(Command::MULTI, Command::parse_resp(args(&["MULTI"])).unwrap());
+        assert_eq!(Command::WATCH(vec!["a".to_string(), "b".to_string()]), Command::parse_resp(args(&["watch", "a", "b"])).unwrap());
+        assert_eq!(Command::EVAL("KEYS[0]".to_string(), vec!["k".to_string()], vec![b"1".to_vec()]),
+            Command::parse_resp(args(&["EVAL", "KEYS[0]", "1", "k", "1"])).unwrap());
+        assert_eq!(Command::SCRIPTEXISTS(vec!["abc".to_string()]), Command::parse_resp(args(&["script", "EXISTS", "abc"])).unwrap());
+        assert_eq!(Command::OBJECTIDLETIME("k".to_string()), Command::parse_resp(args(&["OBJECT", "IdleTime", "k"])).unwrap());
+        assert_eq!(Command::CONFIGGET("port".to_string()), Command::parse_resp(args(&["CONFIG", "get", "port"])).unwrap());
+        assert_eq!(Command::CONFIGSET("save".to_string(), "60 1".to_string()), Command::parse_resp(args(&["config", "SET", "save", "60 1"])).unwrap());
+    }
+
+    #[test]
+    fn test_parse_resp_errors() {
+        assert!(Command::parse_resp(args(&["GET"])).is_err());
+        assert!(Command::parse_resp(args(&["GET", "a", "b"])).is_err());
+        assert!(Command::parse_resp(args(&["SET", "k", "v", "EX"])).is_err());
+        assert!(Command::parse_resp(args(&["SET", "k", "v", "NOPE", "1"])).is_err());
+        assert!(Command::parse_resp(args(&["SET", "k", "v", "NX", "XX"])).is_err());
+        assert!(Command::parse_resp(args(&["SET", "k", "v", "EX", "1", "KEEPTTL"])).is_err());
+        assert!(Command::parse_resp(args(&["SET", "k", "v", "PX", "0"])).is_err());
+        assert!(Command::parse_resp(args(&["SET", "k", "v", "EX", "18446744073709551615"])).is_err());
+        assert!(Command::parse_resp(args(&["HELLO", "three"])).is_err());
+        assert!(Command::parse_resp(args(&["FLUSHALL"])).is_err());
+        assert!(Command::parse_resp(args(&["CONFIG", "RESETSTAT"])).is_err());
+        assert!(Command::parse_resp(args(&["SCAN", "-1"])).is_err());
+        assert!(Command::parse_resp(args(&["LPUSH", "l"])).is_err());
+        assert!(Command::parse_resp(args(&["LPOP", "l", "-1"])).is_err());
+        assert!(Command::parse_resp(args(&["LRANGE", "l", "0", "x"])).is_err());
+        assert!(Command::parse_resp(args(&["LINDEX", "l"])).is_err());
+        assert!(Command::parse_resp(args(&["SCAN", "0", "COUNT", "0"])).is_err());
+        assert!(Command::parse_resp(args(&["SCAN", "0", "MATCH"])).is_err());
+        assert!(Command::parse_resp(args(&["CONFIG", "SET", "port"])).is_err());
+        assert!(Command::parse_resp(args(&["HSET", "h"])).is_err());
+        assert!(Command::parse_resp(args(&["HSET", "h", "f", "1", "g"])).is_err());
+        assert!(Command::parse_resp(args(&["HINCRBY", "h", "f", "one"])).is_err());
+        assert!(Command::parse_resp(args(&["HGETALL", "h", "f"])).is_err());
+        assert!(Command::parse_resp(args(&["SADD", "s"])).is_err());
+        assert!(Command::parse_resp(args(&["SUNION"])).is_err());
+        assert!(Command::parse_resp(args(&["SINTERSTORE", "d"])).is_err());
+        assert!(Command::parse_resp(args(&["ZADD", "z", "1"])).is_err());
+        assert!(Command::parse_resp(args(&["ZADD", "z", "nan", "a"])).is_err());
+        assert!(Command::parse_resp(args(&["ZADD", "z", "one", "a"])).is_err());
+        assert!(Command::parse_resp(args(&["ZRANGE", "z", "0", "1", "WITHSCORE"])).is_err());
+        assert!(Command::parse_resp(args(&["ZRANGEBYSCORE", "z", "((1", "2"])).is_err());
+        assert!(Command::parse_resp(args(&["INCR", "n", "1"])).is_err());
+        assert!(Command::parse_resp(args(&["INCRBY", "n", "9223372036854775808"])).is_err());
+        assert!(Command::parse_resp(args(&["INCRBYFLOAT", "n", "inf"])).is_err());
+        assert!(Command::parse_resp(args(&["EXPIRE", "k"])).is_err());
+        assert!(Command::parse_resp(args(&["DEL"])).is_err());
+        assert!(Command::parse_resp(args(&["SUBSCRIBE"])).is_err());
+        assert!(Command::parse_resp(args(&["PUBLISH", "news"])).is_err());
+        assert!(Command::parse_resp(args(&["MSETNX", "a", "1", "b"])).is_err());
+        assert!(Command::parse_resp(args(&["PEXPIREAT", "k", "soon"])).is_err());
+        assert!(Command::parse_resp(args(&["WATCH"])).is_err());
+        assert!(Command::parse_resp(args(&["EXEC", "now"])).is_err());
+        assert!(Command::parse_resp(args(&["EVAL", "1", "2", "k"])).is_err());
+        assert!(Command::parse_resp(args(&["EVALSHA", "abc", "-1"])).is_err());
+        assert!(Command::parse_resp(args(&["SCRIPT", "DEBUG", "yes"])).is_err());
+        assert!(Command::parse_resp(args(&["OBJECT", "ENCODING", "k"])).is_err());
+        assert!(Command::parse_resp(args(&["OBJECT", "FREQ"])).is_err());
+    }
+
+    #[test]
+    fn test_encode_parse_roundtrip() {
+        let commands = [
+            Command::KEYS(None),
+            Command::KEYS(Some("h[ae]llo".to_string())),
+            Command::GET("k".to_string()),
+            Command::SET("k".to_string(), b"v".to_vec(), SetOptions::ttl(10)),
+            Command::SET("k".to_string(), b"v".to_vec(), SetOptions::new().nx().get().pxat(1_700_000_000_000)),
+            Command::SET("k".to_string(), b"v".to_vec(), SetOptions::new().xx().keep_ttl()),
+            Command::DELETE(vec!["k".to_string()]),
+            Command::DELETE(vec!["a".to_string(), "b".to_string()]),
+            Command::HELLO(None),
+            Command::HELLO(Some(3)),
+            Command::PING(Some("hi".to_string())),
+            Command::SAVE,
+            Command::BGSAVE,
+            Command::BGREWRITEAOF,
+            Command::CONFIGGET("*".to_string()),
+            Command::CONFIGSET("loglevel".to_string(), "debug".to_string()),
+            Command::SCAN(0, None, 10, None),
+            Command::SCAN(u64::MAX, Some("k*".to_string()), 1, Some("string".to_string())),
+            Command::LPUSH("l".to_string(), vec![b"a".to_vec(), vec![0, 255]]),
+            Command::RPUSH("l".to_string(), vec![b"b".to_vec()]),
+            Command::LPOP("l".to_string(), None),
+            Command::RPOP("l".to_string(), Some(3)),
+            Command::LRANGE("l".to_string(), -3, -1),
+            Command::LLEN("l".to_string()),
+            Command::LINDEX("l".to_string(), 7),
+            Command::LTRIM("l".to_string(), 1, i64::MAX),
+            Command::HSET("h".to_string(), vec![(b"f".to_vec(), vec![0, 255]), (vec![], b"v".to_vec())]),
+            Command::HGET("h".to_string(), b"f".to_vec()),
+            Command::HDEL("h".to_string(), vec![b"f".to_vec(), b"g".to_vec()]),
+            Command::HGETALL("h".to_string()),
+            Command::HINCRBY("h".to_string(), b"f".to_vec(), i64::MIN),
+            Command::HKEYS("h".to_string()),
+            Command::HLEN("h".to_string()),
+            Command::SADD("s".to_string(), vec![b"a".to_vec(), vec![0, 255]]),
+            Command::SREM("s".to_string(), vec![b"a".to_vec()]),
+            Command::SISMEMBER("s".to_string(), b"a".to_vec()),
+            Command::SMEMBERS("s".to_string()),
+            Command::SCARD("s".to_string()),
+            Command::SINTER(vec!["a".to_string(), "b".to_string()]),
+            Command::SUNION(vec!["a".to_string()]),
+            Command::SDIFF(vec!["a".to_string(), "b".to_string(), "c".to_string()]),
+            Command::SINTERSTORE("d".to_string(), vec!["a".to_string(), "b".to_string()]),
+            Command::SUNIONSTORE("d".to_string(), vec!["a".to_string()]),
+            Command::SDIFFSTORE("d".to_string(), vec!["a".to_string(), "b".to_string()]),
+            Command::ZADD("z".to_string(), vec![(0.1, b"a".to_vec()), (-1e300, vec![0, 255]), (f64::INFINITY, b"c".to_vec())]),
+            Command::ZREM("z".to_string(), vec![b"a".to_vec()]),
+            Command::ZSCORE("z".to_string(), b"a".to_vec()),
+            Command::ZRANK("z".to_string(), b"a".to_vec()),
+            Command::ZRANGE("z".to_string(), 0, -1, false),
+            Command::ZRANGE("z".to_string(), -5, 10, true),
+            Command::ZRANGEBYSCORE("z".to_string(), ScoreBound::Inclusive(f64::NEG_INFINITY), ScoreBound::Exclusive(2.5), true),
+            Command::ZINCRBY("z".to_string(), -0.25, b"a".to_vec()),
+            Command::ZCARD("z".to_string()),
+            Command::INCR("n".to_string()),
+            Command::DECR("n".to_string()),
+            Command::INCRBY("n".to_string(), i64::MAX),
+            Command::DECRBY("n".to_string(), i64::MIN),
+            Command::INCRBYFLOAT("n".to_string(), -1.0e-7),
+            Command::TTL("k".to_string()),
+            Command::PTTL("k".to_string()),
+            Command::EXPIRE("k".to_string(), 10),
+            Command::PEXPIRE("k".to_string(), -5),
+            Command::EXPIREAT("k".to_string(), 1_700_000_000),
+            Command::PEXPIREAT("k".to_string(), 1_700_000_000_000),
+            Command::PERSIST("k".to_string()),
+            Command::MGET(vec!["a".to_string(), "b".to_string()]),
+            Command::MSET(vec![("a".to_string(), vec![0, 255]), ("b".to_string(), vec![])]),
+            Command::MSETNX(vec![("a".to_string(), b"1".to_vec())]),
+            Command::EXISTS(vec!["a".to_string(), "a".to_string()]),
+            Command::UNLINK(vec!["a".to_string()]),
+            Command::SUBSCRIBE(vec!["news".to_string(), "sport".to_string()]),
+            Command::UNSUBSCRIBE(vec![]),
+            Command::PSUBSCRIBE(vec!["news.*".to_string()]),
+            Command::PUNSUBSCRIBE(vec!["news.*".to_string()]),
+            Command::PUBLISH("news".to_string(), vec![0, 255]),
+            Command::MULTI,
+            Command::EXEC,
+            Command::DISCARD,
+            Command::WATCH(vec!["a".to_string()]),
+            Command::UNWATCH,
+            Command::EVAL("ARGV[0]".to_string(), vec![], vec![vec![0, 255]]),
+            Command::EVALSHA("abc".to_string(), vec!["a".to_string(), "b".to_string()], vec![b"1".to_vec()]),
+            Command::SCRIPTLOAD("1 + 1".to_string()),
+            Command::SCRIPTEXISTS(vec!["abc".to_string()]),
+            Command::SCRIPTFLUSH,
+            Command::SCRIPTKILL,
+            Command::OBJECTIDLETIME("k".to_string()),
+            Command::OBJECTFREQ("k".to_string()),
+        ];
+        for cmd in commands {
+            let values = Command::decode(&cmd.encode()).unwrap();
+            assert_eq!(cmd, Command::parse(values).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_set_options_errors() {
+        let set = |options: &[&str]| Command::parse_resp(args(&[&["SET", "k", "v"], options].concat()));
+        assert!(set(&["NX", "XX"]).is_err());
+        assert!(set(&["xx", "nx"]).is_err());
+        assert!(set(&["EX", "5", "PX", "100"]).is_err());
+        assert!(set(&["KEEPTTL", "EXAT", "1700000000"]).is_err());
+        assert!(set(&["EX", "0"]).is_err());
+        assert!(set(&["PX", "-1"]).is_err());
+        assert!(set(&["EX"]).is_err());
+        let overflow = (u64::MAX / 1000 + 1).to_string();
+        assert!(set(&["EX", &overflow]).is_err());
+        assert!(set(&["EXAT", &overflow]).is_err());
+        assert!(set(&["PX", &overflow]).is_ok());
+        assert!(set(&["NX", "NX", "GET", "GET"]).is_ok());
+    }
+
+    #[test]
+    fn test_format_score() {
+        for (score, expected) in [(0.0, "0"), (-2.5, "-2.5"), (10.6, "10.6"), (1e16, "10000000000000000"),
+                                  (1e300, "1e300"), (-1.5e-7, "-1.5e-7"), (f64::INFINITY, "inf")] {
+            assert_eq!(expected, format_score(score));
+            assert_eq!(score, format_score(score).parse::<f64>().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_set_options_roundtrip() {
+        let options = [
+            SetOptions::new(),
+            SetOptions::new().get(),
+            SetOptions::new().keep_ttl(),
+            SetOptions::new().xx().get().keep_ttl(),
+            SetOptions::new().pxat(1_700_000_000_000),
+            SetOptions::new().nx().get().exat(1_700_000_000),
+            SetOptions::ttl(5000).xx().get(),
+        ];
+        for options in options {
+            let cmd = Command::SET("k".to_string(), b"v".to_vec(), options);
+            assert_eq!(cmd, Command::parse(Command::decode(&cmd.encode()).unwrap()).unwrap());
+        }
+
+        // A SET written before options existed is just the relative TTL, 0 for none.
+        let legacy = VecDeque::from([b"set".to_vec(), b"k".to_vec(), b"v".to_vec(), 0u64.to_le_bytes().to_vec()]);
+        assert_eq!(Command::SET("k".to_string(), b"v".to_vec(), SetOptions::new()), Command::parse(legacy).unwrap());
+        let mut unknown = Command::decode(&Command::SET("k".to_string(), b"v".to_vec(), SetOptions::new()).encode()).unwrap();
+        unknown.push_back(b"bogus".to_vec());
+        assert!(Command::parse(unknown).is_err());
+        let mut missing_at = Command::decode(&Command::SET("k".to_string(), b"v".to_vec(), SetOptions::new()).encode()).unwrap();
+        missing_at.push_back(b"pxat".to_vec());
+        assert!(Command::parse(missing_at).is_err());
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(vec!["k"], Command::GET("k".to_string()).keys());
+        let store = Command::SUNIONSTORE("dest".to_string(), vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(vec!["dest", "a", "b"], store.keys());
+        let pairs = Command::MSET(vec![("a".to_string(), b"1".to_vec()), ("b".to_string(), b"2".to_vec())]);
+        assert_eq!(vec!["a", "b"], pairs.keys());
+        assert!(Command::KEYS(None).keys().is_empty());
+        assert!(Command::PUBLISH("channel".to_string(), b"m".to_vec()).keys().is_empty());
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let mut encoded = Command::GET("1234".to_string()).encode();
+        encoded.truncate(encoded.len() - 1);
+        assert!(Command::decode(&encoded).is_err());
+
+        let mut trailing = Command::GET("1234".to_string()).encode();
+        trailing.push(0);
+        assert!(Command::decode(&trailing).is_err());
+    }
 }
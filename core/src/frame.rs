@@ -0,0 +1,227 @@
+use std::io::{Error, ErrorKind};
+
+use tokio::io::{AsyncReadExt, self};
+
+use crate::resp::{RespScanner, RespValue};
+use crate::BUF_MAX;
+
+/// Size in bytes of the length header preceding every frame.
+pub const FRAME_HEADER_LEN: usize = 4;
+
+/// Default upper bound on a single frame's payload, matching Redis' 512MB proto-max-bulk-len.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 512 * 1024 * 1024;
+
+/// Prefix a payload with its total length.
+///
+/// [Len][Payload]
+/// The length is written in network byte order (big-endian).
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Accumulates bytes read off of a stream until complete frames are available.
+///
+/// Any bytes read beyond the end of a frame are kept for the next call,
+/// so short reads and multiple frames arriving in a single read are both handled.
+#[derive(Debug)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+    max_frame_size: usize,
+    /// How much of the RESP value at the front of the buffer has arrived.
+    scanner: RespScanner,
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        FrameReader::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameReader {
+    pub fn new(max_frame_size: usize) -> Self {
+        FrameReader { buffer: Vec::with_capacity(BUF_MAX), max_frame_size, scanner: RespScanner::default() }
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Read from the stream until a full frame is buffered, returning its payload.
+    pub async fn read_frame<T>(&mut self, stream: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncReadExt + Unpin,
+    {
+        loop {
+            if let Some(frame) = self.try_frame()? {
+                return Ok(frame);
+            }
+            self.fill(stream).await?;
+        }
+    }
+
+    /// Return the first buffered byte, reading from the stream if nothing is buffered yet.
+    pub async fn peek<T>(&mut self, stream: &mut T) -> io::Result<u8>
+    where
+        T: AsyncReadExt + Unpin,
+    {
+        if self.buffer.is_empty() {
+            self.fill(stream).await?;
+        }
+        Ok(self.buffer[0])
+    }
+
+    /// Read from the stream until a full RESP value is buffered.
+    pub async fn read_resp<T>(&mut self, stream: &mut T) -> io::Result<RespValue>
+    where
+        T: AsyncReadExt + Unpin,
+    {
+        loop {
+            if let Some(value) = self.try_resp()? {
+                return Ok(value);
+            }
+            if self.buffer.len() > self.max_frame_size {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("Protocol error: request exceeds max frame size of {} bytes", self.max_frame_size)));
+            }
+            self.fill(stream).await?;
+        }
+    }
+
+    /// Pop the next complete RESP value out of the buffer, if one has been fully read.
+    /// The value is only parsed once the scanner has seen all of it arrive.
+    pub fn try_resp(&mut self) -> io::Result<Option<RespValue>> {
+        let invalid = |e: anyhow::Error| Error::new(ErrorKind::InvalidData, e.to_string());
+        let Some(len) = self.scanner.scan(&self.buffer).map_err(invalid)? else {
+            return Ok(None);
+        };
+        match RespValue::parse(&self.buffer[..len]).map_err(invalid)? {
+            Some((value, used)) => {
+                self.buffer.drain(..used);
+                Ok(Some(value))
+            },
+            None => Err(Error::new(ErrorKind::InvalidData, "Protocol error: incomplete value")),
+        }
+    }
+
+    /// Pop the next complete frame out of the buffer, if one has been fully read.
+    pub fn try_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.buffer.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let len_buf: [u8; FRAME_HEADER_LEN] = self.buffer[0..FRAME_HEADER_LEN].try_into().unwrap();
+        let frame_len = u32::from_be_bytes(len_buf) as usize;
+        if frame_len > self.max_frame_size {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("Protocol error: frame of {} bytes exceeds max frame size of {} bytes", frame_len, self.max_frame_size)));
+        }
+        // The buffer grows as the payload arrives rather than up front, so a header
+        // alone can't make the connection allocate up to the max frame size.
+        if self.buffer.len() < FRAME_HEADER_LEN + frame_len {
+            return Ok(None);
+        }
+        let frame = self.buffer[FRAME_HEADER_LEN..FRAME_HEADER_LEN + frame_len].to_vec();
+        self.buffer.drain(..FRAME_HEADER_LEN + frame_len);
+        Ok(Some(frame))
+    }
+
+    /// Perform a single read from the stream, appending whatever arrives to the buffer.
+    async fn fill<T>(&mut self, stream: &mut T) -> io::Result<()>
+    where
+        T: AsyncReadExt + Unpin,
+    {
+        self.buffer.reserve(BUF_MAX);
+        let b = stream.read_buf(&mut self.buffer).await?;
+        if b == 0 {
+            let msg = if self.buffer.is_empty() { "Stream read 0 bytes" } else { "Stream closed mid-frame" };
+            return Err(Error::new(ErrorKind::UnexpectedEof, msg));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_test::io::Builder;
+
+    #[tokio::test]
+    async fn test_read_frame_across_short_reads() {
+        let payload = vec![7u8; BUF_MAX * 3 + 17];
+        let frame = encode_frame(&payload);
+
+        let mut mock = Builder::new()
+            .read(&frame[..2])
+            .read(&frame[2..100])
+            .read(&frame[100..])
+            .build();
+
+        let mut reader = FrameReader::default();
+        let actual = reader.read_frame(&mut mock).await.expect("Failed to read frame");
+        assert_eq!(payload, actual);
+    }
+
+    #[tokio::test]
+    async fn test_read_multiple_frames_in_one_read() {
+        let mut bytes = encode_frame(b"first");
+        bytes.extend_from_slice(&encode_frame(b"second"));
+        let third = encode_frame(b"third");
+        bytes.extend_from_slice(&third[..7]);
+
+        let mut mock = Builder::new()
+            .read(&bytes)
+            .read(&third[7..])
+            .build();
+
+        let mut reader = FrameReader::default();
+        assert_eq!(b"first".to_vec(), reader.read_frame(&mut mock).await.unwrap());
+        assert_eq!(b"second".to_vec(), reader.read_frame(&mut mock).await.unwrap());
+        assert_eq!(b"third".to_vec(), reader.read_frame(&mut mock).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_read_resp_across_short_reads() {
+        let members: Vec<RespValue> = (0..10_000).map(|i| RespValue::BulkString(format!("member{i}").into_bytes())).collect();
+        let request = RespValue::Array([vec![RespValue::BulkString(b"SADD".to_vec())], members].concat());
+        let mut bytes = request.encode();
+        bytes.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+
+        let mut builder = Builder::new();
+        bytes.chunks(1000).for_each(|chunk| { builder.read(chunk); });
+        let mut mock = builder.build();
+
+        let mut reader = FrameReader::default();
+        assert_eq!(request, reader.read_resp(&mut mock).await.unwrap());
+        assert_eq!(RespValue::Array(vec![RespValue::BulkString(b"PING".to_vec())]), reader.read_resp(&mut mock).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_frame_exceeds_max_size() {
+        let frame = encode_frame(&[0u8; 64]);
+        let mut mock = Builder::new().read(&frame).build();
+
+        let mut reader = FrameReader::new(32);
+        let err = reader.read_frame(&mut mock).await.expect_err("Frame should have been rejected");
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_header_does_not_allocate_payload() {
+        let mut reader = FrameReader::default();
+        reader.buffer.extend_from_slice(&(256 * 1024 * 1024u32).to_be_bytes());
+        assert!(reader.try_frame().unwrap().is_none());
+        assert!(reader.buffer.capacity() <= BUF_MAX);
+    }
+
+    #[tokio::test]
+    async fn test_eof_mid_frame() {
+        let frame = encode_frame(b"truncated");
+        let mut mock = Builder::new().read(&frame[..6]).build();
+
+        let mut reader = FrameReader::default();
+        let err = reader.read_frame(&mut mock).await.expect_err("Frame should be incomplete");
+        assert_eq!(ErrorKind::UnexpectedEof, err.kind());
+    }
+}
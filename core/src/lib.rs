@@ -1,4 +1,9 @@
 pub mod response;
 pub mod command;
+pub mod frame;
+pub mod resp;
+pub mod protocol;
+pub mod pattern;
+pub mod pubsub;
 
 pub const BUF_MAX: usize = 256;
@@ -0,0 +1,122 @@
+/// Match `string` against a Redis style glob pattern.
+///
+/// `*` matches any run of bytes, `?` any single byte, `[abc]`, `[a-z]` and
+/// `[^a]` a byte in (or not in) the set, and `\` escapes the next byte.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+    // Where to resume if the current attempt fails: the pattern just after the
+    // last `*`, and the next byte of string that `*` should swallow.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            backtrack = Some((p, s));
+            continue;
+        }
+        if let Some(next) = match_one(pattern, p, string[s]) {
+            p = next;
+            s += 1;
+            continue;
+        }
+        match backtrack {
+            Some((star_p, star_s)) => {
+                p = star_p;
+                s = star_s + 1;
+                backtrack = Some((star_p, star_s + 1));
+            },
+            None => return false,
+        }
+    }
+    pattern[p.min(pattern.len())..].iter().all(|&c| c == b'*')
+}
+
+/// Match a single byte against the token at `p`, returning where the next token starts.
+fn match_one(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    match *pattern.get(p)? {
+        b'?' => Some(p + 1),
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        b'[' => match_class(pattern, p + 1, c),
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+/// Match a byte against a `[...]` class whose contents start at `p`.
+fn match_class(pattern: &[u8], mut p: usize, c: u8) -> Option<usize> {
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+    let mut matched = false;
+    loop {
+        match pattern.get(p) {
+            // An unterminated class runs to the end of the pattern, as in Redis.
+            None => break,
+            Some(b']') => {
+                p += 1;
+                break;
+            },
+            Some(b'\\') if p + 1 < pattern.len() => {
+                matched |= pattern[p + 1] == c;
+                p += 2;
+            },
+            Some(&start) if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() && pattern[p + 2] != b']' => {
+                let end = pattern[p + 2];
+                let (low, high) = if start <= end { (start, end) } else { (end, start) };
+                matched |= (low..=high).contains(&c);
+                p += 3;
+            },
+            Some(&literal) => {
+                matched |= literal == c;
+                p += 1;
+            },
+        }
+    }
+    (matched != negate).then_some(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, string: &str) -> bool {
+        glob_match(pattern.as_bytes(), string.as_bytes())
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("session:*", "session:123"));
+        assert!(!matches("session:*", "user:123"));
+        assert!(matches("*:123", "session:123"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(!matches("a*b*c", "axxbyy"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("**", "x"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        assert!(matches("h[c-a]llo", "hbllo"));
+        assert!(!matches("h[a-c]llo", "hdllo"));
+        assert!(matches("[a-]", "-"));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(matches("h\\*llo", "h*llo"));
+        assert!(!matches("h\\*llo", "hello"));
+        assert!(matches("what\\?", "what?"));
+        assert!(!matches("what\\?", "whats"));
+        assert!(matches("[\\]]", "]"));
+    }
+}
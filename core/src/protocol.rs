@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+
+use crate::command::Command;
+use crate::frame::encode_frame;
+use crate::resp::RespValue;
+use crate::response::Response;
+
+/// Wire protocol spoken by a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// Length prefixed frames holding `Command::encode`/`Response::serialize` payloads.
+    Native,
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    /// Determine the protocol from the first byte a client sends.
+    ///
+    /// Every RESP request is an array and starts with '*'. Native frames start with the
+    /// most significant byte of a big-endian length, which can only be '*' (0x2A) for frames
+    /// of 704MB or more, well above `DEFAULT_MAX_FRAME_SIZE`.
+    pub fn detect(first_byte: u8) -> Self {
+        match first_byte {
+            b'*' => Protocol::Resp2,
+            _ => Protocol::Native,
+        }
+    }
+
+    pub fn is_resp(&self) -> bool {
+        matches!(self, Protocol::Resp2 | Protocol::Resp3)
+    }
+
+    pub fn parse_command(&self, args: VecDeque<Vec<u8>>) -> anyhow::Result<Command> {
+        match self {
+            Protocol::Native => Command::parse(args),
+            Protocol::Resp2 | Protocol::Resp3 => Command::parse_resp(args),
+        }
+    }
+
+    pub fn encode_response(&self, response: &Response) -> Vec<u8> {
+        match self {
+            Protocol::Native => encode_frame(&response.serialize()),
+            Protocol::Resp2 => RespValue::from_response(response, false).encode(),
+            Protocol::Resp3 => RespValue::from_response(response, true).encode(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::DEFAULT_MAX_FRAME_SIZE;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Protocol::Resp2, Protocol::detect(b'*'));
+        let native = encode_frame(&Command::KEYS(None).encode());
+        assert_eq!(Protocol::Native, Protocol::detect(native[0]));
+        assert!((DEFAULT_MAX_FRAME_SIZE as u32).to_be_bytes()[0] < b'*');
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let native = VecDeque::from([b"get".to_vec(), b"key".to_vec()]);
+        assert_eq!(Command::GET("key".to_string()), Protocol::Native.parse_command(native).unwrap());
+
+        let resp = VecDeque::from([b"GET".to_vec(), b"key".to_vec()]);
+        assert_eq!(Command::GET("key".to_string()), Protocol::Resp2.parse_command(resp).unwrap());
+    }
+}
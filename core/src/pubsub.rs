@@ -0,0 +1,81 @@
+use anyhow::anyhow;
+
+use crate::response::Response;
+
+/// A message published to a channel, as it is pushed to subscribers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub channel: String,
+    /// The pattern the channel matched, when delivered through PSUBSCRIBE.
+    pub pattern: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+/// Everything the server pushes to a subscribed connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Push {
+    Message(Message),
+    /// Confirms a SUBSCRIBE, UNSUBSCRIBE, PSUBSCRIBE or PUNSUBSCRIBE of one channel or pattern,
+    /// with how many subscriptions the connection holds afterwards.
+    Subscription { kind: String, name: Option<String>, count: i64 },
+}
+
+impl Push {
+    /// Laid out as Redis does, e.g. `message channel payload` or `subscribe channel 1`.
+    pub fn into_response(self) -> Response {
+        let text = |s: String| Response::Data(s.into_bytes());
+        match self {
+            Push::Message(Message { channel, pattern: None, payload }) => {
+                Response::Push(vec![text("message".to_string()), text(channel), Response::Data(payload)])
+            },
+            Push::Message(Message { channel, pattern: Some(pattern), payload }) => {
+                Response::Push(vec![text("pmessage".to_string()), text(pattern), text(channel), Response::Data(payload)])
+            },
+            Push::Subscription { kind, name, count } => {
+                Response::Push(vec![text(kind), name.map(text).unwrap_or(Response::Empty), Response::Int(count)])
+            },
+        }
+    }
+
+    pub fn from_response(response: Response) -> anyhow::Result<Self> {
+        let parts = match response {
+            Response::Push(parts) | Response::Multi(parts) => parts,
+            Response::Error(e) => return Err(anyhow!(e)),
+            other => return Err(anyhow!("Unexpected push {:?}", other)),
+        };
+        let text = |part: &Response| match part {
+            Response::Data(data) => Ok(String::from_utf8(data.clone())?),
+            other => Err(anyhow!("Unexpected push value {:?}", other)),
+        };
+        match parts.as_slice() {
+            [kind, channel, Response::Data(payload)] if text(kind)? == "message" => {
+                Ok(Push::Message(Message { channel: text(channel)?, pattern: None, payload: payload.clone() }))
+            },
+            [kind, pattern, channel, Response::Data(payload)] if text(kind)? == "pmessage" => {
+                Ok(Push::Message(Message { channel: text(channel)?, pattern: Some(text(pattern)?), payload: payload.clone() }))
+            },
+            [kind, Response::Empty, Response::Int(count)] => Ok(Push::Subscription { kind: text(kind)?, name: None, count: *count }),
+            [kind, name, Response::Int(count)] => Ok(Push::Subscription { kind: text(kind)?, name: Some(text(name)?), count: *count }),
+            _ => Err(anyhow!("Unexpected push {:?}", parts)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_roundtrip() {
+        let pushes = [
+            Push::Message(Message { channel: "news".to_string(), pattern: None, payload: vec![0, 255] }),
+            Push::Message(Message { channel: "news.uk".to_string(), pattern: Some("news.*".to_string()), payload: b"hi".to_vec() }),
+            Push::Subscription { kind: "subscribe".to_string(), name: Some("news".to_string()), count: 1 },
+            Push::Subscription { kind: "unsubscribe".to_string(), name: None, count: 0 },
+        ];
+        for push in pushes {
+            let response = Response::deserialize(&push.clone().into_response().serialize()).unwrap();
+            assert_eq!(push, Push::from_response(response).unwrap());
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::VecDeque;
+
+use anyhow::anyhow;
+
+use crate::response::Response;
+
+const CRLF: &[u8] = b"\r\n";
+
+/// Deepest nesting of arrays, sets, maps and pushes accepted, so a malicious
+/// `*1\r\n*1\r\n...` can't recurse until the stack overflows.
+pub const MAX_NESTING_DEPTH: usize = 64;
+
+/// A single value of the REdis Serialization Protocol.
+///
+/// Covers both RESP2 and the additional RESP3 types.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RespValue {
+    SimpleString(String), // +
+    Error(String), // -
+    Integer(i64), // :
+    BulkString(Vec<u8>), // $
+    Array(Vec<RespValue>), // *
+    Null, // _
+    NullBulkString, // $-1, RESP2's null
+    NullArray, // *-1
+    Double(f64), // ,
+    Boolean(bool), // #
+    BigNumber(String), // (
+    Map(Vec<(RespValue, RespValue)>), // %
+    Set(Vec<RespValue>), // ~
+    Push(Vec<RespValue>), // >
+}
+
+impl RespValue {
+    /// Attempt to parse a single value from the front of the buffer.
+    ///
+    /// Returns the value along with the number of bytes it occupied,
+    /// or None if the buffer does not yet hold a complete value.
+    pub fn parse(buffer: &[u8]) -> anyhow::Result<Option<(RespValue, usize)>> {
+        RespValue::parse_nested(buffer, 0)
+    }
+
+    /// Parse a value found `depth` aggregates deep.
+    fn parse_nested(buffer: &[u8], depth: usize) -> anyhow::Result<Option<(RespValue, usize)>> {
+        let Some(&type_byte) = buffer.first() else {
+            return Ok(None);
+        };
+        let Some((line, mut cur)) = read_line(buffer, 1) else {
+            return Ok(None);
+        };
+
+        let value = match type_byte {
+            b'+' => RespValue::SimpleString(std::str::from_utf8(line)?.to_string()),
+            b'-' => RespValue::Error(std::str::from_utf8(line)?.to_string()),
+            b':' => RespValue::Integer(parse_int(line)?),
+            b',' => RespValue::Double(parse_double(line)?),
+            b'(' => RespValue::BigNumber(std::str::from_utf8(line)?.to_string()),
+            b'_' => RespValue::Null,
+            b'#' => match line {
+                b"t" => RespValue::Boolean(true),
+                b"f" => RespValue::Boolean(false),
+                _ => return Err(anyhow!("Protocol error: invalid boolean")),
+            },
+            b'$' | b'=' | b'!' => {
+                let len = parse_int(line)?;
+                if len == -1 {
+                    return Ok(Some((RespValue::NullBulkString, cur)));
+                }
+                let len = usize::try_from(len).map_err(|_| anyhow!("Protocol error: invalid bulk length"))?;
+                if buffer.len() < cur + len + CRLF.len() {
+                    return Ok(None);
+                }
+                if &buffer[cur + len..cur + len + CRLF.len()] != CRLF {
+                    return Err(anyhow!("Protocol error: bulk string is not terminated by CRLF"));
+                }
+                let data = buffer[cur..cur + len].to_vec();
+                cur += len + CRLF.len();
+                match type_byte {
+                    b'!' => RespValue::Error(String::from_utf8(data)?),
+                    _ => RespValue::BulkString(data),
+                }
+            },
+            b'*' | b'~' | b'>' | b'%' => {
+                let len = parse_int(line)?;
+                if len == -1 {
+                    return Ok(Some((RespValue::NullArray, cur)));
+                }
+                let len = usize::try_from(len).map_err(|_| anyhow!("Protocol error: invalid multibulk length"))?;
+                if depth >= MAX_NESTING_DEPTH {
+                    return Err(nested_too_deep());
+                }
+                let count = if type_byte == b'%' { len * 2 } else { len };
+
+                let mut values = Vec::with_capacity(count.min(1024));
+                for _ in 0..count {
+                    match RespValue::parse_nested(&buffer[cur..], depth + 1)? {
+                        Some((value, used)) => {
+                            values.push(value);
+                            cur += used;
+                        },
+                        None => return Ok(None),
+                    }
+                }
+                match type_byte {
+                    b'*' => RespValue::Array(values),
+                    b'~' => RespValue::Set(values),
+                    b'>' => RespValue::Push(values),
+                    _ => {
+                        let mut pairs = Vec::with_capacity(len);
+                        let mut values = values.into_iter();
+                        while let (Some(k), Some(v)) = (values.next(), values.next()) {
+                            pairs.push((k, v));
+                        }
+                        RespValue::Map(pairs)
+                    },
+                }
+            },
+            b => return Err(anyhow!("Protocol error: unexpected type byte '{}'", b as char)),
+        };
+        Ok(Some((value, cur)))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            RespValue::SimpleString(s) => write_line(out, b'+', s.as_bytes()),
+            RespValue::Error(s) => write_line(out, b'-', s.as_bytes()),
+            RespValue::Integer(i) => write_line(out, b':', i.to_string().as_bytes()),
+            RespValue::BulkString(data) => {
+                write_line(out, b'$', data.len().to_string().as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(CRLF);
+            },
+            RespValue::Array(values) => write_aggregate(out, b'*', values),
+            RespValue::Null => write_line(out, b'_', b""),
+            RespValue::NullBulkString => write_line(out, b'$', b"-1"),
+            RespValue::NullArray => write_line(out, b'*', b"-1"),
+            RespValue::Double(d) => write_line(out, b',', format_double(*d).as_bytes()),
+            RespValue::Boolean(b) => write_line(out, b'#', if *b { b"t" } else { b"f" }),
+            RespValue::BigNumber(n) => write_line(out, b'(', n.as_bytes()),
+            RespValue::Map(pairs) => {
+                write_line(out, b'%', pairs.len().to_string().as_bytes());
+                for (k, v) in pairs {
+                    k.encode_into(out);
+                    v.encode_into(out);
+                }
+            },
+            RespValue::Set(values) => write_aggregate(out, b'~', values),
+            RespValue::Push(values) => write_aggregate(out, b'>', values),
+        }
+    }
+
+    /// Convert a client request into the list of arguments expected by `Command::parse_resp`.
+    pub fn into_args(self) -> anyhow::Result<VecDeque<Vec<u8>>> {
+        match self {
+            RespValue::Array(values) => values.into_iter()
+                .map(|v| match v {
+                    RespValue::BulkString(data) => Ok(data),
+                    RespValue::SimpleString(s) => Ok(s.into_bytes()),
+                    _ => Err(anyhow!("Protocol error: expected bulk string arguments")),
+                })
+                .collect(),
+            _ => Err(anyhow!("Protocol error: expected an array of bulk strings")),
+        }
+    }
+
+    /// Convert a server response into its RESP representation.
+    ///
+    /// RESP2 has no null, double or map types, those are downgraded to
+    /// a null bulk string, a bulk string and a flat array respectively.
+    pub fn from_response(response: &Response, resp3: bool) -> RespValue {
+        match response {
+            Response::Empty if resp3 => RespValue::Null,
+            Response::Empty => RespValue::NullBulkString,
+            Response::Error(msg) => RespValue::Error(error_with_code(msg)),
+            Response::String(s) if s.contains(['\r', '\n']) => RespValue::BulkString(s.as_bytes().to_vec()),
+            Response::String(s) => RespValue::SimpleString(s.clone()),
+            Response::Int(i) => RespValue::Integer(*i),
+            Response::Float(f) if resp3 => RespValue::Double(*f as f64),
+            Response::Float(f) => RespValue::BulkString(format_double(*f as f64).into_bytes()),
+            Response::Array(arr) => RespValue::Array(arr.iter()
+                .map(|s| RespValue::BulkString(s.as_bytes().to_vec()))
+                .collect()),
+            Response::Data(data) => RespValue::BulkString(data.clone()),
+            Response::Multi(responses) => RespValue::Array(responses.iter()
+                .map(|r| RespValue::from_response(r, resp3))
+                .collect()),
+            Response::Push(responses) if resp3 => RespValue::Push(responses.iter()
+                .map(|r| RespValue::from_response(r, true))
+                .collect()),
+            // RESP2 clients tell pushes apart by their first element, e.g. "message".
+            Response::Push(responses) => RespValue::Array(responses.iter()
+                .map(|r| RespValue::from_response(r, false))
+                .collect()),
+            Response::Map(pairs) if resp3 => RespValue::Map(pairs.iter()
+                .map(|(field, value)| (RespValue::BulkString(field.clone()), RespValue::BulkString(value.clone())))
+                .collect()),
+            // RESP2 has no map type, pairs are flattened into an array of fields and values.
+            Response::Map(pairs) => RespValue::Array(pairs.iter()
+                .flat_map(|(field, value)| [RespValue::BulkString(field.clone()), RespValue::BulkString(value.clone())])
+                .collect()),
+        }
+    }
+}
+
+/// Finds where the RESP value at the front of a buffer ends without building it. Progress is kept
+/// between calls, so a large value arriving over many reads is only scanned once.
+#[derive(Debug, Default)]
+pub struct RespScanner {
+    /// Bytes of the value known to be complete so far.
+    cur: usize,
+    /// Elements still to come in each aggregate opened but not yet finished, innermost last.
+    pending: Vec<usize>,
+}
+
+impl RespScanner {
+    /// Continue scanning `buffer`, which must only have grown since the last call, returning
+    /// the length of the value once all of it has arrived. The scanner then starts over.
+    pub fn scan(&mut self, buffer: &[u8]) -> anyhow::Result<Option<usize>> {
+        loop {
+            let Some(&type_byte) = buffer.get(self.cur) else {
+                return Ok(None);
+            };
+            let Some((line, mut next)) = read_line(buffer, self.cur + 1) else {
+                return Ok(None);
+            };
+            let elements = match type_byte {
+                b'+' | b'-' | b':' | b',' | b'(' | b'_' | b'#' => 0,
+                b'$' | b'=' | b'!' => {
+                    let len = parse_int(line)?;
+                    if len != -1 {
+                        let len = usize::try_from(len).map_err(|_| anyhow!("Protocol error: invalid bulk length"))?;
+                        if buffer.len() < next + len + CRLF.len() {
+                            return Ok(None);
+                        }
+                        if &buffer[next + len..next + len + CRLF.len()] != CRLF {
+                            return Err(anyhow!("Protocol error: bulk string is not terminated by CRLF"));
+                        }
+                        next += len + CRLF.len();
+                    }
+                    0
+                },
+                b'*' | b'~' | b'>' | b'%' => match parse_int(line)? {
+                    -1 => 0,
+                    len => {
+                        let len = usize::try_from(len).map_err(|_| anyhow!("Protocol error: invalid multibulk length"))?;
+                        if type_byte == b'%' { len * 2 } else { len }
+                    },
+                },
+                b => return Err(anyhow!("Protocol error: unexpected type byte '{}'", b as char)),
+            };
+            self.cur = next;
+            if elements > 0 {
+                if self.pending.len() >= MAX_NESTING_DEPTH {
+                    return Err(nested_too_deep());
+                }
+                self.pending.push(elements);
+                continue;
+            }
+            // A value just finished, which may finish the aggregates around it too.
+            loop {
+                match self.pending.last_mut() {
+                    None => {
+                        let len = self.cur;
+                        self.cur = 0;
+                        return Ok(Some(len));
+                    },
+                    Some(1) => { self.pending.pop(); },
+                    Some(remaining) => {
+                        *remaining -= 1;
+                        break;
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Error codes a message may already start with, anything else gets the generic ERR.
+const ERROR_CODES: &[&str] = &["ERR", "WRONGTYPE", "NOSCRIPT", "NOTBUSY", "UNKILLABLE", "BUSY", "EXECABORT", "NOPROTO", "OOM"];
+
+/// Prefix an error message with the generic ERR code unless it already carries one, e.g. WRONGTYPE.
+fn error_with_code(msg: &str) -> String {
+    let code = msg.split(' ').next().unwrap_or_default();
+    if ERROR_CODES.contains(&code) {
+        msg.to_string()
+    } else {
+        format!("ERR {}", msg)
+    }
+}
+
+fn nested_too_deep() -> anyhow::Error {
+    anyhow!("Protocol error: more than {} nested aggregates", MAX_NESTING_DEPTH)
+}
+
+fn read_line(buffer: &[u8], start: usize) -> Option<(&[u8], usize)> {
+    let end = buffer.get(start..)?
+        .windows(CRLF.len())
+        .position(|w| w == CRLF)?;
+    Some((&buffer[start..start + end], start + end + CRLF.len()))
+}
+
+fn parse_int(line: &[u8]) -> anyhow::Result<i64> {
+    std::str::from_utf8(line)?
+        .parse::<i64>()
+        .map_err(|_| anyhow!("Protocol error: invalid integer"))
+}
+
+fn parse_double(line: &[u8]) -> anyhow::Result<f64> {
+    match line {
+        b"inf" => Ok(f64::INFINITY),
+        b"-inf" => Ok(f64::NEG_INFINITY),
+        _ => std::str::from_utf8(line)?
+            .parse::<f64>()
+            .map_err(|_| anyhow!("Protocol error: invalid double")),
+    }
+}
+
+fn format_double(d: f64) -> String {
+    match d {
+        d if d == f64::INFINITY => String::from("inf"),
+        d if d == f64::NEG_INFINITY => String::from("-inf"),
+        d => d.to_string(),
+    }
+}
+
+fn write_line(out: &mut Vec<u8>, type_byte: u8, line: &[u8]) {
+    out.push(type_byte);
+    out.extend_from_slice(line);
+    out.extend_from_slice(CRLF);
+}
+
+fn write_aggregate(out: &mut Vec<u8>, type_byte: u8, values: &[RespValue]) {
+    write_line(out, type_byte, values.len().to_string().as_bytes());
+    for v in values {
+        v.encode_into(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    #[test]
+    fn test_parse_command_array() {
+        let input = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n";
+        let (value, used) = RespValue::parse(input).unwrap().unwrap();
+
+        assert_eq!(input.len(), used);
+        assert_eq!(VecDeque::from([b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]), value.into_args().unwrap());
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        let input = b"*2\r\n$3\r\nGET\r\n$3\r\nke";
+        for i in 0..input.len() {
+            assert!(RespValue::parse(&input[..i]).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_parse_resp3_types() {
+        let input = b"%2\r\n+proto\r\n:3\r\n$4\r\nnull\r\n_\r\n";
+        let (value, _) = RespValue::parse(input).unwrap().unwrap();
+        assert_eq!(RespValue::Map(vec![
+            (RespValue::SimpleString("proto".to_string()), RespValue::Integer(3)),
+            (RespValue::BulkString(b"null".to_vec()), RespValue::Null),
+        ]), value);
+
+        assert_eq!(RespValue::Double(1.5), RespValue::parse(b",1.5\r\n").unwrap().unwrap().0);
+        assert_eq!(RespValue::Boolean(true), RespValue::parse(b"#t\r\n").unwrap().unwrap().0);
+        assert_eq!(RespValue::NullBulkString, RespValue::parse(b"$-1\r\n").unwrap().unwrap().0);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(RespValue::parse(b"?what\r\n").is_err());
+        assert!(RespValue::parse(b"$3\r\nabcde\r\n").is_err());
+        assert!(RespValue::parse(b"*-5\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_nesting_limit() {
+        let nested = |depth: usize| [b"*1\r\n".repeat(depth), b":1\r\n".to_vec()].concat();
+        assert!(RespValue::parse(&nested(MAX_NESTING_DEPTH)).unwrap().is_some());
+        assert!(RespValue::parse(&nested(MAX_NESTING_DEPTH + 1)).is_err());
+        // Deep enough to overflow the stack if each level recursed.
+        assert!(RespValue::parse(&nested(1_000_000)).is_err());
+    }
+
+    #[test]
+    fn test_scan() {
+        let input = b"*2\r\n$3\r\nGET\r\n%1\r\n+k\r\n*0\r\n:1\r\n";
+        let mut scanner = RespScanner::default();
+        for i in 0..input.len() - 4 {
+            assert_eq!(None, scanner.scan(&input[..i]).unwrap());
+        }
+        assert_eq!(Some(input.len() - 4), scanner.scan(input).unwrap());
+        assert_eq!(Some(4), scanner.scan(&input[input.len() - 4..]).unwrap());
+
+        assert!(RespScanner::default().scan(b"$3\r\nabcde\r\n").is_err());
+        assert!(RespScanner::default().scan(b"?what\r\n").is_err());
+        let nested = [b"*1\r\n".repeat(1_000_000), b":1\r\n".to_vec()].concat();
+        assert!(RespScanner::default().scan(&nested).is_err());
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let value = RespValue::Array(vec![
+            RespValue::SimpleString("OK".to_string()),
+            RespValue::Error("ERR bad".to_string()),
+            RespValue::Integer(-42),
+            RespValue::BulkString(b"bin\r\nary".to_vec()),
+            RespValue::Null,
+            RespValue::Double(2.25),
+            RespValue::Boolean(false),
+            RespValue::Set(vec![RespValue::Integer(1)]),
+        ]);
+        let encoded = value.encode();
+        assert_eq!((value, encoded.len()), RespValue::parse(&encoded).unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_from_response() {
+        assert_eq!(b"$-1\r\n".to_vec(), RespValue::from_response(&Response::Empty, false).encode());
+        assert_eq!(b"_\r\n".to_vec(), RespValue::from_response(&Response::Empty, true).encode());
+        assert_eq!(b"-ERR oops\r\n".to_vec(), RespValue::from_response(&Response::Error("oops".to_string()), false).encode());
+        assert_eq!(b"-NOPROTO no\r\n".to_vec(), RespValue::from_response(&Response::Error("NOPROTO no".to_string()), false).encode());
+        assert_eq!(b"-ERR TTL must be positive\r\n".to_vec(),
+            RespValue::from_response(&Response::Error("TTL must be positive".to_string()), false).encode());
+        assert_eq!(b"$3\r\n1.5\r\n".to_vec(), RespValue::from_response(&Response::Float(1.5), false).encode());
+        assert_eq!(b",1.5\r\n".to_vec(), RespValue::from_response(&Response::Float(1.5), true).encode());
+        assert_eq!(b"*2\r\n$1\r\na\r\n$1\r\nb\r\n".to_vec(),
+            RespValue::from_response(&Response::Array(Arc::new(vec!["a".to_string(), "b".to_string()])), false).encode());
+        assert_eq!(b"*2\r\n$1\r\n0\r\n*1\r\n$1\r\na\r\n".to_vec(),
+            RespValue::from_response(&Response::Multi(vec![
+                Response::Data(b"0".to_vec()),
+                Response::Array(Arc::new(vec!["a".to_string()])),
+            ]), false).encode());
+        let map = Response::Map(vec![(b"f".to_vec(), b"v".to_vec())]);
+        assert_eq!(b"*2\r\n$1\r\nf\r\n$1\r\nv\r\n".to_vec(), RespValue::from_response(&map, false).encode());
+        assert_eq!(b"%1\r\n$1\r\nf\r\n$1\r\nv\r\n".to_vec(), RespValue::from_response(&map, true).encode());
+        let push = Response::Push(vec![Response::Data(b"message".to_vec()), Response::Int(1)]);
+        assert_eq!(b"*2\r\n$7\r\nmessage\r\n:1\r\n".to_vec(), RespValue::from_response(&push, false).encode());
+        assert_eq!(b">2\r\n$7\r\nmessage\r\n:1\r\n".to_vec(), RespValue::from_response(&push, true).encode());
+    }
+}
@@ -6,58 +6,76 @@ pub enum Response {
     Empty, // 0
     Error(String), // 1
     String(String), // 2
-    Int(i32), // 3
+    Int(i64), // 3
     Float(f32), // 4
     Array(Arc<Vec<String>>), // 5
     Data(Vec<u8>), // 6
+    Multi(Vec<Response>), // 7
+    /// Field and value pairs, binary safe.
+    Map(Vec<(Vec<u8>, Vec<u8>)>), // 8
+    /// Sent by the server on its own rather than in reply to a command, such as a published message.
+    Push(Vec<Response>), // 9
 }
 
 impl Response {
     pub fn deserialize(buffer: &[u8]) -> Result<Response, anyhow::Error> {
-        let response_type: &[u8; 4] = &buffer[0..4].try_into()?;
-
-        match u32::from_le_bytes(*response_type) {
+        match read_u32(buffer, 0)? {
             0 => Ok(Response::Empty),
             1 => { // Response::Error
-                let len_buf: &[u8; 4] = &buffer[4..8].try_into()?;
-                let msg_size = u32::from_le_bytes(*len_buf) as usize;
-                let remaining = &buffer[8..msg_size+8];
-                Ok(Response::Error(std::str::from_utf8(remaining)?.to_string()))
+                let (msg, _) = read_bytes(buffer, 4)?;
+                Ok(Response::Error(std::str::from_utf8(msg)?.to_string()))
             },
             2 => { // Response::String
-                let len_buf: &[u8; 4] = &buffer[4..8].try_into()?;
-                let val_size = u32::from_le_bytes(*len_buf) as usize;
-                let val_bytes = &buffer[8..8+val_size];
+                let (val_bytes, _) = read_bytes(buffer, 4)?;
                 Ok(Response::String(std::str::from_utf8(val_bytes)?.to_string()))
             },
-            3 => { // Response::Float
-                Ok(Response::Float(f32::from_le_bytes(buffer[4..8].try_into()?)))
+            3 => { // Response::Int
+                Ok(Response::Int(i64::from_le_bytes(buffer.get(4..12).ok_or_else(truncated)?.try_into()?)))
             },
-            4 => { // Response:: Int
-                Ok(Response::Int(i32::from_le_bytes(buffer[4..8].try_into()?)))
+            4 => { // Response::Float
+                Ok(Response::Float(f32::from_le_bytes(buffer.get(4..8).ok_or_else(truncated)?.try_into()?)))
             },
             5 => { // Response::Array
-                let size_buf: &[u8; 4] = &buffer[4..8].try_into()?;
-                let array_size = u32::from_le_bytes(*size_buf) as usize;
+                let array_size = read_u32(buffer, 4)?;
 
                 let mut arr = Vec::new();
                 let mut cur = 8;
                 for _ in 0..array_size {
-                    let len_buf = &buffer[cur..cur+4].try_into()?;
-                    let val_size = u32::from_le_bytes(*len_buf) as usize;
-                    let val_bytes = &buffer[cur+4..cur+4+val_size];
-                    let st = std::str::from_utf8(val_bytes)?;
-                    arr.push(st.to_owned());
-                    cur = cur+4+val_size;
+                    let (val_bytes, next) = read_bytes(buffer, cur)?;
+                    arr.push(std::str::from_utf8(val_bytes)?.to_owned());
+                    cur = next;
                 };
                 Ok(Response::Array(Arc::new(arr)))
             },
             6 => { // Response::Data
-                let len_buf: &[u8; 4] = &buffer[4..8].try_into()?;
-                let msg_size = u32::from_le_bytes(*len_buf) as usize;
-                let data = &buffer[8..msg_size+8];
+                let (data, _) = read_bytes(buffer, 4)?;
                 Ok(Response::Data(data.to_vec()))
             },
+            tag @ (7 | 9) => { // Response::Multi and Response::Push
+                let count = read_u32(buffer, 4)?;
+
+                let mut responses = Vec::new();
+                let mut cur = 8;
+                for _ in 0..count {
+                    let (value, next) = read_bytes(buffer, cur)?;
+                    responses.push(Response::deserialize(value)?);
+                    cur = next;
+                }
+                Ok(if tag == 7 { Response::Multi(responses) } else { Response::Push(responses) })
+            },
+            8 => { // Response::Map
+                let count = read_u32(buffer, 4)?;
+
+                let mut pairs = Vec::new();
+                let mut cur = 8;
+                for _ in 0..count {
+                    let (field, next) = read_bytes(buffer, cur)?;
+                    let (value, next) = read_bytes(buffer, next)?;
+                    pairs.push((field.to_vec(), value.to_vec()));
+                    cur = next;
+                }
+                Ok(Response::Map(pairs))
+            },
             _ => Err(anyhow!(String::from("Unrecognized Response Code")))
         }
     }
@@ -104,7 +122,73 @@ impl Response {
                 vv.extend_from_slice(data);
                 vv
             },
+            Response::Multi(responses) | Response::Push(responses) => {
+                let tag = if matches!(self, Response::Multi(_)) { 7u32 } else { 9u32 };
+                let mut vv = tag.to_le_bytes().to_vec();
+                vv.extend_from_slice(&(responses.len() as u32).to_le_bytes());
+                for response in responses {
+                    let value = response.serialize();
+                    vv.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    vv.extend_from_slice(&value);
+                }
+                vv
+            },
+            Response::Map(pairs) => {
+                let mut vv = 8u32.to_le_bytes().to_vec();
+                vv.extend_from_slice(&(pairs.len() as u32).to_le_bytes());
+                for (field, value) in pairs {
+                    vv.extend_from_slice(&(field.len() as u32).to_le_bytes());
+                    vv.extend_from_slice(field);
+                    vv.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    vv.extend_from_slice(value);
+                }
+                vv
+            },
         }
     }
 }
 
+fn truncated() -> anyhow::Error {
+    anyhow!("Response is truncated")
+}
+
+/// The little endian u32 at `at`.
+fn read_u32(buffer: &[u8], at: usize) -> Result<usize, anyhow::Error> {
+    let bytes = buffer.get(at..at + 4).ok_or_else(truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into()?) as usize)
+}
+
+/// The length prefixed bytes at `at`, along with where the next value starts.
+fn read_bytes(buffer: &[u8], at: usize) -> Result<(&[u8], usize), anyhow::Error> {
+    let len = read_u32(buffer, at)?;
+    let start = at + 4;
+    let bytes = buffer.get(start..start + len).ok_or_else(truncated)?;
+    Ok((bytes, start + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_truncated() {
+        let responses = [
+            Response::Error("ERR bad".to_string()),
+            Response::String("OK".to_string()),
+            Response::Int(-7),
+            Response::Float(1.5),
+            Response::Array(Arc::new(vec!["a".to_string(), "bc".to_string()])),
+            Response::Data(b"data".to_vec()),
+            Response::Multi(vec![Response::Int(1), Response::Data(b"x".to_vec())]),
+            Response::Map(vec![(b"f".to_vec(), b"v".to_vec())]),
+            Response::Push(vec![Response::Data(b"message".to_vec())]),
+        ];
+        for response in responses {
+            let encoded = response.serialize();
+            assert_eq!(response, Response::deserialize(&encoded).unwrap());
+            for len in 0..encoded.len() {
+                assert!(Response::deserialize(&encoded[..len]).is_err(), "{response:?} cut to {len} bytes");
+            }
+        }
+    }
+}
@@ -1,9 +1,16 @@
-use rusty_redis::RedisServer;
+use std::process;
+
+use rusty_redis::{init_logging, RedisServer, ServerConfig};
 
 #[tokio::main]
 async fn main() {
-    let host = String::from("0.0.0.0");
-    let port = 8081u32;
-    RedisServer::new(host, port).start_server().await;
+    let config = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            process::exit(1);
+        }
+    };
+    init_logging(config.loglevel);
+    RedisServer::from_config(config).start_server().await;
 }
-
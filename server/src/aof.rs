@@ -0,0 +1,433 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{error, info, warn};
+use tokio::sync::RwLock;
+use tokio::time::sleep;
+
+use rusty_redis_core::command::{Command, Expiry, SetOptions};
+use rusty_redis_core::frame::{encode_frame, FRAME_HEADER_LEN};
+
+use super::snapshot::SnapshotEntry;
+use super::value::Value;
+use super::store::{DataStore, SetOp};
+
+/// When appended commands are flushed from the OS page cache to disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// fsync after every write, slowest but loses nothing.
+    Always,
+    /// fsync once per second from a background task, losing at most a second of writes.
+    EverySec,
+    /// Leave flushing up to the OS.
+    No,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("invalid fsync policy '{}'", s))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AofConfig {
+    pub path: PathBuf,
+    pub fsync: FsyncPolicy,
+}
+
+impl Default for AofConfig {
+    fn default() -> Self {
+        AofConfig { path: PathBuf::from("appendonly.aof"), fsync: FsyncPolicy::EverySec }
+    }
+}
+
+/// Write-ahead log of every command that modified the DataStore.
+///
+/// Each record is a frame holding the time it was logged followed by the encoded command,
+/// [Len][LoggedAt][Command]. The timestamp lets replay account for TTLs that
+/// ran down while the server was offline.
+#[derive(Debug)]
+pub struct AppendOnlyFile {
+    config: AofConfig,
+    file: File,
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+impl AppendOnlyFile {
+    pub fn open(config: AofConfig) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&config.path)?;
+        Ok(AppendOnlyFile { config, file, rewrite_buffer: None })
+    }
+
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        self.config.fsync
+    }
+
+    pub fn set_fsync_policy(&mut self, fsync: FsyncPolicy) {
+        self.config.fsync = fsync;
+    }
+
+    pub fn append(&mut self, cmd: &Command) -> io::Result<()> {
+        let record = encode_record(now_millis(), cmd);
+        self.file.write_all(&record)?;
+        if self.config.fsync == FsyncPolicy::Always {
+            self.file.sync_data()?;
+        }
+        if let Some(buffer) = self.rewrite_buffer.as_mut() {
+            buffer.extend_from_slice(&record);
+        }
+        Ok(())
+    }
+
+    /// A second handle to the log, so it can be fsynced without holding the DataStore lock.
+    pub fn sync_handle(&self) -> io::Result<File> {
+        self.file.try_clone()
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.rewrite_buffer.is_some()
+    }
+
+    /// Begin buffering appended records, they are replayed onto the rewritten log once it is complete.
+    pub fn start_rewrite(&mut self) -> io::Result<PathBuf> {
+        if self.rewrite_in_progress() {
+            return Err(Error::new(ErrorKind::WouldBlock, "Background append only file rewriting already in progress"));
+        }
+        self.rewrite_buffer = Some(vec![]);
+        Ok(rewrite_path(&self.config.path))
+    }
+
+    /// Append everything logged during the rewrite to the new log and swap it into place.
+    pub fn finish_rewrite(&mut self, rewritten: &Path) -> io::Result<()> {
+        let buffer = self.rewrite_buffer.take().unwrap_or_default();
+        let mut file = OpenOptions::new().append(true).open(rewritten)?;
+        file.write_all(&buffer)?;
+        file.sync_all()?;
+        fs::rename(rewritten, &self.config.path)?;
+        self.file = file;
+        Ok(())
+    }
+
+    pub fn abort_rewrite(&mut self) {
+        self.rewrite_buffer = None;
+    }
+}
+
+/// Compact the log from the current DataStore contents without blocking the request path.
+///
+/// Only collecting the entries happens under the lock, commands arriving while the
+/// new log is written are buffered and appended to it before it replaces the old one.
+pub async fn bgrewrite(store: Arc<RwLock<DataStore>>) -> io::Result<()> {
+    let (rewritten, entries) = {
+        let mut store_rw = store.write().await;
+        let entries = store_rw.entries();
+        let aof = store_rw.aof_mut()
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Append only file is disabled"))?;
+        (aof.start_rewrite()?, entries)
+    };
+
+    tokio::spawn(async move {
+        let path = rewritten.clone();
+        let written = tokio::task::spawn_blocking(move || write_rewrite(&path, &entries))
+            .await
+            .map_err(Error::other)
+            .and_then(|r| r);
+
+        let mut store_rw = store.write().await;
+        let Some(aof) = store_rw.aof_mut() else { return };
+        match written.and_then(|_| aof.finish_rewrite(&rewritten)) {
+            Ok(_) => info!("Background append only file rewriting terminated with success"),
+            Err(e) => {
+                error!("Background append only file rewriting failed: {}", e);
+                aof.abort_rewrite();
+                let _ = fs::remove_file(&rewritten);
+            },
+        }
+    });
+    Ok(())
+}
+
+/// Fsync the log once a second when using the everysec policy.
+/// The fsync itself runs on a blocking thread against a cloned handle, outside of the DataStore lock.
+pub fn spawn_fsync_task(store: Arc<RwLock<DataStore>>) {
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_millis(1000)).await;
+            let handle = match store.read().await.aof() {
+                Some(aof) if aof.fsync_policy() == FsyncPolicy::EverySec => aof.sync_handle(),
+                _ => continue,
+            };
+            let synced = match handle {
+                Ok(file) => tokio::task::spawn_blocking(move || file.sync_data())
+                    .await
+                    .map_err(Error::other)
+                    .and_then(|r| r),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = synced {
+                error!("Failed to fsync append only file: {}", e);
+            }
+        }
+    });
+}
+
+/// Write a compacted log to `path`, holding a single command per key to rebuild its value
+/// followed by a PEXPIREAT for keys that expire.
+pub fn write_rewrite(path: &Path, entries: &[SnapshotEntry]) -> io::Result<()> {
+    let now = now_millis();
+    let mut file = File::create(path)?;
+    let mut buffer = vec![];
+    for entry in entries {
+        if entry.expire_at.is_some_and(|expire_at| expire_at <= now) {
+            continue;
+        }
+        let cmd = match &*entry.value {
+            Value::String(data) => Command::SET(entry.key.clone(), data.clone(), SetOptions::new()),
+            Value::List(list) => Command::RPUSH(entry.key.clone(), list.iter().cloned().collect()),
+            Value::Hash(hash) => Command::HSET(entry.key.clone(), hash.iter().map(|(f, v)| (f.clone(), v.clone())).collect()),
+            Value::Set(set) => Command::SADD(entry.key.clone(), set.iter().cloned().collect()),
+            Value::SortedSet(zset) => Command::ZADD(entry.key.clone(), zset.iter().map(|(member, score)| (score, member.to_vec())).collect()),
+        };
+        buffer.extend_from_slice(&encode_record(now, &cmd));
+        if let Some(expire_at) = entry.expire_at {
+            buffer.extend_from_slice(&encode_record(now, &Command::PEXPIREAT(entry.key.clone(), expire_at as i64)));
+        }
+    }
+    file.write_all(&buffer)?;
+    file.sync_all()
+}
+
+/// Apply every command in the log to the store, returning how many were replayed.
+///
+/// A record cut short by a crash mid-write is dropped and the file truncated
+/// to the last complete record, so new appends start from a clean boundary.
+pub fn replay(path: &Path, store: &mut DataStore) -> io::Result<usize> {
+    let mut bytes = vec![];
+    match File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut bytes)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut cur = 0;
+    let mut replayed = 0;
+    while let Some((logged_at, cmd, used)) = decode_record(&bytes[cur..])? {
+        cur += used;
+        replayed += 1;
+        match cmd {
+            Command::SET(key, value, mut options) => {
+                // A relative TTL counts from when the record was logged, not from now.
+                if let Some(Expiry::In(ttl)) = options.expiry {
+                    options.expiry = Some(Expiry::At(logged_at.saturating_add(ttl)));
+                }
+                store.insert_with(&key, value, &options)?;
+            },
+            Command::DELETE(keys) => keys.iter().for_each(|key| { store.delete(key); }),
+            Command::MSET(pairs) => store.mset(pairs),
+            Command::LPUSH(key, values) => { store.push(&key, values, true)?; },
+            Command::RPUSH(key, values) => { store.push(&key, values, false)?; },
+            Command::LPOP(key, count) => { store.pop(&key, count, true)?; },
+            Command::RPOP(key, count) => { store.pop(&key, count, false)?; },
+            Command::LTRIM(key, start, stop) => store.ltrim(&key, start, stop)?,
+            Command::HSET(key, pairs) => { store.hset(&key, pairs)?; },
+            Command::HDEL(key, fields) => { store.hdel(&key, fields)?; },
+            Command::HINCRBY(key, field, increment) => { store.hincrby(&key, &field, increment)?; },
+            Command::SADD(key, members) => { store.sadd(&key, members)?; },
+            Command::SREM(key, members) => { store.srem(&key, members)?; },
+            Command::SINTERSTORE(destination, keys) => { store.set_op_store(SetOp::Inter, &destination, &keys)?; },
+            Command::SUNIONSTORE(destination, keys) => { store.set_op_store(SetOp::Union, &destination, &keys)?; },
+            Command::SDIFFSTORE(destination, keys) => { store.set_op_store(SetOp::Diff, &destination, &keys)?; },
+            Command::ZADD(key, entries) => { store.zadd(&key, entries)?; },
+            Command::ZREM(key, members) => { store.zrem(&key, members)?; },
+            Command::ZINCRBY(key, increment, member) => { store.zincrby(&key, increment, &member)?; },
+            Command::INCRBY(key, increment) => { store.incr_by(&key, increment)?; },
+            Command::INCRBYFLOAT(key, increment) => { store.incr_by_float(&key, increment)?; },
+            Command::PEXPIREAT(key, at) => { store.expire_at(&key, at); },
+            Command::PERSIST(key) => { store.persist(&key); },
+            cmd => return Err(Error::new(ErrorKind::InvalidData, format!("Unexpected command in append only file: {:?}", cmd))),
+        }
+    }
+    if cur < bytes.len() {
+        warn!("Append only file has {} trailing bytes of a partial record, truncating", bytes.len() - cur);
+        OpenOptions::new().write(true).open(path)?.set_len(cur as u64)?;
+    }
+    Ok(replayed)
+}
+
+fn encode_record(logged_at: u128, cmd: &Command) -> Vec<u8> {
+    let mut payload = (logged_at as u64).to_le_bytes().to_vec();
+    payload.extend_from_slice(&cmd.encode());
+    encode_frame(&payload)
+}
+
+/// Returns None once the remaining bytes do not hold a complete record.
+fn decode_record(bytes: &[u8]) -> io::Result<Option<(u64, Command, usize)>> {
+    let Some(len_buf) = bytes.get(0..FRAME_HEADER_LEN) else {
+        return Ok(None);
+    };
+    let len = u32::from_be_bytes(len_buf.try_into().unwrap()) as usize;
+    let Some(payload) = bytes.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len) else {
+        return Ok(None);
+    };
+    let logged_at = payload.get(0..8)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Append only file record is missing its timestamp"))?;
+    let logged_at = u64::from_le_bytes(logged_at.try_into().unwrap());
+    let cmd = Command::parse(Command::decode(&payload[8..])?)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    Ok(Some((logged_at, cmd, FRAME_HEADER_LEN + len)))
+}
+
+fn rewrite_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".rewrite");
+    PathBuf::from(tmp_path)
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rusty-redis-test-{}-{}.aof", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_fsync_policy_from_str() {
+        assert_eq!(FsyncPolicy::Always, "always".parse().unwrap());
+        assert_eq!(FsyncPolicy::EverySec, "EVERYSEC".parse().unwrap());
+        assert_eq!(FsyncPolicy::No, "no".parse().unwrap());
+        assert!("sometimes".parse::<FsyncPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let path = temp_path("replay");
+        let mut aof = AppendOnlyFile::open(AofConfig { path: path.clone(), fsync: FsyncPolicy::Always }).unwrap();
+        aof.append(&Command::SET("a".to_string(), b"1".to_vec(), SetOptions::new())).unwrap();
+        aof.append(&Command::SET("b".to_string(), b"2".to_vec(), SetOptions::ttl(60_000))).unwrap();
+        aof.append(&Command::SET("a".to_string(), b"3".to_vec(), SetOptions::new())).unwrap();
+        aof.append(&Command::DELETE(vec!["b".to_string()])).unwrap();
+        aof.append(&Command::SET("c".to_string(), b"4".to_vec(), SetOptions::ttl(60_000))).unwrap();
+        aof.append(&Command::RPUSH("l".to_string(), vec![b"x".to_vec(), b"y".to_vec(), b"z".to_vec()])).unwrap();
+        aof.append(&Command::LPOP("l".to_string(), None)).unwrap();
+        aof.append(&Command::HSET("h".to_string(), vec![(b"f".to_vec(), b"1".to_vec()), (b"g".to_vec(), b"2".to_vec())])).unwrap();
+        aof.append(&Command::HINCRBY("h".to_string(), b"f".to_vec(), 9)).unwrap();
+        aof.append(&Command::HDEL("h".to_string(), vec![b"g".to_vec()])).unwrap();
+        aof.append(&Command::SADD("s1".to_string(), vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])).unwrap();
+        aof.append(&Command::SADD("s2".to_string(), vec![b"b".to_vec()])).unwrap();
+        aof.append(&Command::SDIFFSTORE("s3".to_string(), vec!["s1".to_string(), "s2".to_string()])).unwrap();
+        aof.append(&Command::SREM("s3".to_string(), vec![b"a".to_vec()])).unwrap();
+        aof.append(&Command::ZADD("z".to_string(), vec![(1.0, b"a".to_vec()), (2.0, b"b".to_vec()), (3.0, b"c".to_vec())])).unwrap();
+        aof.append(&Command::ZINCRBY("z".to_string(), 0.5, b"c".to_vec())).unwrap();
+        aof.append(&Command::ZREM("z".to_string(), vec![b"a".to_vec()])).unwrap();
+        aof.append(&Command::INCRBY("a".to_string(), 4)).unwrap();
+        aof.append(&Command::INCRBYFLOAT("n".to_string(), 1.5)).unwrap();
+        aof.append(&Command::PERSIST("c".to_string())).unwrap();
+
+        let mut store = DataStore::new(4);
+        assert_eq!(20, replay(&path, &mut store).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(b"7".to_vec(), store.get("a").unwrap().unwrap());
+        assert_eq!(b"1.5".to_vec(), store.get("n").unwrap().unwrap());
+        assert!(store.get("b").unwrap().is_none());
+        assert_eq!(b"4".to_vec(), store.get("c").unwrap().unwrap());
+        assert_eq!(Some(None), store.pttl("c"));
+        assert_eq!(vec![b"y".to_vec(), b"z".to_vec()], store.lrange("l", 0, -1).unwrap());
+        assert_eq!(vec![(b"f".to_vec(), b"10".to_vec())], store.hgetall("h").unwrap());
+        assert_eq!(vec![b"c".to_vec()], store.smembers("s3").unwrap());
+        assert_eq!(vec![(b"b".to_vec(), 2.0), (b"c".to_vec(), 3.5)], store.zrange("z", 0, -1).unwrap());
+    }
+
+    #[test]
+    fn test_incr_by_float_logs_result() {
+        let path = temp_path("incrbyfloat");
+        let mut store = DataStore::new(4);
+        store.attach_aof(AppendOnlyFile::open(AofConfig { path: path.clone(), fsync: FsyncPolicy::Always }).unwrap());
+        store.insert("n", b"0.1".to_vec(), 60_000);
+        store.incr_by_float("n", 0.2).unwrap();
+
+        let mut replayed = DataStore::new(4);
+        assert_eq!(2, replay(&path, &mut replayed).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(store.get("n").unwrap(), replayed.get("n").unwrap());
+        assert!(replayed.pttl("n").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_replay_drops_elapsed_ttl() {
+        let path = temp_path("elapsed");
+        let mut record = encode_record(now_millis() - 10_000, &Command::SET("gone".to_string(), b"1".to_vec(), SetOptions::ttl(5_000)));
+        record.extend_from_slice(&encode_record(now_millis() - 10_000, &Command::SET("kept".to_string(), b"2".to_vec(), SetOptions::ttl(50_000))));
+        fs::write(&path, record).unwrap();
+
+        let mut store = DataStore::new(4);
+        assert_eq!(2, replay(&path, &mut store).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert!(store.get("gone").unwrap().is_none());
+        assert_eq!(b"2".to_vec(), store.get("kept").unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_replay_truncated_tail() {
+        let path = temp_path("truncated");
+        let complete = encode_record(now_millis(), &Command::SET("a".to_string(), b"1".to_vec(), SetOptions::new()));
+        let mut bytes = complete.clone();
+        let partial = encode_record(now_millis(), &Command::SET("b".to_string(), b"2".to_vec(), SetOptions::new()));
+        bytes.extend_from_slice(&partial[..partial.len() - 3]);
+        fs::write(&path, bytes).unwrap();
+
+        let mut store = DataStore::new(4);
+        assert_eq!(1, replay(&path, &mut store).unwrap());
+        assert_eq!(complete.len() as u64, fs::metadata(&path).unwrap().len());
+        fs::remove_file(&path).unwrap();
+
+        assert!(store.get("b").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let path = temp_path("rewrite");
+        let mut aof = AppendOnlyFile::open(AofConfig { path: path.clone(), fsync: FsyncPolicy::No }).unwrap();
+        for i in 0..10 {
+            aof.append(&Command::SET("a".to_string(), i.to_string().into_bytes(), SetOptions::new())).unwrap();
+        }
+
+        let rewritten = aof.start_rewrite().unwrap();
+        assert!(aof.start_rewrite().is_err());
+        let entries = vec![
+            SnapshotEntry { key: "a".to_string(), value: Arc::new(Value::String(b"9".to_vec())), expire_at: None },
+            SnapshotEntry { key: "l".to_string(), value: Arc::new(Value::List([b"x".to_vec(), b"y".to_vec()].into())), expire_at: Some(now_millis() + 60_000) },
+        ];
+        write_rewrite(&rewritten, &entries).unwrap();
+        aof.append(&Command::SET("b".to_string(), b"during".to_vec(), SetOptions::new())).unwrap();
+        aof.finish_rewrite(&rewritten).unwrap();
+        aof.append(&Command::SET("c".to_string(), b"after".to_vec(), SetOptions::new())).unwrap();
+
+        let mut store = DataStore::new(4);
+        assert_eq!(5, replay(&path, &mut store).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(vec![b"x".to_vec(), b"y".to_vec()], store.lrange("l", 0, -1).unwrap());
+        assert!(store.pttl("l").unwrap().is_some());
+        assert_eq!(b"9".to_vec(), store.get("a").unwrap().unwrap());
+        assert_eq!(b"during".to_vec(), store.get("b").unwrap().unwrap());
+        assert_eq!(b"after".to_vec(), store.get("c").unwrap().unwrap());
+    }
+}
@@ -0,0 +1,482 @@
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::LevelFilter;
+use rusty_redis_core::frame::DEFAULT_MAX_FRAME_SIZE;
+use rusty_redis_core::pattern::glob_match;
+
+use super::aof::{AofConfig, FsyncPolicy};
+use super::pubsub::KeyspaceEvents;
+use super::snapshot::SaveRule;
+use super::store::EvictionPolicy;
+
+/// Verbosity of the server log, using the same names as Redis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl LogLevel {
+    pub fn filter(&self) -> LevelFilter {
+        match self {
+            LogLevel::Debug => LevelFilter::Trace,
+            LogLevel::Verbose => LevelFilter::Debug,
+            LogLevel::Notice => LevelFilter::Info,
+            LogLevel::Warning => LevelFilter::Warn,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "verbose" => Ok(LogLevel::Verbose),
+            "notice" => Ok(LogLevel::Notice),
+            "warning" => Ok(LogLevel::Warning),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("invalid log level '{}'", s))),
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Server settings, read from a redis.conf style file and overridden by command line flags.
+///
+/// ```text
+/// # comment
+/// port 8080
+/// maxmemory 100mb
+/// save 3600 1 300 100
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u32,
+    /// Initial number of HTable buckets, must be a power of 2.
+    pub table_size: usize,
+    /// Memory limit in bytes, 0 for no limit.
+    pub maxmemory: u64,
+    /// Which keys are evicted once maxmemory is reached.
+    pub maxmemory_policy: EvictionPolicy,
+    pub max_frame_size: usize,
+    /// Directory snapshots and the append only file are written to.
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    pub save_rules: Vec<SaveRule>,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    pub loglevel: LogLevel,
+    /// Milliseconds between sweeps for expired keys.
+    pub expire_interval: u64,
+    /// Which keyspace notifications to publish, none by default.
+    pub notify_keyspace_events: KeyspaceEvents,
+    /// Milliseconds a script may run before it is stopped, 0 for no limit.
+    pub script_time_limit: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: String::from("0.0.0.0"),
+            port: 8080,
+            table_size: 64,
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            dir: PathBuf::from("."),
+            dbfilename: String::from("dump.rrdb"),
+            save_rules: vec![
+                SaveRule { seconds: 3600, changes: 1 },
+                SaveRule { seconds: 300, changes: 100 },
+                SaveRule { seconds: 60, changes: 10000 },
+            ],
+            appendonly: false,
+            appendfilename: String::from("appendonly.aof"),
+            appendfsync: FsyncPolicy::EverySec,
+            loglevel: LogLevel::Notice,
+            expire_interval: 1000,
+            notify_keyspace_events: KeyspaceEvents::default(),
+            script_time_limit: 5000,
+        }
+    }
+}
+
+/// Every setting name, in the order CONFIG GET reports them.
+const SETTINGS: &[&str] = &[
+    "bind", "port", "table-size", "maxmemory", "maxmemory-policy", "max-frame-size", "dir", "dbfilename", "save",
+    "appendonly", "appendfilename", "appendfsync", "loglevel", "expire-interval", "notify-keyspace-events",
+    "script-time-limit",
+];
+
+/// Settings that CONFIG SET may change while the server is running.
+const RUNTIME_SETTINGS: &[&str] = &[
+    "maxmemory", "maxmemory-policy", "save", "appendfsync", "loglevel", "expire-interval", "notify-keyspace-events",
+    "script-time-limit",
+];
+
+impl ServerConfig {
+    /// Build the configuration from command line arguments, in the style of redis-server.
+    ///
+    /// `server [/path/to/redis.conf] [--port 7777] [--save 60 1000 ...]`
+    pub fn from_args<I>(args: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut args = args.into_iter().peekable();
+        let mut config = match args.next_if(|arg| !arg.starts_with("--")) {
+            Some(path) => ServerConfig::from_file(Path::new(&path))?,
+            None => ServerConfig::default(),
+        };
+        while let Some(flag) = args.next() {
+            let name = flag.strip_prefix("--")
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unexpected argument '{}'", flag)))?;
+            let mut values = vec![];
+            while let Some(value) = args.next_if(|arg| !arg.starts_with("--")) {
+                values.push(value);
+            }
+            config.apply(name, &values)?;
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::new(e.kind(), format!("failed to read config file {}: {}", path.display(), e)))?;
+        ServerConfig::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let mut config = ServerConfig::default();
+        // save lines accumulate, the first one replaces the defaults.
+        let mut saw_save = false;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words = split_line(line)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("line {}: {}", i + 1, e)))?;
+            let (name, values) = words.split_first().unwrap();
+            let name = name.to_ascii_lowercase();
+            if name == "save" && saw_save {
+                let rules = parse_save_rules(values)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, format!("line {}: {}", i + 1, e)))?;
+                config.save_rules.extend(rules);
+                continue;
+            }
+            saw_save |= name == "save";
+            config.apply(&name, values)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("line {}: {}", i + 1, e)))?;
+        }
+        Ok(config)
+    }
+
+    /// Change a single setting at runtime, rejecting those that only take effect on startup.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let name = name.to_ascii_lowercase();
+        if !SETTINGS.contains(&name.as_str()) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Unknown option or number of arguments for CONFIG SET - '{}'", name)));
+        }
+        if !RUNTIME_SETTINGS.contains(&name.as_str()) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("CONFIG SET failed (possibly related to argument '{}') - can't set immutable config", name)));
+        }
+        let values = split_line(value)?;
+        self.apply(&name, &values)
+    }
+
+    /// Name and current value of every setting matching the glob `pattern`.
+    pub fn get(&self, pattern: &str) -> Vec<(String, String)> {
+        let pattern = pattern.to_ascii_lowercase();
+        SETTINGS.iter()
+            .filter(|name| glob_match(pattern.as_bytes(), name.as_bytes()))
+            .map(|name| (name.to_string(), self.value(name)))
+            .collect()
+    }
+
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// Settings for the append only file, None when it is disabled.
+    pub fn aof_config(&self) -> Option<AofConfig> {
+        self.appendonly.then(|| AofConfig { path: self.dir.join(&self.appendfilename), fsync: self.appendfsync })
+    }
+
+    fn apply(&mut self, name: &str, values: &[String]) -> Result<(), Error> {
+        let single = || match values {
+            [value] => Ok(value.as_str()),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("wrong number of arguments for '{}'", name))),
+        };
+        match name {
+            "bind" => self.bind = single()?.to_string(),
+            "port" => self.port = parse_number(single()?)?,
+            "table-size" => {
+                let size: usize = parse_number(single()?)?;
+                if !size.is_power_of_two() {
+                    return Err(Error::new(ErrorKind::InvalidInput, "table-size must be a power of 2"));
+                }
+                self.table_size = size;
+            },
+            "maxmemory" => self.maxmemory = parse_memory(single()?)?,
+            "maxmemory-policy" => self.maxmemory_policy = single()?.parse()?,
+            "max-frame-size" => self.max_frame_size = parse_memory(single()?)? as usize,
+            "dir" => self.dir = PathBuf::from(single()?),
+            "dbfilename" => self.dbfilename = single()?.to_string(),
+            "save" => self.save_rules = parse_save_rules(values)?,
+            "appendonly" => self.appendonly = parse_bool(single()?)?,
+            "appendfilename" => self.appendfilename = single()?.to_string(),
+            "appendfsync" => self.appendfsync = single()?.parse()?,
+            "loglevel" => self.loglevel = single()?.parse()?,
+            "expire-interval" => {
+                let interval: u64 = parse_number(single()?)?;
+                if interval == 0 {
+                    return Err(Error::new(ErrorKind::InvalidInput, "expire-interval must be greater than 0"));
+                }
+                self.expire_interval = interval;
+            },
+            // An empty value, `notify-keyspace-events ""`, switches notifications off.
+            "notify-keyspace-events" => match values {
+                [] => self.notify_keyspace_events = KeyspaceEvents::default(),
+                _ => self.notify_keyspace_events = single()?.parse()?,
+            },
+            "script-time-limit" => self.script_time_limit = parse_number(single()?)?,
+            _ => return Err(Error::new(ErrorKind::InvalidInput, format!("unknown setting '{}'", name))),
+        }
+        Ok(())
+    }
+
+    fn value(&self, name: &str) -> String {
+        match name {
+            "bind" => self.bind.clone(),
+            "port" => self.port.to_string(),
+            "table-size" => self.table_size.to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.to_string(),
+            "max-frame-size" => self.max_frame_size.to_string(),
+            "dir" => self.dir.display().to_string(),
+            "dbfilename" => self.dbfilename.clone(),
+            "save" => self.save_rules.iter()
+                .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+                .collect::<Vec<String>>()
+                .join(" "),
+            "appendonly" => String::from(if self.appendonly { "yes" } else { "no" }),
+            "appendfilename" => self.appendfilename.clone(),
+            "appendfsync" => format!("{:?}", self.appendfsync).to_ascii_lowercase(),
+            "loglevel" => self.loglevel.to_string(),
+            "expire-interval" => self.expire_interval.to_string(),
+            "notify-keyspace-events" => self.notify_keyspace_events.to_string(),
+            "script-time-limit" => self.script_time_limit.to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Split a line into words, honouring double quoted values such as `save ""`.
+fn split_line(line: &str) -> Result<Vec<String>, Error> {
+    let mut words = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => continue,
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => return Err(Error::new(ErrorKind::InvalidData, "unbalanced quotes")),
+                    }
+                }
+                words.push(word);
+            },
+            c => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+                words.push(word);
+            },
+        }
+    }
+    Ok(words)
+}
+
+fn parse_number<N: FromStr>(value: &str) -> Result<N, Error> {
+    value.parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("'{}' is not a valid number", value)))
+}
+
+fn parse_bool(value: &str) -> Result<bool, Error> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("'{}' must be yes or no", value))),
+    }
+}
+
+/// Parse a byte count with an optional unit, e.g. 1024, 100mb or 2gb.
+fn parse_memory(value: &str) -> Result<u64, Error> {
+    let lower = value.to_ascii_lowercase();
+    let units: [(&str, u64); 6] = [
+        ("kb", 1024), ("mb", 1024 * 1024), ("gb", 1024 * 1024 * 1024),
+        ("k", 1000), ("m", 1000 * 1000), ("g", 1000 * 1000 * 1000),
+    ];
+    let (number, multiplier) = units.iter()
+        .find_map(|(suffix, multiplier)| lower.strip_suffix(suffix).map(|n| (n, *multiplier)))
+        .unwrap_or((lower.as_str(), 1));
+    let number: u64 = parse_number(number)?;
+    number.checked_mul(multiplier)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("'{}' is out of range", value)))
+}
+
+/// Parse `seconds changes` pairs, an empty value disables saving.
+fn parse_save_rules(values: &[String]) -> Result<Vec<SaveRule>, Error> {
+    let values: Vec<&String> = values.iter().filter(|v| !v.is_empty()).collect();
+    if !values.len().is_multiple_of(2) {
+        return Err(Error::new(ErrorKind::InvalidInput, "save expects pairs of <seconds> <changes>"));
+    }
+    values.chunks(2)
+        .map(|pair| Ok(SaveRule { seconds: parse_number(pair[0])?, changes: parse_number(pair[1])? }))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = ServerConfig::parse(r#"
+            # A comment
+            bind 127.0.0.1
+            port 6380
+            table-size 1024
+            maxmemory 100mb
+            maxmemory-policy allkeys-lru
+            dir /var/lib/rusty-redis
+            dbfilename "my dump.rrdb"
+            save 900 1
+            save 60 10000
+            appendonly yes
+            appendfsync always
+            loglevel warning
+            expire-interval 100
+            notify-keyspace-events Ex
+            script-time-limit 250
+        "#).unwrap();
+
+        assert_eq!("127.0.0.1", config.bind);
+        assert_eq!(6380, config.port);
+        assert_eq!(1024, config.table_size);
+        assert_eq!(100 * 1024 * 1024, config.maxmemory);
+        assert_eq!(EvictionPolicy::AllKeysLru, config.maxmemory_policy);
+        assert_eq!(PathBuf::from("/var/lib/rusty-redis/my dump.rrdb"), config.snapshot_path());
+        assert_eq!(vec![SaveRule { seconds: 900, changes: 1 }, SaveRule { seconds: 60, changes: 10000 }], config.save_rules);
+        assert_eq!(Some(FsyncPolicy::Always), config.aof_config().map(|aof| aof.fsync));
+        assert_eq!(LogLevel::Warning, config.loglevel);
+        assert_eq!(100, config.expire_interval);
+        assert_eq!("xE", config.notify_keyspace_events.to_string());
+        assert_eq!(250, config.script_time_limit);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(ServerConfig::parse("port eighty").is_err());
+        assert!(ServerConfig::parse("table-size 100").is_err());
+        assert!(ServerConfig::parse("save 60").is_err());
+        assert!(ServerConfig::parse("appendonly maybe").is_err());
+        assert!(ServerConfig::parse("dbfilename \"unterminated").is_err());
+        assert!(ServerConfig::parse("nonsense 1").is_err());
+        assert!(ServerConfig::parse("notify-keyspace-events Eq").is_err());
+        assert!(ServerConfig::parse("maxmemory-policy lru").is_err());
+    }
+
+    #[test]
+    fn test_disable_save() {
+        let config = ServerConfig::parse("save \"\"").unwrap();
+        assert!(config.save_rules.is_empty());
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = ServerConfig::from_args(args(&["--port", "7777", "--save", "60", "5", "--appendonly", "yes"])).unwrap();
+        assert_eq!(7777, config.port);
+        assert_eq!(vec![SaveRule { seconds: 60, changes: 5 }], config.save_rules);
+        assert!(config.appendonly);
+
+        assert!(ServerConfig::from_args(args(&["--port"])).is_err());
+        assert!(ServerConfig::from_args(args(&["/does/not/exist.conf"])).is_err());
+    }
+
+    #[test]
+    fn test_args_override_file() {
+        let path = std::env::temp_dir().join(format!("rusty-redis-test-{}.conf", std::process::id()));
+        fs::write(&path, "port 6000\nloglevel debug\n").unwrap();
+        let config = ServerConfig::from_args(args(&[path.to_str().unwrap(), "--port", "6001"])).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(6001, config.port);
+        assert_eq!(LogLevel::Debug, config.loglevel);
+    }
+
+    #[test]
+    fn test_get_set() {
+        let mut config = ServerConfig::default();
+        config.set("maxmemory", "1gb").unwrap();
+        config.set("save", "10 1 20 2").unwrap();
+        config.set("LOGLEVEL", "verbose").unwrap();
+
+        assert_eq!(vec![(String::from("maxmemory"), String::from("1073741824"))], config.get("maxmemory"));
+        assert_eq!(vec![(String::from("save"), String::from("10 1 20 2"))], config.get("save"));
+        assert_eq!(vec![(String::from("loglevel"), String::from("verbose"))], config.get("loglevel"));
+        assert_eq!(SETTINGS.len(), config.get("*").len());
+        assert!(config.get("nope").is_empty());
+        let append: Vec<String> = config.get("append*").into_iter().map(|(name, _)| name).collect();
+        assert_eq!(vec!["appendonly", "appendfilename", "appendfsync"], append);
+
+        assert!(config.set("port", "1234").is_err());
+        assert!(config.set("nope", "1").is_err());
+        assert!(config.set("expire-interval", "0").is_err());
+
+        config.set("notify-keyspace-events", "KA").unwrap();
+        assert_eq!(vec![(String::from("notify-keyspace-events"), String::from("AK"))], config.get("notify-*"));
+        config.set("notify-keyspace-events", "\"\"").unwrap();
+        assert_eq!(KeyspaceEvents::default(), config.notify_keyspace_events);
+    }
+
+    #[test]
+    fn test_get_glob() {
+        let config = ServerConfig::default();
+        let names = |pattern: &str| -> Vec<String> { config.get(pattern).into_iter().map(|(name, _)| name).collect() };
+        assert_eq!(vec!["maxmemory", "maxmemory-policy"], names("MAXMEMORY*"));
+        assert_eq!(vec!["port"], names("p?rt"));
+        assert_eq!(vec!["dir", "dbfilename"], names("d[ib]*"));
+        assert!(names("[^a-z]*").is_empty());
+        assert!(names("port?").is_empty());
+    }
+}
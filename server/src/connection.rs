@@ -3,22 +3,42 @@ use std::io::ErrorKind;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt, self};
 
+use rusty_redis_core::command::Command;
+use rusty_redis_core::frame::FrameReader;
+use rusty_redis_core::protocol::Protocol;
 use rusty_redis_core::response::Response;
-use rusty_redis_core::BUF_MAX;
+
+/// Queued responses are written out early once they reach this size.
+const MAX_PENDING_BYTES: usize = 64 * 1024;
 
 pub struct RedisServerConnection<T>
 where
     T: AsyncReadExt + AsyncWriteExt + Unpin,
 {
     stream: T,
+    reader: FrameReader,
+    protocol: Option<Protocol>,
+    pending: Vec<u8>,
+    /// Responses held back while a transaction runs, see `capture_responses`.
+    captured: Option<Vec<Response>>,
 }
 
 impl<T> RedisServerConnection<T>
 where
     T: AsyncReadExt + AsyncWriteExt + Unpin,
 {
-    pub fn new(stream: T) -> Self {
-        RedisServerConnection { stream }
+    pub fn new(stream: T, max_frame_size: usize) -> Self {
+        RedisServerConnection { stream, reader: FrameReader::new(max_frame_size), protocol: None, pending: vec![], captured: None }
+    }
+
+    /// Protocol spoken by the client, Native until the first byte has been read.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol.unwrap_or(Protocol::Native)
+    }
+
+    /// Switch between RESP versions, as requested by HELLO.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = Some(protocol);
     }
 }
 
@@ -26,42 +46,86 @@ impl<T> RedisServerConnection<T>
 where
     T: AsyncReadExt + AsyncWriteExt + Unpin,
 {
+    /// Read the next command sent by the client.
+    ///
+    /// Pipelined commands already sitting in the read buffer are returned without touching
+    /// the stream. Queued responses are only flushed once the buffer runs dry, so a whole
+    /// pipeline is answered with a single write.
     pub async fn read_command(&mut self) -> io::Result<VecDeque<Vec<u8>>> {
-        let mut buffer = [0u8; BUF_MAX];
-        let b = self.stream.read(&mut buffer).await?;
-        if b == 0 {
-            return Err(io::Error::new(ErrorKind::UnexpectedEof, "Stream read 0 bytes"))
+        if let Some(cmd) = self.try_command()? {
+            return Ok(cmd);
         }
+        self.flush().await?;
 
-        let len_buf: &[u8; 4] = &buffer[0..4].try_into().unwrap();
-        let cmd_len = u32::from_le_bytes(*len_buf) as usize;
-
-        // [NumV][LNV][V][LNV][V][LNV][V]
-        // [cmd_len][str_len][st][str_len][st][str_len][st]
-        let mut strs = VecDeque::new();
-        let mut remaining = &buffer[4..];
-        for _ in 0..cmd_len {
-            let str_len = u32::from_le_bytes(remaining[0..4].try_into().unwrap()) as usize;
-            // TODO Can we use slices instead of allocating a new vec for each command?
-            let st = remaining[4..str_len + 4].to_vec();
-            strs.push_back(st);
-            remaining = &remaining[str_len + 4..]
+        let protocol = match self.protocol {
+            Some(protocol) => protocol,
+            None => {
+                let protocol = Protocol::detect(self.reader.peek(&mut self.stream).await?);
+                self.protocol = Some(protocol);
+                protocol
+            },
+        };
+        if protocol.is_resp() {
+            let value = self.reader.read_resp(&mut self.stream).await?;
+            value.into_args().map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))
+        } else {
+            let frame = self.reader.read_frame(&mut self.stream).await?;
+            Command::decode(&frame)
         }
-        assert_eq!(cmd_len, strs.len());
+    }
+
+    fn try_command(&mut self) -> io::Result<Option<VecDeque<Vec<u8>>>> {
+        match self.protocol {
+            None => Ok(None),
+            Some(protocol) if protocol.is_resp() => match self.reader.try_resp()? {
+                Some(value) => value.into_args()
+                    .map(Some)
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string())),
+                None => Ok(None),
+            },
+            Some(_) => self.reader.try_frame()?.map(|frame| Command::decode(&frame)).transpose(),
+        }
+    }
+
+    /// Collect the responses written from now on instead of sending them,
+    /// so EXEC can reply with those of every queued command as one array.
+    pub fn capture_responses(&mut self) {
+        self.captured = Some(vec![]);
+    }
 
-        Ok(strs)
+    /// Stop capturing and hand back what was written since `capture_responses`.
+    pub fn take_captured(&mut self) -> Vec<Response> {
+        self.captured.take().unwrap_or_default()
     }
 
+    /// Queue a response, it is sent on the next `flush` or when the connection waits for input.
     pub async fn write_response(&mut self, response: Response) -> io::Result<()> {
-        self.stream.write_all(&response.serialize()).await?;
+        if let Some(captured) = self.captured.as_mut() {
+            captured.push(response);
+            return Ok(());
+        }
+        let encoded = self.protocol().encode_response(&response);
+        self.pending.extend_from_slice(&encoded);
+        if self.pending.len() >= MAX_PENDING_BYTES {
+            self.flush().await?;
+        }
         Ok(())
     }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            self.stream.write_all(&self.pending).await?;
+            self.pending.clear();
+        }
+        self.stream.flush().await
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::VecDeque;
-    use rusty_redis_core::command::Command;
+    use rusty_redis_core::command::SetOptions;
+    use rusty_redis_core::frame::{encode_frame, DEFAULT_MAX_FRAME_SIZE};
     use super::*;
     use tokio_test::io::Builder;
 
@@ -77,11 +141,85 @@ mod tests {
                 b"Hello Stream!".to_vec(),
                 5000u64.to_le_bytes().to_vec()
             ]);
-        handle.read(Command::SET("1234".to_string(), b"Hello Stream!".to_vec(), 5000u64).encode().as_slice());
+        handle.read(&encode_frame(&Command::SET("1234".to_string(), b"Hello Stream!".to_vec(), SetOptions::ttl(5000)).encode()));
 
-        let mut conn = RedisServerConnection::new(mock);
+        let mut conn = RedisServerConnection::new(mock, DEFAULT_MAX_FRAME_SIZE);
         let actual = conn.read_command().await.expect("Failed to read commands");
 
         assert_eq!(expected, actual);
     }
+
+    #[tokio::test]
+    async fn test_read_large_command() {
+        let mut builder = Builder::new();
+        let (mock, mut handle) = builder.build_with_handle();
+
+        let value = b"{\"blob\":\"".iter()
+            .chain([b'x'; 8192].iter())
+            .chain(b"\"}".iter())
+            .copied()
+            .collect::<Vec<u8>>();
+        let frame = encode_frame(&Command::SET("large".to_string(), value.clone(), SetOptions::new()).encode());
+        frame.chunks(1000).for_each(|chunk| { handle.read(chunk); });
+
+        let mut conn = RedisServerConnection::new(mock, DEFAULT_MAX_FRAME_SIZE);
+        let actual = conn.read_command().await.expect("Failed to read commands");
+
+        assert_eq!(Some(&value), actual.get(2));
+    }
+
+    #[tokio::test]
+    async fn test_read_resp_command() {
+        let mock = Builder::new()
+            .read(b"*3\r\n$3\r\nSET\r\n$3\r\nkey")
+            .read(b"\r\n$5\r\nvalue\r\n")
+            .write(b"+OK\r\n")
+            .build();
+
+        let mut conn = RedisServerConnection::new(mock, DEFAULT_MAX_FRAME_SIZE);
+        let actual = conn.read_command().await.expect("Failed to read commands");
+
+        assert_eq!(Protocol::Resp2, conn.protocol());
+        assert_eq!(VecDeque::from([b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]), actual);
+        conn.write_response(Response::String("OK".to_string())).await.expect("Failed to write response");
+        conn.flush().await.expect("Failed to flush");
+    }
+
+    #[tokio::test]
+    async fn test_write_resp3_response() {
+        let mock = Builder::new()
+            .read(b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n")
+            .write(b"_\r\n")
+            .build();
+
+        let mut conn = RedisServerConnection::new(mock, DEFAULT_MAX_FRAME_SIZE);
+        conn.read_command().await.expect("Failed to read commands");
+        conn.set_protocol(Protocol::Resp3);
+        conn.write_response(Response::Empty).await.expect("Failed to write response");
+        conn.flush().await.expect("Failed to flush");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_commands() {
+        let mut pipeline = encode_frame(&Command::GET("a".to_string()).encode());
+        pipeline.extend_from_slice(&encode_frame(&Command::GET("b".to_string()).encode()));
+        pipeline.extend_from_slice(&encode_frame(&Command::KEYS(None).encode()));
+
+        let mut responses = encode_frame(&Response::Data(b"1".to_vec()).serialize());
+        responses.extend_from_slice(&encode_frame(&Response::Data(b"2".to_vec()).serialize()));
+        responses.extend_from_slice(&encode_frame(&Response::Empty.serialize()));
+
+        // All three commands arrive in one read and are answered with one write.
+        let mock = Builder::new()
+            .read(&pipeline)
+            .write(&responses)
+            .build();
+
+        let mut conn = RedisServerConnection::new(mock, DEFAULT_MAX_FRAME_SIZE);
+        for response in [Response::Data(b"1".to_vec()), Response::Data(b"2".to_vec()), Response::Empty] {
+            conn.read_command().await.expect("Failed to read commands");
+            conn.write_response(response).await.expect("Failed to write response");
+        }
+        assert!(conn.read_command().await.is_err());
+    }
 }
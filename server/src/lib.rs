@@ -2,130 +2,1704 @@ mod table;
 mod tree;
 mod store;
 mod connection;
+mod snapshot;
+mod aof;
+mod config;
+mod logger;
+mod pubsub;
+mod scripting;
+mod sorted_set;
+mod transaction;
+mod value;
 
+use std::io::ErrorKind;
 use std::sync::Arc;
 use std::time::Duration;
+use log::{debug, error, info, warn};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
-use tokio::sync::RwLock;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
 use tokio::time::sleep;
-use rusty_redis_core::command::Command;
+use rusty_redis_core::command::{format_score, Command, KeyValue, ScoreBound, SetOptions};
+use rusty_redis_core::protocol::Protocol;
+use rusty_redis_core::pubsub::{Message, Push};
 use rusty_redis_core::response::Response;
 use crate::connection::RedisServerConnection;
 
-use self::store::DataStore;
+use self::aof::AppendOnlyFile;
+use self::pubsub::{EventClass, PubSub, SubscriberId};
+use self::scripting::Scripts;
+use self::snapshot::Snapshotter;
+use self::store::{DataStore, SetOp};
+use self::transaction::{StoreRef, Transaction};
+
+pub use self::aof::{AofConfig, FsyncPolicy};
+pub use self::config::{LogLevel, ServerConfig};
+pub use self::pubsub::KeyspaceEvents;
+pub use self::logger::init as init_logging;
+pub use self::snapshot::SaveRule;
+pub use self::store::EvictionPolicy;
+
+/// Longest the expire cycle holds the store at a time, as Redis' 1ms fast expire cycle.
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(1);
 
 pub struct RedisServer {
-    host: String,
-    port: u32,
-    store: Arc<RwLock<DataStore>>
+    config: Arc<RwLock<ServerConfig>>,
+    store: Arc<RwLock<DataStore>>,
+    snapshotter: Arc<Snapshotter>,
+    pubsub: Arc<RwLock<PubSub>>,
+    scripts: Arc<Scripts>,
+}
+
+/// Held by a connection while it is subscribed to at least one channel or pattern.
+struct Subscription {
+    id: SubscriberId,
+    messages: UnboundedReceiver<Message>,
 }
 
 impl RedisServer {
     pub fn new(host: String, port: u32) -> Self {
-        let store = Arc::new(RwLock::new(DataStore::new(64usize)));
-        RedisServer{host, port, store}
+        RedisServer::from_config(ServerConfig { bind: host, port, ..ServerConfig::default() })
+    }
+
+    pub fn from_config(config: ServerConfig) -> Self {
+        let mut store = DataStore::new(config.table_size);
+        store.set_maxmemory(config.maxmemory, config.maxmemory_policy);
+        let store = Arc::new(RwLock::new(store));
+        let snapshotter = Arc::new(Snapshotter::new(config.snapshot_path()));
+        let mut pubsub = PubSub::new();
+        pubsub.set_keyspace_events(config.notify_keyspace_events);
+        let scripts = Scripts::new();
+        scripts.set_time_limit(config.script_time_limit);
+        RedisServer{config: Arc::new(RwLock::new(config)), store, snapshotter, pubsub: Arc::new(RwLock::new(pubsub)), scripts: Arc::new(scripts)}
     }
 
     pub async fn start_server(&self) {
+        self.load().await;
+
         let data_store = self.store.clone();
+        let snapshotter = self.snapshotter.clone();
+        let config = self.config.clone();
         tokio::spawn(async move {
             loop {
                 sleep(Duration::from_millis(1000)).await;
-                let ex_keys = {
-                    let write_store = data_store.write();
-                    write_store.await.expire()
-                };
-                if let Some(expired) = ex_keys {
-                    println!("Cache Key ({:?}) expired", expired);
-                };
+                let save_rules = config.read().await.save_rules.clone();
+                let should_save = snapshotter.should_save(&*data_store.read().await, &save_rules);
+                if should_save && snapshotter.bgsave(data_store.clone()) {
+                    info!("Save rule met, background saving started");
+                }
+            }
+        });
+
+        let data_store = self.store.clone();
+        let config = self.config.clone();
+        let pubsub = self.pubsub.clone();
+        tokio::spawn(async move {
+            loop {
+                let interval = config.read().await.expire_interval;
+                sleep(Duration::from_millis(interval)).await;
+                loop {
+                    let (expired, more) = data_store.write().await.expire_cycle(ACTIVE_EXPIRE_BUDGET);
+                    if !expired.is_empty() {
+                        debug!("Cache Keys ({:?}) expired", expired);
+                        let pubsub = pubsub.read().await;
+                        expired.iter().for_each(|key| pubsub.notify(EventClass::Expired, "expired", key));
+                    }
+                    if !more {
+                        break;
+                    }
+                    // The lock is fair, so clients already waiting on the store get in before the next chunk.
+                    tokio::task::yield_now().await;
+                }
             }
         });
-        if let Ok(listener) = TcpListener::bind(
-            format!("{}:{}", self.host, self.port)).await {
-            self.listen(listener).await;
+
+        let addr = {
+            let config = self.config.read().await;
+            format!("{}:{}", config.bind, config.port)
+        };
+        match TcpListener::bind(&addr).await {
+            Ok(listener) => {
+                info!("Ready to accept connections on {}", addr);
+                self.listen(listener).await;
+            },
+            Err(e) => error!("Failed to bind {}: {}", addr, e),
+        }
+    }
+
+    /// Restore data from the append only file when it is enabled and present, otherwise from the snapshot.
+    async fn load(&self) {
+        let aof_config = self.config.read().await.aof_config();
+        let mut store_rw = self.store.write().await;
+        let aof_exists = aof_config.as_ref().is_some_and(|config| config.path.exists());
+        if let (Some(config), true) = (&aof_config, aof_exists) {
+            match aof::replay(&config.path, &mut store_rw) {
+                Ok(replayed) => info!("Replayed {} commands from append only file", replayed),
+                Err(e) => error!("Failed to replay append only file: {}", e),
+            }
+        } else {
+            match self.snapshotter.load(&mut store_rw) {
+                Ok(restored) => info!("Loaded {} keys from snapshot", restored),
+                Err(e) => error!("Failed to load snapshot: {}", e),
+            }
+        }
+        let dirty = store_rw.dirty();
+        store_rw.saved(dirty);
+
+        if let Some(config) = aof_config {
+            if !aof_exists {
+                // Seed a new log with whatever the snapshot held.
+                if let Err(e) = aof::write_rewrite(&config.path, &store_rw.entries()) {
+                    error!("Failed to create append only file: {}", e);
+                }
+            }
+            match AppendOnlyFile::open(config) {
+                Ok(aof) => {
+                    store_rw.attach_aof(aof);
+                    aof::spawn_fsync_task(self.store.clone());
+                },
+                Err(e) => error!("Failed to open append only file: {}", e),
+            }
         }
     }
 
     async fn listen(&self, listener: TcpListener) {
+        let max_frame_size = self.config.read().await.max_frame_size;
         loop {
-            let (stream, _addr) = listener.accept().await.expect("Failed to accept connection");
-            println!("Receiving Incoming Transmission");
+            let (stream, addr) = listener.accept().await.expect("Failed to accept connection");
+            debug!("Accepted connection from {}", addr);
             let data_store = self.store.clone();
-            let mut conn = RedisServerConnection::new(stream);
+            let snapshotter = self.snapshotter.clone();
+            let config = self.config.clone();
+            let pubsub = self.pubsub.clone();
+            let scripts = self.scripts.clone();
+            let mut conn = RedisServerConnection::new(stream, max_frame_size);
             tokio::spawn(async move {
+                let mut subscription: Option<Subscription> = None;
+                let mut transaction = Transaction::default();
                 loop {
-                    if let Ok(cmd) = conn.read_command().await {
-                        match Command::parse(cmd) {
+                    let cmd = match subscription.as_mut() {
+                        // A subscribed connection is also waiting on messages to push. Responses are
+                        // flushed up front, as reading is the only part safe to abandon mid-way.
+                        Some(subscription) => match conn.flush().await {
+                            Ok(()) => tokio::select! {
+                                cmd = conn.read_command() => cmd,
+                                Some(message) = subscription.messages.recv() => {
+                                    if let Err(e) = conn.write_response(Push::Message(message).into_response()).await {
+                                        warn!("Failed to write message {}", e);
+                                    }
+                                    continue;
+                                },
+                            },
+                            Err(e) => Err(e),
+                        },
+                        None => conn.read_command().await,
+                    };
+                    if let Ok(cmd) = cmd {
+                        match conn.protocol().parse_command(cmd) {
+                            // Only RESP3 can tell pushed messages apart from replies to other commands.
+                            Ok(the_cmd) if subscription.is_some() && conn.protocol() != Protocol::Resp3 && !allowed_when_subscribed(&the_cmd) => {
+                                let response = Response::Error(String::from("only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING are allowed in this context"));
+                                if let Err(e) = conn.write_response(response).await {
+                                    warn!("Failed to write response {}", e);
+                                }
+                            },
+                            // Between MULTI and EXEC commands are only queued, their replies all come with EXEC.
+                            Ok(the_cmd) if transaction.is_queueing() && !matches!(the_cmd, Command::EXEC | Command::DISCARD) => {
+                                let response = match the_cmd {
+                                    // Refused without spoiling the transaction, as in Redis.
+                                    Command::WATCH(_) => Response::Error(String::from("ERR WATCH inside MULTI is not allowed")),
+                                    Command::MULTI => Response::Error(String::from("ERR MULTI calls can not be nested")),
+                                    cmd if is_data_command(&cmd) || matches!(cmd, Command::EVAL(..) | Command::EVALSHA(..)) => {
+                                        transaction.queue(cmd);
+                                        Response::String(String::from("QUEUED"))
+                                    },
+                                    _ => {
+                                        transaction.fail();
+                                        Response::Error(String::from("Command not allowed inside a transaction"))
+                                    },
+                                };
+                                if let Err(e) = conn.write_response(response).await {
+                                    warn!("Failed to write response {}", e);
+                                }
+                            },
                             Ok(the_cmd) => {
                                 match the_cmd {
-                                    Command::GET(key) => execute_get(&mut conn, data_store.clone(), &key).await,
-                                    Command::KEYS => execute_keys(&mut conn, data_store.clone()).await,
-                                    Command::SET(key, value, ttl) => execute_set(&mut conn, data_store.clone(), &key, value, ttl).await,
-                                    Command::DELETE(key) => execute_delete(&mut conn, data_store.clone(), &key).await,
+                                    Command::HELLO(version) => execute_hello(&mut conn, version).await,
+                                    Command::SAVE => execute_save(&mut conn, data_store.clone(), &snapshotter).await,
+                                    Command::BGSAVE => execute_bgsave(&mut conn, data_store.clone(), &snapshotter).await,
+                                    Command::BGREWRITEAOF => execute_bgrewriteaof(&mut conn, data_store.clone()).await,
+                                    Command::CONFIGGET(pattern) => execute_config_get(&mut conn, &config, &pattern).await,
+                                    Command::CONFIGSET(name, value) => execute_config_set(&mut conn, &config, data_store.clone(), &pubsub, &scripts, &name, &value).await,
+                                    Command::SUBSCRIBE(channels) => execute_subscribe(&mut conn, &pubsub, &mut subscription, channels, false).await,
+                                    Command::UNSUBSCRIBE(channels) => execute_unsubscribe(&mut conn, &pubsub, &mut subscription, channels, false).await,
+                                    Command::PSUBSCRIBE(patterns) => execute_subscribe(&mut conn, &pubsub, &mut subscription, patterns, true).await,
+                                    Command::PUNSUBSCRIBE(patterns) => execute_unsubscribe(&mut conn, &pubsub, &mut subscription, patterns, true).await,
+                                    Command::MULTI => execute_multi(&mut conn, &mut transaction).await,
+                                    Command::EXEC => execute_exec(&mut conn, data_store.clone(), &pubsub, &scripts, &mut transaction).await,
+                                    Command::DISCARD => execute_discard(&mut conn, &data_store, &mut transaction).await,
+                                    Command::WATCH(keys) => execute_watch(&mut conn, &data_store, &mut transaction, keys).await,
+                                    Command::UNWATCH => execute_unwatch(&mut conn, &data_store, &mut transaction).await,
+                                    Command::EVAL(source, keys, args) => {
+                                        let script = scripts.load(&source).map(|(_, script)| script);
+                                        execute_eval(&mut conn, data_store.clone(), pubsub.clone(), scripts.clone(), script, keys, args).await
+                                    },
+                                    Command::EVALSHA(sha, keys, args) => {
+                                        let script = scripts.get(&sha);
+                                        execute_eval(&mut conn, data_store.clone(), pubsub.clone(), scripts.clone(), script, keys, args).await
+                                    },
+                                    Command::SCRIPTLOAD(source) => execute_script_load(&mut conn, &scripts, &source).await,
+                                    Command::SCRIPTEXISTS(shas) => execute_script_exists(&mut conn, &scripts, &shas).await,
+                                    Command::SCRIPTFLUSH => execute_script_flush(&mut conn, &scripts).await,
+                                    Command::SCRIPTKILL => execute_script_kill(&mut conn, &scripts).await,
+                                    cmd => execute_command(&mut conn, &mut StoreRef::Shared(data_store.clone()), &pubsub, cmd).await,
                                 };
                             },
-                            Err(e) => println!("invalid command received: {}", e),
+                            Err(e) => {
+                                debug!("invalid command received: {}", e);
+                                if transaction.is_queueing() {
+                                    transaction.fail();
+                                }
+                                if let Err(e) = conn.write_response(Response::Error(e.to_string())).await {
+                                    warn!("Failed to write response {}", e);
+                                }
+                            },
+                        }
+                    } else if let Err(e) = cmd {
+                        if e.kind() == ErrorKind::InvalidData {
+                            // Whatever follows a request that can't be decoded can't be read either, so the
+                            // client is sent the replies to the requests before it and why it is being closed.
+                            debug!("invalid request received: {}", e);
+                            if let Err(e) = conn.write_response(Response::Error(format!("ERR {}", e))).await {
+                                warn!("Failed to write response {}", e);
+                            }
+                            if let Err(e) = conn.flush().await {
+                                warn!("Failed to write response {}", e);
+                            }
+                        } else {
+                            debug!("connection ended");
                         }
-                    } else {
-                        println!("connection ended");
                         break;
                     }
                 }
+                if let Some(subscription) = subscription {
+                    pubsub.write().await.unregister(subscription.id);
+                }
+                if transaction.is_watching() {
+                    transaction.unwatch(&mut *data_store.write().await);
+                }
             });
         }
     }
 }
 
-async fn execute_keys<T>(conn: &mut RedisServerConnection<T>, data_store: Arc<RwLock<DataStore>>)
+/// Run a command that only needs the data store and pub/sub, the ones a transaction may queue.
+async fn execute_command<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, cmd: Command)
     where T: AsyncReadExt + AsyncWriteExt + Unpin
 {
-    println!("KEYS");
-    let store_read = data_store.read().await;
-    let keys = store_read.keys();
-    let response = Response::Array(Arc::new(keys.to_vec()));
+    expire_keys(data_store, pubsub, &cmd).await;
+    if adds_data(&cmd) && !make_room(data_store, pubsub).await {
+        let response = Response::Error(String::from("OOM command not allowed when used memory > 'maxmemory'."));
+        if let Err(e) = conn.write_response(response).await {
+            warn!("Failed to write response {}", e);
+        }
+        return;
+    }
+    match cmd {
+        Command::GET(key) => execute_get(conn, data_store, &key).await,
+        Command::KEYS(pattern) => execute_keys(conn, data_store, pattern).await,
+        Command::SET(key, value, options) => execute_set(conn, data_store, pubsub, &key, value, options).await,
+        Command::DELETE(keys) => execute_delete(conn, data_store, pubsub, &keys).await,
+        Command::PING(msg) => execute_ping(conn, msg).await,
+        Command::SCAN(cursor, pattern, count, kind) => execute_scan(conn, data_store, cursor, pattern, count, kind).await,
+        Command::LPUSH(key, values) => execute_push(conn, data_store, pubsub, &key, values, true).await,
+        Command::RPUSH(key, values) => execute_push(conn, data_store, pubsub, &key, values, false).await,
+        Command::LPOP(key, count) => execute_pop(conn, data_store, pubsub, &key, count, true).await,
+        Command::RPOP(key, count) => execute_pop(conn, data_store, pubsub, &key, count, false).await,
+        Command::LRANGE(key, start, stop) => execute_lrange(conn, data_store, &key, start, stop).await,
+        Command::LLEN(key) => execute_llen(conn, data_store, &key).await,
+        Command::LINDEX(key, index) => execute_lindex(conn, data_store, &key, index).await,
+        Command::LTRIM(key, start, stop) => execute_ltrim(conn, data_store, pubsub, &key, start, stop).await,
+        Command::HSET(key, pairs) => execute_hset(conn, data_store, pubsub, &key, pairs).await,
+        Command::HGET(key, field) => execute_hget(conn, data_store, &key, &field).await,
+        Command::HDEL(key, fields) => execute_hdel(conn, data_store, pubsub, &key, fields).await,
+        Command::HGETALL(key) => execute_hgetall(conn, data_store, &key).await,
+        Command::HINCRBY(key, field, increment) => execute_hincrby(conn, data_store, pubsub, &key, &field, increment).await,
+        Command::HKEYS(key) => execute_hkeys(conn, data_store, &key).await,
+        Command::HLEN(key) => execute_hlen(conn, data_store, &key).await,
+        Command::SADD(key, members) => execute_sadd(conn, data_store, pubsub, &key, members).await,
+        Command::SREM(key, members) => execute_srem(conn, data_store, pubsub, &key, members).await,
+        Command::SISMEMBER(key, member) => execute_sismember(conn, data_store, &key, &member).await,
+        Command::SMEMBERS(key) => execute_smembers(conn, data_store, &key).await,
+        Command::SCARD(key) => execute_scard(conn, data_store, &key).await,
+        Command::SINTER(keys) => execute_set_op(conn, data_store, SetOp::Inter, &keys).await,
+        Command::SUNION(keys) => execute_set_op(conn, data_store, SetOp::Union, &keys).await,
+        Command::SDIFF(keys) => execute_set_op(conn, data_store, SetOp::Diff, &keys).await,
+        Command::SINTERSTORE(destination, keys) => execute_set_op_store(conn, data_store, pubsub, SetOp::Inter, &destination, &keys).await,
+        Command::SUNIONSTORE(destination, keys) => execute_set_op_store(conn, data_store, pubsub, SetOp::Union, &destination, &keys).await,
+        Command::SDIFFSTORE(destination, keys) => execute_set_op_store(conn, data_store, pubsub, SetOp::Diff, &destination, &keys).await,
+        Command::ZADD(key, entries) => execute_zadd(conn, data_store, pubsub, &key, entries).await,
+        Command::ZREM(key, members) => execute_zrem(conn, data_store, pubsub, &key, members).await,
+        Command::ZSCORE(key, member) => execute_zscore(conn, data_store, &key, &member).await,
+        Command::ZRANK(key, member) => execute_zrank(conn, data_store, &key, &member).await,
+        Command::ZRANGE(key, start, stop, with_scores) => execute_zrange(conn, data_store, &key, start, stop, with_scores).await,
+        Command::ZRANGEBYSCORE(key, min, max, with_scores) => execute_zrange_by_score(conn, data_store, &key, min, max, with_scores).await,
+        Command::ZINCRBY(key, increment, member) => execute_zincrby(conn, data_store, pubsub, &key, increment, &member).await,
+        Command::ZCARD(key) => execute_zcard(conn, data_store, &key).await,
+        Command::INCR(key) => execute_incr_by(conn, data_store, pubsub, &key, 1).await,
+        Command::DECR(key) => execute_decr_by(conn, data_store, pubsub, &key, 1).await,
+        Command::INCRBY(key, increment) => execute_incr_by(conn, data_store, pubsub, &key, increment).await,
+        Command::DECRBY(key, decrement) => execute_decr_by(conn, data_store, pubsub, &key, decrement).await,
+        Command::INCRBYFLOAT(key, increment) => execute_incr_by_float(conn, data_store, pubsub, &key, increment).await,
+        Command::TTL(key) => execute_ttl(conn, data_store, &key, false).await,
+        Command::PTTL(key) => execute_ttl(conn, data_store, &key, true).await,
+        Command::EXPIRE(key, seconds) => execute_expire(conn, data_store, pubsub, &key, seconds.checked_mul(1000), false).await,
+        Command::PEXPIRE(key, millis) => execute_expire(conn, data_store, pubsub, &key, Some(millis), false).await,
+        Command::EXPIREAT(key, seconds) => execute_expire(conn, data_store, pubsub, &key, seconds.checked_mul(1000), true).await,
+        Command::PEXPIREAT(key, millis) => execute_expire(conn, data_store, pubsub, &key, Some(millis), true).await,
+        Command::PERSIST(key) => execute_persist(conn, data_store, pubsub, &key).await,
+        Command::MGET(keys) => execute_mget(conn, data_store, &keys).await,
+        Command::MSET(pairs) => execute_mset(conn, data_store, pubsub, pairs).await,
+        Command::MSETNX(pairs) => execute_msetnx(conn, data_store, pubsub, pairs).await,
+        Command::EXISTS(keys) => execute_exists(conn, data_store, &keys).await,
+        Command::UNLINK(keys) => execute_unlink(conn, data_store, pubsub, &keys).await,
+        Command::OBJECTIDLETIME(key) => execute_object(conn, data_store, &key, false).await,
+        Command::OBJECTFREQ(key) => execute_object(conn, data_store, &key, true).await,
+        Command::PUBLISH(channel, message) => execute_publish(conn, pubsub, &channel, &message).await,
+        _ => {
+            let response = Response::Error(String::from("Command not allowed inside a transaction"));
+            if let Err(e) = conn.write_response(response).await {
+                warn!("Failed to write response {}", e);
+            }
+        },
+    }
+}
+
+/// Commands that can grow the store, refused when it is over maxmemory and nothing can be evicted.
+fn adds_data(cmd: &Command) -> bool {
+    matches!(cmd, Command::SET(..) | Command::MSET(_) | Command::MSETNX(_)
+        | Command::INCR(_) | Command::DECR(_) | Command::INCRBY(..) | Command::DECRBY(..) | Command::INCRBYFLOAT(..)
+        | Command::LPUSH(..) | Command::RPUSH(..) | Command::HSET(..) | Command::HINCRBY(..) | Command::SADD(..)
+        | Command::SINTERSTORE(..) | Command::SUNIONSTORE(..) | Command::SDIFFSTORE(..) | Command::ZADD(..) | Command::ZINCRBY(..))
+}
+
+/// Remove any of the keys `cmd` acts on that are past their TTL, so it sees them as gone
+/// rather than waiting on the next expire cycle.
+async fn expire_keys(data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, cmd: &Command) {
+    let keys = cmd.keys();
+    let due = {
+        let store = data_store.read().await;
+        keys.iter().any(|key| store.is_due(key))
+    };
+    if !due {
+        return;
+    }
+    let expired: Vec<&str> = {
+        let mut store_rw = data_store.write().await;
+        keys.into_iter().filter(|key| store_rw.expire_if_due(key)).collect()
+    };
+    if !expired.is_empty() {
+        debug!("Cache Keys ({:?}) expired", expired);
+        let pubsub = pubsub.read().await;
+        expired.iter().for_each(|key| pubsub.notify(EventClass::Expired, "expired", key));
+    }
+}
+
+/// Evict keys until the store is back under maxmemory, returning false if it can't be.
+async fn make_room(data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>) -> bool {
+    if !data_store.read().await.over_maxmemory() {
+        return true;
+    }
+    let (evicted, room) = {
+        let mut store_rw = data_store.write().await;
+        (store_rw.evict(), !store_rw.over_maxmemory())
+    };
+    if !evicted.is_empty() {
+        debug!("Cache Keys ({:?}) evicted", evicted);
+        let pubsub = pubsub.read().await;
+        evicted.iter().for_each(|key| pubsub.notify(EventClass::Evicted, "evicted", key));
+    }
+    room
+}
+
+/// Whether `cmd` only needs the data store and pub/sub, so can be queued by a transaction or called
+/// from a script. Commands that act on the server or the connection itself are run straight away.
+fn is_data_command(cmd: &Command) -> bool {
+    !matches!(cmd, Command::HELLO(_) | Command::SAVE | Command::BGSAVE | Command::BGREWRITEAOF
+        | Command::CONFIGGET(_) | Command::CONFIGSET(..)
+        | Command::SUBSCRIBE(_) | Command::UNSUBSCRIBE(_) | Command::PSUBSCRIBE(_) | Command::PUNSUBSCRIBE(_)
+        | Command::MULTI | Command::EXEC | Command::DISCARD | Command::WATCH(_) | Command::UNWATCH
+        | Command::EVAL(..) | Command::EVALSHA(..)
+        | Command::SCRIPTLOAD(_) | Command::SCRIPTEXISTS(_) | Command::SCRIPTFLUSH | Command::SCRIPTKILL)
+}
+
+async fn execute_multi<T>(conn: &mut RedisServerConnection<T>, transaction: &mut Transaction)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("MULTI");
+    let response = if transaction.begin() {
+        Response::String(String::from("OK"))
+    } else {
+        Response::Error(String::from("ERR MULTI calls can not be nested"))
+    };
     if let Err(e) = conn.write_response(response).await {
-        eprintln!("Failed to write message {}", e);
+        warn!("Failed to write response {}", e);
     }
 }
 
-async fn execute_get<T>(conn: &mut RedisServerConnection<T>, data_store: Arc<RwLock<DataStore>>, key: &str)
+/// Run every queued command under a single write lock, so no other connection sees the store
+/// part way through. Replies with an array of their responses, or empty if a watched key was written.
+async fn execute_exec<T>(conn: &mut RedisServerConnection<T>, data_store: Arc<RwLock<DataStore>>, pubsub: &Arc<RwLock<PubSub>>,
+                         scripts: &Arc<Scripts>, transaction: &mut Transaction)
     where T: AsyncReadExt + AsyncWriteExt + Unpin
 {
-    println!("GET {key}");
-    let store_read = data_store.read().await;
-    if let Some(data) = store_read.get(key) {
-        // This seems dirty. But it copies it!
-        let response = Response::Data(data.to_vec());
+    debug!("EXEC");
+    let commands = transaction.exec();
+    let mut store_rw = data_store.clone().write_owned().await;
+    let unchanged = transaction.unchanged(&store_rw);
+    transaction.unwatch(&mut store_rw);
+    let response = match commands {
+        Ok(commands) if unchanged => {
+            conn.capture_responses();
+            for cmd in commands {
+                let (script, keys, args) = match cmd {
+                    Command::EVAL(source, keys, args) => (scripts.load(&source).map(|(_, script)| script), keys, args),
+                    Command::EVALSHA(sha, keys, args) => (scripts.get(&sha), keys, args),
+                    cmd => {
+                        execute_command(conn, &mut StoreRef::Locked(&mut store_rw), pubsub, cmd).await;
+                        continue;
+                    },
+                };
+                let response = match script {
+                    Ok(script) => {
+                        let (response, returned) = run_script(store_rw, pubsub.clone(), scripts.clone(), script, keys, args).await;
+                        store_rw = match returned {
+                            Some(store_rw) => store_rw,
+                            None => data_store.clone().write_owned().await,
+                        };
+                        response
+                    },
+                    Err(e) => Response::Error(e.to_string()),
+                };
+                if let Err(e) = conn.write_response(response).await {
+                    warn!("Failed to write response {}", e);
+                }
+            }
+            Response::Multi(conn.take_captured())
+        },
+        Ok(_) => Response::Empty,
+        Err(e) => Response::Error(e.to_string()),
+    };
+    drop(store_rw);
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_discard<T>(conn: &mut RedisServerConnection<T>, data_store: &RwLock<DataStore>, transaction: &mut Transaction)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("DISCARD");
+    let response = if transaction.discard() {
+        transaction.unwatch(&mut *data_store.write().await);
+        Response::String(String::from("OK"))
+    } else {
+        Response::Error(String::from("ERR DISCARD without MULTI"))
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// Have the next EXEC abort if any of `keys` is written before it.
+async fn execute_watch<T>(conn: &mut RedisServerConnection<T>, data_store: &RwLock<DataStore>, transaction: &mut Transaction, keys: Vec<String>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("WATCH {keys:?}");
+    transaction.watch(&mut *data_store.write().await, keys);
+    if let Err(e) = conn.write_response(Response::String(String::from("OK"))).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_unwatch<T>(conn: &mut RedisServerConnection<T>, data_store: &RwLock<DataStore>, transaction: &mut Transaction)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("UNWATCH");
+    transaction.unwatch(&mut *data_store.write().await);
+    if let Err(e) = conn.write_response(Response::String(String::from("OK"))).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// Run a script from EVAL or EVALSHA, holding the store's write lock until it finishes so it is
+/// applied as a whole. SCRIPT KILL and the other SCRIPT commands don't wait on the lock.
+async fn execute_eval<T>(conn: &mut RedisServerConnection<T>, data_store: Arc<RwLock<DataStore>>, pubsub: Arc<RwLock<PubSub>>,
+                         scripts: Arc<Scripts>, script: std::io::Result<Arc<rhai::AST>>, keys: Vec<String>, args: Vec<Vec<u8>>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("EVAL {keys:?}");
+    let response = match script {
+        Ok(script) => run_script(data_store.write_owned().await, pubsub, scripts, script, keys, args).await.0,
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// Run a script off the async workers with the store already locked, handing the lock back
+/// afterwards. None if the script panicked, taking the lock with it.
+async fn run_script(store_rw: OwnedRwLockWriteGuard<DataStore>, pubsub: Arc<RwLock<PubSub>>, scripts: Arc<Scripts>,
+                    script: Arc<rhai::AST>, keys: Vec<String>, args: Vec<Vec<u8>>) -> (Response, Option<OwnedRwLockWriteGuard<DataStore>>) {
+    let run = move || scripts.run(&script, store_rw, pubsub, keys, args);
+    match tokio::task::spawn_blocking(run).await {
+        Ok((response, store_rw)) => (response, Some(store_rw)),
+        Err(e) => (Response::Error(format!("ERR Error running script: {}", e)), None),
+    }
+}
+
+async fn execute_script_load<T>(conn: &mut RedisServerConnection<T>, scripts: &Scripts, source: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SCRIPT LOAD");
+    let response = match scripts.load(source) {
+        Ok((sha, _)) => Response::Data(sha.into_bytes()),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_script_exists<T>(conn: &mut RedisServerConnection<T>, scripts: &Scripts, shas: &[String])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SCRIPT EXISTS {shas:?}");
+    let response = Response::Multi(shas.iter().map(|sha| Response::Int(scripts.exists(sha) as i64)).collect());
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_script_flush<T>(conn: &mut RedisServerConnection<T>, scripts: &Scripts)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SCRIPT FLUSH");
+    scripts.flush();
+    if let Err(e) = conn.write_response(Response::String(String::from("OK"))).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_script_kill<T>(conn: &mut RedisServerConnection<T>, scripts: &Scripts)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SCRIPT KILL");
+    let response = match scripts.kill() {
+        Ok(()) => Response::String(String::from("OK")),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+fn allowed_when_subscribed(cmd: &Command) -> bool {
+    matches!(cmd, Command::SUBSCRIBE(_) | Command::UNSUBSCRIBE(_) | Command::PSUBSCRIBE(_) | Command::PUNSUBSCRIBE(_) | Command::PING(_))
+}
+
+/// SUBSCRIBE, or PSUBSCRIBE when `pattern` is set, confirming each channel or pattern with a push.
+async fn execute_subscribe<T>(conn: &mut RedisServerConnection<T>, pubsub: &RwLock<PubSub>, subscription: &mut Option<Subscription>, names: Vec<String>, pattern: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    let kind = if pattern { "psubscribe" } else { "subscribe" };
+    debug!("{kind} {names:?}");
+    let mut pubsub_rw = pubsub.write().await;
+    let id = subscription.get_or_insert_with(|| {
+        let (id, messages) = pubsub_rw.register();
+        Subscription { id, messages }
+    }).id;
+    let responses: Vec<_> = names.into_iter()
+        .map(|name| {
+            let count = pubsub_rw.subscribe(id, &name, pattern) as i64;
+            Push::Subscription { kind: kind.to_string(), name: Some(name), count }.into_response()
+        })
+        .collect();
+    drop(pubsub_rw);
+    for response in responses {
         if let Err(e) = conn.write_response(response).await {
-            eprintln!("Failed to write response {}", e);
+            warn!("Failed to write response {}", e);
         }
-    } else {
-        // return nil, if we had nil in Rust.
-        if let Err(e) = conn.write_response(Response::Empty).await {
-            eprintln!("Failed to write response {}", e);
+    }
+}
+
+/// UNSUBSCRIBE, or PUNSUBSCRIBE when `pattern` is set, from `names` or from everything when empty.
+/// The connection leaves subscribed mode once it has no subscriptions left.
+async fn execute_unsubscribe<T>(conn: &mut RedisServerConnection<T>, pubsub: &RwLock<PubSub>, subscription: &mut Option<Subscription>, names: Vec<String>, pattern: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    let kind = if pattern { "punsubscribe" } else { "unsubscribe" };
+    debug!("{kind} {names:?}");
+    let mut pubsub_rw = pubsub.write().await;
+    let id = subscription.as_ref().map(|subscription| subscription.id);
+    let names = match id {
+        Some(id) if names.is_empty() => pubsub_rw.subscriptions(id, pattern),
+        _ => names,
+    };
+    let mut responses: Vec<_> = names.into_iter()
+        .map(|name| {
+            let count = id.map_or(0, |id| pubsub_rw.unsubscribe(id, &name, pattern)) as i64;
+            Push::Subscription { kind: kind.to_string(), name: Some(name), count }.into_response()
+        })
+        .collect();
+    if responses.is_empty() {
+        let count = id.map_or(0, |id| pubsub_rw.count(id)) as i64;
+        responses.push(Push::Subscription { kind: kind.to_string(), name: None, count }.into_response());
+    }
+    if let Some(id) = id.filter(|id| pubsub_rw.count(*id) == 0) {
+        pubsub_rw.unregister(id);
+        *subscription = None;
+    }
+    drop(pubsub_rw);
+    for response in responses {
+        if let Err(e) = conn.write_response(response).await {
+            warn!("Failed to write response {}", e);
         }
     }
 }
 
-async fn execute_set<T>(conn: &mut RedisServerConnection<T>, data_store: Arc<RwLock<DataStore>>, key: &str, value: Vec<u8>, ttl: u64)
+async fn execute_publish<T>(conn: &mut RedisServerConnection<T>, pubsub: &RwLock<PubSub>, channel: &str, message: &[u8])
     where T: AsyncReadExt + AsyncWriteExt + Unpin
 {
-    println!("SET {key}: {}", String::from_utf8(value.to_vec()).unwrap());
-    let mut store_rw = data_store.write().await;
-    store_rw.insert(key, value, ttl);
-    if let Err(e) = conn.write_response(Response::String(String::from("Hi Client! I'm Dad!"))).await {
-        eprintln!("Failed to write message {}", e);
+    debug!("PUBLISH {channel}");
+    let receivers = pubsub.read().await.publish(channel, message);
+    if let Err(e) = conn.write_response(Response::Int(receivers as i64)).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_keys<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pattern: Option<String>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("KEYS {pattern:?}");
+    let store_read = data_store.read().await;
+    let keys = store_read.keys(pattern.as_deref());
+    let response = Response::Array(Arc::new(keys));
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write message {}", e);
+    }
+}
+
+async fn execute_scan<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, cursor: u64, pattern: Option<String>, count: u64, kind: Option<String>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SCAN {cursor} {pattern:?} {count} {kind:?}");
+    let (cursor, keys) = data_store.read().await.scan(cursor, pattern.as_deref(), count, kind.as_deref());
+    let response = Response::Multi(vec![
+        Response::Data(cursor.to_string().into_bytes()),
+        Response::Array(Arc::new(keys)),
+    ]);
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_get<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("GET {key}");
+    let response = match data_store.read().await.get(key) {
+        // This seems dirty. But it copies it!
+        Ok(Some(data)) => Response::Data(data.to_vec()),
+        // return nil, if we had nil in Rust.
+        Ok(None) => Response::Empty,
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_set<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, value: Vec<u8>, options: SetOptions)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SET {key}: {} {options:?}", String::from_utf8_lossy(&value));
+    let result = data_store.write().await.insert_with(key, value, &options);
+    if let Ok((true, _)) = result {
+        pubsub.read().await.notify(EventClass::String, "set", key);
+    }
+    let response = match result {
+        // GET replies with the old value whether or not the condition let the write through.
+        Ok((_, previous)) if options.get => previous.map(Response::Data).unwrap_or(Response::Empty),
+        Ok((true, _)) => Response::String(String::from("OK")),
+        Ok((false, _)) => Response::Empty,
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write message {}", e);
     }
 }
 
-async fn execute_delete<T>(conn: &mut RedisServerConnection<T>, cache: Arc<RwLock<DataStore>>, key: &str)
+async fn execute_delete<T>(conn: &mut RedisServerConnection<T>, cache: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, keys: &[String])
     where T: AsyncReadExt + AsyncWriteExt + Unpin
 {
-    println!("DEL {key}");
+    debug!("DEL {keys:?}");
     let mut cache_rw = cache.write().await;
-    cache_rw.delete(key);
-    if let Err(e) = conn.write_response(Response::String(String::from("Hi Client! I'm Dad!"))).await {
-        eprintln!("Failed to write message {}", e);
+    let deleted: Vec<&String> = keys.iter().filter(|key| cache_rw.delete(key)).collect();
+    drop(cache_rw);
+    let pubsub = pubsub.read().await;
+    deleted.iter().for_each(|key| pubsub.notify(EventClass::Generic, "del", key));
+    drop(pubsub);
+    if let Err(e) = conn.write_response(Response::Int(deleted.len() as i64)).await {
+        warn!("Failed to write message {}", e);
+    }
+}
+
+/// DEL that drops the removed values on a blocking thread, as freeing a large
+/// list, hash or set can take long enough to hold up other requests.
+async fn execute_unlink<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, keys: &[String])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("UNLINK {keys:?}");
+    let mut store_rw = data_store.write().await;
+    let (unlinked, values): (Vec<&String>, Vec<_>) = keys.iter()
+        .filter_map(|key| store_rw.unlink(key).map(|value| (key, value)))
+        .unzip();
+    drop(store_rw);
+    let pubsub = pubsub.read().await;
+    unlinked.iter().for_each(|key| pubsub.notify(EventClass::Generic, "del", key));
+    drop(pubsub);
+    let response = Response::Int(values.len() as i64);
+    if !values.is_empty() {
+        tokio::task::spawn_blocking(move || drop(values));
+    }
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_exists<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, keys: &[String])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("EXISTS {keys:?}");
+    let response = Response::Int(data_store.read().await.exists(keys) as i64);
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// OBJECT IDLETIME, or OBJECT FREQ when `freq` is set. Neither counts as an access to the key.
+async fn execute_object<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, freq: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("OBJECT {} {key}", if freq { "FREQ" } else { "IDLETIME" });
+    let store = data_store.read().await;
+    let value = if freq { store.frequency(key).map(i64::from) } else { store.idle_time(key).map(|idle| idle as i64) };
+    drop(store);
+    let response = value.map_or(Response::Empty, Response::Int);
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// Replies with the value of each key in turn, empty for a missing key or one that is not a string.
+async fn execute_mget<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, keys: &[String])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("MGET {keys:?}");
+    let values = data_store.read().await.mget(keys);
+    let response = Response::Multi(values.into_iter()
+        .map(|value| value.map(Response::Data).unwrap_or(Response::Empty))
+        .collect());
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_mset<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, pairs: Vec<KeyValue>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("MSET ({} keys)", pairs.len());
+    let keys: Vec<String> = pairs.iter().map(|(key, _)| key.clone()).collect();
+    data_store.write().await.mset(pairs);
+    notify_set(pubsub, &keys).await;
+    if let Err(e) = conn.write_response(Response::String(String::from("OK"))).await {
+        warn!("Failed to write message {}", e);
+    }
+}
+
+async fn execute_msetnx<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, pairs: Vec<KeyValue>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("MSETNX ({} keys)", pairs.len());
+    let keys: Vec<String> = pairs.iter().map(|(key, _)| key.clone()).collect();
+    let written = data_store.write().await.msetnx(pairs);
+    if written {
+        notify_set(pubsub, &keys).await;
+    }
+    if let Err(e) = conn.write_response(Response::Int(written as i64)).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn notify_set(pubsub: &RwLock<PubSub>, keys: &[String]) {
+    let pubsub = pubsub.read().await;
+    keys.iter().for_each(|key| pubsub.notify(EventClass::String, "set", key));
+}
+
+async fn execute_hello<T>(conn: &mut RedisServerConnection<T>, version: Option<u32>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("HELLO {version:?}");
+    let response = match (conn.protocol(), version) {
+        (Protocol::Native, _) => Response::Error(String::from("HELLO is only supported by RESP connections")),
+        (current, None) => hello_response(current),
+        (_, Some(2)) => {
+            conn.set_protocol(Protocol::Resp2);
+            hello_response(Protocol::Resp2)
+        },
+        (_, Some(3)) => {
+            conn.set_protocol(Protocol::Resp3);
+            hello_response(Protocol::Resp3)
+        },
+        (_, Some(_)) => Response::Error(String::from("NOPROTO unsupported protocol version")),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// A map of server details, sent to RESP2 clients as a flat array of fields and values.
+fn hello_response(protocol: Protocol) -> Response {
+    let proto = if protocol == Protocol::Resp3 { "3" } else { "2" };
+    let fields = [
+        ("server", "rusty-redis"),
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("proto", proto),
+        ("mode", "standalone"),
+        ("role", "master"),
+    ];
+    Response::Map(fields.iter().map(|(field, value)| (field.as_bytes().to_vec(), value.as_bytes().to_vec())).collect())
+}
+
+async fn execute_ping<T>(conn: &mut RedisServerConnection<T>, msg: Option<String>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    let response = match msg {
+        Some(msg) => Response::Data(msg.into_bytes()),
+        None => Response::String(String::from("PONG")),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_save<T>(conn: &mut RedisServerConnection<T>, data_store: Arc<RwLock<DataStore>>, snapshotter: &Snapshotter)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SAVE");
+    let response = match snapshotter.save(&data_store).await {
+        Ok(_) => Response::String(String::from("OK")),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_bgsave<T>(conn: &mut RedisServerConnection<T>, data_store: Arc<RwLock<DataStore>>, snapshotter: &Arc<Snapshotter>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("BGSAVE");
+    let response = if snapshotter.bgsave(data_store) {
+        Response::String(String::from("Background saving started"))
+    } else {
+        Response::Error(String::from("Background save already in progress"))
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_bgrewriteaof<T>(conn: &mut RedisServerConnection<T>, data_store: Arc<RwLock<DataStore>>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("BGREWRITEAOF");
+    let response = match aof::bgrewrite(data_store).await {
+        Ok(_) => Response::String(String::from("Background append only file rewriting started")),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_config_get<T>(conn: &mut RedisServerConnection<T>, config: &RwLock<ServerConfig>, pattern: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("CONFIG GET {pattern}");
+    let fields = config.read().await.get(pattern)
+        .into_iter()
+        .flat_map(|(name, value)| [name, value])
+        .collect();
+    if let Err(e) = conn.write_response(Response::Array(Arc::new(fields))).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_config_set<T>(conn: &mut RedisServerConnection<T>, config: &RwLock<ServerConfig>, data_store: Arc<RwLock<DataStore>>, pubsub: &RwLock<PubSub>, scripts: &Scripts, name: &str, value: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("CONFIG SET {name} {value}");
+    let mut config_rw = config.write().await;
+    let response = match config_rw.set(name, value) {
+        Ok(_) => {
+            // Most settings are read as they are needed, these have to be pushed to where they are used.
+            match name.to_ascii_lowercase().as_str() {
+                "loglevel" => logger::set_level(config_rw.loglevel),
+                "appendfsync" => {
+                    if let Some(aof) = data_store.write().await.aof_mut() {
+                        aof.set_fsync_policy(config_rw.appendfsync);
+                    }
+                },
+                "maxmemory" | "maxmemory-policy" => data_store.write().await.set_maxmemory(config_rw.maxmemory, config_rw.maxmemory_policy),
+                "notify-keyspace-events" => pubsub.write().await.set_keyspace_events(config_rw.notify_keyspace_events),
+                "script-time-limit" => scripts.set_time_limit(config_rw.script_time_limit),
+                _ => {},
+            }
+            Response::String(String::from("OK"))
+        },
+        Err(e) => Response::Error(e.to_string()),
+    };
+    drop(config_rw);
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_push<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, values: Vec<Vec<u8>>, front: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("{} {key} ({} values)", if front { "LPUSH" } else { "RPUSH" }, values.len());
+    let result = data_store.write().await.push(key, values, front);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::List, if front { "lpush" } else { "rpush" }, key);
+    }
+    let response = match result {
+        Ok(len) => Response::Int(len as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_pop<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, count: Option<u64>, front: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("{} {key} {count:?}", if front { "LPOP" } else { "RPOP" });
+    let result = data_store.write().await.pop(key, count, front);
+    if matches!(&result, Ok(Some(values)) if !values.is_empty()) {
+        pubsub.read().await.notify(EventClass::List, if front { "lpop" } else { "rpop" }, key);
+    }
+    let response = match (result, count) {
+        (Ok(Some(values)), Some(_)) => data_array(values),
+        (Ok(Some(mut values)), None) if !values.is_empty() => Response::Data(values.remove(0)),
+        (Ok(_), _) => Response::Empty,
+        (Err(e), _) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_lrange<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, start: i64, stop: i64)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("LRANGE {key} {start} {stop}");
+    let response = match data_store.read().await.lrange(key, start, stop) {
+        Ok(values) => data_array(values),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_llen<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("LLEN {key}");
+    let response = match data_store.read().await.llen(key) {
+        Ok(len) => Response::Int(len as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_lindex<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, index: i64)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("LINDEX {key} {index}");
+    let response = match data_store.read().await.lindex(key, index) {
+        Ok(Some(value)) => Response::Data(value),
+        Ok(None) => Response::Empty,
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_ltrim<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, start: i64, stop: i64)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("LTRIM {key} {start} {stop}");
+    let result = data_store.write().await.ltrim(key, start, stop);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::List, "ltrim", key);
+    }
+    let response = match result {
+        Ok(_) => Response::String(String::from("OK")),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_hset<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, pairs: Vec<(Vec<u8>, Vec<u8>)>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("HSET {key} ({} fields)", pairs.len());
+    let result = data_store.write().await.hset(key, pairs);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::Hash, "hset", key);
+    }
+    let response = match result {
+        Ok(added) => Response::Int(added as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_hget<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, field: &[u8])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("HGET {key} {}", String::from_utf8_lossy(field));
+    let response = match data_store.read().await.hget(key, field) {
+        Ok(Some(value)) => Response::Data(value),
+        Ok(None) => Response::Empty,
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_hdel<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, fields: Vec<Vec<u8>>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("HDEL {key} ({} fields)", fields.len());
+    let result = data_store.write().await.hdel(key, fields);
+    if matches!(result, Ok(removed) if removed > 0) {
+        pubsub.read().await.notify(EventClass::Hash, "hdel", key);
+    }
+    let response = match result {
+        Ok(removed) => Response::Int(removed as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_hgetall<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("HGETALL {key}");
+    let response = match data_store.read().await.hgetall(key) {
+        Ok(pairs) => Response::Map(pairs),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_hincrby<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, field: &[u8], increment: i64)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("HINCRBY {key} {} {increment}", String::from_utf8_lossy(field));
+    let result = data_store.write().await.hincrby(key, field, increment);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::Hash, "hincrby", key);
+    }
+    let response = match result {
+        Ok(value) => Response::Int(value),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_hkeys<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("HKEYS {key}");
+    let response = match data_store.read().await.hkeys(key) {
+        Ok(fields) => data_array(fields),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_hlen<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("HLEN {key}");
+    let response = match data_store.read().await.hlen(key) {
+        Ok(len) => Response::Int(len as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_sadd<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, members: Vec<Vec<u8>>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SADD {key} ({} members)", members.len());
+    let result = data_store.write().await.sadd(key, members);
+    if matches!(result, Ok(added) if added > 0) {
+        pubsub.read().await.notify(EventClass::Set, "sadd", key);
+    }
+    let response = match result {
+        Ok(added) => Response::Int(added as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_srem<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, members: Vec<Vec<u8>>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SREM {key} ({} members)", members.len());
+    let result = data_store.write().await.srem(key, members);
+    if matches!(result, Ok(removed) if removed > 0) {
+        pubsub.read().await.notify(EventClass::Set, "srem", key);
+    }
+    let response = match result {
+        Ok(removed) => Response::Int(removed as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_sismember<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, member: &[u8])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SISMEMBER {key} {}", String::from_utf8_lossy(member));
+    let response = match data_store.read().await.sismember(key, member) {
+        Ok(is_member) => Response::Int(is_member as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_smembers<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SMEMBERS {key}");
+    let response = match data_store.read().await.smembers(key) {
+        Ok(members) => data_array(members),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_scard<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("SCARD {key}");
+    let response = match data_store.read().await.scard(key) {
+        Ok(len) => Response::Int(len as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// SINTER, SUNION and SDIFF, every key is read under the same lock so the result is consistent.
+async fn execute_set_op<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, op: SetOp, keys: &[String])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("{} {keys:?}", op.name());
+    let response = match data_store.read().await.set_op(op, keys) {
+        Ok(members) => data_array(members.into_iter().collect()),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_set_op_store<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, op: SetOp, destination: &str, keys: &[String])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("{}STORE {destination} {keys:?}", op.name());
+    let result = data_store.write().await.set_op_store(op, destination, keys);
+    if result.is_ok() {
+        let event = format!("{}store", op.name().to_ascii_lowercase());
+        pubsub.read().await.notify(EventClass::Set, &event, destination);
+    }
+    let response = match result {
+        Ok(len) => Response::Int(len as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_zadd<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, entries: Vec<(f64, Vec<u8>)>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("ZADD {key} ({} members)", entries.len());
+    let result = data_store.write().await.zadd(key, entries);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::SortedSet, "zadd", key);
+    }
+    let response = match result {
+        Ok(added) => Response::Int(added as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_zrem<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, members: Vec<Vec<u8>>)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("ZREM {key} ({} members)", members.len());
+    let result = data_store.write().await.zrem(key, members);
+    if matches!(result, Ok(removed) if removed > 0) {
+        pubsub.read().await.notify(EventClass::SortedSet, "zrem", key);
+    }
+    let response = match result {
+        Ok(removed) => Response::Int(removed as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_zscore<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, member: &[u8])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("ZSCORE {key} {}", String::from_utf8_lossy(member));
+    let response = match data_store.read().await.zscore(key, member) {
+        Ok(Some(score)) => Response::Data(format_score(score).into_bytes()),
+        Ok(None) => Response::Empty,
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_zrank<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, member: &[u8])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("ZRANK {key} {}", String::from_utf8_lossy(member));
+    let response = match data_store.read().await.zrank(key, member) {
+        Ok(Some(rank)) => Response::Int(rank as i64),
+        Ok(None) => Response::Empty,
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_zrange<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, start: i64, stop: i64, with_scores: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("ZRANGE {key} {start} {stop} {with_scores}");
+    let response = match data_store.read().await.zrange(key, start, stop) {
+        Ok(entries) => scored_members(entries, with_scores),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_zrange_by_score<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, min: ScoreBound, max: ScoreBound, with_scores: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("ZRANGEBYSCORE {key} {min:?} {max:?} {with_scores}");
+    let response = match data_store.read().await.zrange_by_score(key, min, max) {
+        Ok(entries) => scored_members(entries, with_scores),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_zincrby<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, increment: f64, member: &[u8])
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("ZINCRBY {key} {increment} {}", String::from_utf8_lossy(member));
+    let result = data_store.write().await.zincrby(key, increment, member);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::SortedSet, "zincr", key);
+    }
+    let response = match result {
+        Ok(score) => Response::Data(format_score(score).into_bytes()),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_zcard<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("ZCARD {key}");
+    let response = match data_store.read().await.zcard(key) {
+        Ok(len) => Response::Int(len as i64),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_incr_by<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, increment: i64)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("INCRBY {key} {increment}");
+    let result = data_store.write().await.incr_by(key, increment);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::String, "incrby", key);
+    }
+    let response = match result {
+        Ok(value) => Response::Int(value),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_decr_by<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, decrement: i64)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("DECRBY {key} {decrement}");
+    let result = data_store.write().await.decr_by(key, decrement);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::String, "decrby", key);
+    }
+    let response = match result {
+        Ok(value) => Response::Int(value),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_incr_by_float<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, increment: f64)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("INCRBYFLOAT {key} {increment}");
+    let result = data_store.write().await.incr_by_float(key, increment);
+    if result.is_ok() {
+        pubsub.read().await.notify(EventClass::String, "incrbyfloat", key);
+    }
+    let response = match result {
+        Ok(value) => Response::Data(format_score(value).into_bytes()),
+        Err(e) => Response::Error(e.to_string()),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// TTL in seconds, or PTTL in milliseconds when `millis` is set.
+/// Replies -2 when there is no such key and -1 when it never expires.
+async fn execute_ttl<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, key: &str, millis: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("TTL {key}");
+    let response = match data_store.read().await.pttl(key) {
+        None => Response::Int(-2),
+        Some(None) => Response::Int(-1),
+        Some(Some(ttl)) if millis => Response::Int(ttl as i64),
+        Some(Some(ttl)) => Response::Int(ttl.div_ceil(1000) as i64),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// EXPIRE and its variants, with the expiry already in milliseconds.
+/// `millis` is None when converting from seconds overflowed.
+async fn execute_expire<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str, millis: Option<i64>, absolute: bool)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("EXPIRE {key} {millis:?} absolute:{absolute}");
+    let mut store = data_store.write().await;
+    let applied = match millis {
+        Some(at) if absolute => Some(store.expire_at(key, at)),
+        Some(millis) => Some(store.expire_in(key, millis)),
+        None => None,
+    };
+    // A time already past deletes the key rather than setting a TTL.
+    let deleted = store.pttl(key).is_none();
+    drop(store);
+    if applied == Some(true) {
+        pubsub.read().await.notify(EventClass::Generic, if deleted { "del" } else { "expire" }, key);
+    }
+    let response = match applied {
+        Some(applied) => Response::Int(applied as i64),
+        None => Response::Error(String::from("invalid expire time")),
+    };
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+async fn execute_persist<T>(conn: &mut RedisServerConnection<T>, data_store: &mut StoreRef<'_>, pubsub: &RwLock<PubSub>, key: &str)
+    where T: AsyncReadExt + AsyncWriteExt + Unpin
+{
+    debug!("PERSIST {key}");
+    let persisted = data_store.write().await.persist(key);
+    if persisted {
+        pubsub.read().await.notify(EventClass::Generic, "persist", key);
+    }
+    let response = Response::Int(persisted as i64);
+    if let Err(e) = conn.write_response(response).await {
+        warn!("Failed to write response {}", e);
+    }
+}
+
+/// Members in score order, paired up with their scores when `with_scores` is set.
+fn scored_members(entries: Vec<(Vec<u8>, f64)>, with_scores: bool) -> Response {
+    if with_scores {
+        Response::Map(entries.into_iter().map(|(member, score)| (member, format_score(score).into_bytes())).collect())
+    } else {
+        data_array(entries.into_iter().map(|(member, _)| member).collect())
+    }
+}
+
+/// Binary safe array of values, unlike `Response::Array` which only holds text.
+fn data_array(values: Vec<Vec<u8>>) -> Response {
+    Response::Multi(values.into_iter().map(Response::Data).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use rusty_redis_core::frame::DEFAULT_MAX_FRAME_SIZE;
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+    use super::*;
+
+    /// Serve connections from a fresh server on a local port, returning its address.
+    async fn serve() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = RedisServer::from_config(ServerConfig::default());
+        tokio::spawn(async move { server.listen(listener).await });
+        addr
+    }
+
+    /// Read exactly as many bytes as `expected` holds and compare them.
+    async fn expect_reply(stream: &mut TcpStream, expected: &[u8]) {
+        let mut reply = vec![0; expected.len()];
+        timeout(Duration::from_secs(5), stream.read_exact(&mut reply)).await
+            .expect("Timed out waiting for a reply")
+            .expect("Failed to read reply");
+        assert_eq!(String::from_utf8_lossy(expected), String::from_utf8_lossy(&reply));
+    }
+
+    #[tokio::test]
+    async fn test_resp_set_replies_ok() {
+        let mut stream = TcpStream::connect(serve().await).await.unwrap();
+        stream.write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n").await.unwrap();
+        expect_reply(&mut stream, b"+OK\r\n").await;
+        stream.write_all(b"*4\r\n$3\r\nSET\r\n$4\r\nlock\r\n$1\r\n1\r\n$2\r\nNX\r\n").await.unwrap();
+        expect_reply(&mut stream, b"+OK\r\n").await;
+        stream.write_all(b"*5\r\n$4\r\nMSET\r\n$1\r\na\r\n$1\r\n1\r\n$1\r\nb\r\n$1\r\n2\r\n").await.unwrap();
+        expect_reply(&mut stream, b"+OK\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_resp_pipeline_protocol_error() {
+        let mut stream = TcpStream::connect(serve().await).await.unwrap();
+        stream.write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n*1\r\n$x\r\n").await.unwrap();
+        let mut reply = vec![];
+        timeout(Duration::from_secs(5), stream.read_to_end(&mut reply)).await
+            .expect("Timed out waiting for the connection to close")
+            .expect("Failed to read reply");
+        let reply = String::from_utf8_lossy(&reply);
+        assert!(reply.starts_with("+OK\r\n$1\r\nv\r\n-ERR Protocol error: "), "{}", reply);
+        assert!(reply.ends_with("\r\n"));
+    }
+
+    /// A RESP request for `args`.
+    fn request(args: &[&str]) -> Vec<u8> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn test_resp_hello() {
+        let mut stream = TcpStream::connect(serve().await).await.unwrap();
+        stream.write_all(&request(&["HELLO", "3"])).await.unwrap();
+        let version = env!("CARGO_PKG_VERSION");
+        let hello = format!("%5\r\n$6\r\nserver\r\n$11\r\nrusty-redis\r\n$7\r\nversion\r\n${}\r\n{}\r\n\
+                             $5\r\nproto\r\n$1\r\n3\r\n$4\r\nmode\r\n$10\r\nstandalone\r\n$4\r\nrole\r\n$6\r\nmaster\r\n",
+                            version.len(), version);
+        expect_reply(&mut stream, hello.as_bytes()).await;
+
+        // Later replies use the RESP3 types, a null rather than a null bulk string and maps rather than flat arrays.
+        stream.write_all(&request(&["GET", "missing"])).await.unwrap();
+        expect_reply(&mut stream, b"_\r\n").await;
+        stream.write_all(&request(&["HSET", "h", "f", "v"])).await.unwrap();
+        expect_reply(&mut stream, b":1\r\n").await;
+        stream.write_all(&request(&["HGETALL", "h"])).await.unwrap();
+        expect_reply(&mut stream, b"%1\r\n$1\r\nf\r\n$1\r\nv\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resp_transaction() {
+        let addr = serve().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut other = TcpStream::connect(addr).await.unwrap();
+
+        stream.write_all(&request(&["WATCH", "k"])).await.unwrap();
+        expect_reply(&mut stream, b"+OK\r\n").await;
+        stream.write_all(&request(&["MULTI"])).await.unwrap();
+        expect_reply(&mut stream, b"+OK\r\n").await;
+        // Neither of these spoils the transaction.
+        stream.write_all(&request(&["WATCH", "other"])).await.unwrap();
+        expect_reply(&mut stream, b"-ERR WATCH inside MULTI is not allowed\r\n").await;
+        stream.write_all(&request(&["MULTI"])).await.unwrap();
+        expect_reply(&mut stream, b"-ERR MULTI calls can not be nested\r\n").await;
+        stream.write_all(&request(&["SET", "k", "mine"])).await.unwrap();
+        expect_reply(&mut stream, b"+QUEUED\r\n").await;
+
+        other.write_all(&request(&["SET", "k", "theirs"])).await.unwrap();
+        expect_reply(&mut other, b"+OK\r\n").await;
+        stream.write_all(&request(&["EXEC"])).await.unwrap();
+        expect_reply(&mut stream, b"$-1\r\n").await;
+        stream.write_all(&request(&["GET", "k"])).await.unwrap();
+        expect_reply(&mut stream, b"$6\r\ntheirs\r\n").await;
+
+        // Scripts are queued and run in order with the rest.
+        stream.write_all(&request(&["MULTI"])).await.unwrap();
+        expect_reply(&mut stream, b"+OK\r\n").await;
+        stream.write_all(&request(&["SET", "n", "1"])).await.unwrap();
+        expect_reply(&mut stream, b"+QUEUED\r\n").await;
+        stream.write_all(&request(&["EVAL", r#"redis.call("INCR", KEYS[0])"#, "1", "n"])).await.unwrap();
+        expect_reply(&mut stream, b"+QUEUED\r\n").await;
+        stream.write_all(&request(&["INCR", "n"])).await.unwrap();
+        expect_reply(&mut stream, b"+QUEUED\r\n").await;
+        stream.write_all(&request(&["EXEC"])).await.unwrap();
+        expect_reply(&mut stream, b"*3\r\n+OK\r\n:2\r\n:3\r\n").await;
+    }
+
+    /// Run `cmd` against `store`, returning its reply.
+    async fn run(store: &mut DataStore, cmd: Command) -> Response {
+        let mut conn = RedisServerConnection::new(tokio::io::duplex(1).0, DEFAULT_MAX_FRAME_SIZE);
+        conn.capture_responses();
+        execute_command(&mut conn, &mut StoreRef::Locked(store), &RwLock::new(PubSub::new()), cmd).await;
+        conn.take_captured().pop().expect("No reply captured")
+    }
+
+    #[tokio::test]
+    async fn test_set_get_reply() {
+        let mut store = DataStore::new(16);
+        let set = |key: &str, value: &[u8], options| Command::SET(key.to_string(), value.to_vec(), options);
+
+        // A missing old value replies nil, whether or not the write went ahead.
+        assert_eq!(Response::Empty, run(&mut store, set("k", b"1", SetOptions::new().get())).await);
+        assert_eq!(Response::Empty, run(&mut store, set("missing", b"1", SetOptions::new().xx().get())).await);
+        assert_eq!(Response::Empty, run(&mut store, Command::GET("missing".to_string())).await);
+
+        assert_eq!(Response::Data(b"1".to_vec()), run(&mut store, set("k", b"2", SetOptions::new().nx().get())).await);
+        assert_eq!(Response::Data(b"1".to_vec()), run(&mut store, set("k", b"3", SetOptions::new().get())).await);
+        assert_eq!(Response::Data(b"3".to_vec()), run(&mut store, Command::GET("k".to_string())).await);
+
+        // GET against another type of value is an error and leaves it in place.
+        run(&mut store, Command::LPUSH("list".to_string(), vec![b"a".to_vec()])).await;
+        let reply = run(&mut store, set("list", b"v", SetOptions::new().get())).await;
+        assert!(matches!(&reply, Response::Error(msg) if msg.starts_with("WRONGTYPE")), "{reply:?}");
+        assert_eq!(Response::Int(1), run(&mut store, Command::LLEN("list".to_string())).await);
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_events() {
+        let mut store = DataStore::new(16);
+        let mut pubsub = PubSub::new();
+        pubsub.set_keyspace_events("EA".parse().unwrap());
+        let (id, mut messages) = pubsub.register();
+        pubsub.subscribe(id, "__keyevent@0__:*", true);
+        let pubsub = RwLock::new(pubsub);
+        let mut conn = RedisServerConnection::new(tokio::io::duplex(1).0, DEFAULT_MAX_FRAME_SIZE);
+        conn.capture_responses();
+
+        let key = || "k".to_string();
+        for cmd in [
+            Command::LPUSH("l".to_string(), vec![b"a".to_vec()]),
+            Command::HSET("h".to_string(), vec![(b"f".to_vec(), b"v".to_vec())]),
+            Command::SADD("s".to_string(), vec![b"a".to_vec()]),
+            // Removing nothing changes nothing, so publishes nothing.
+            Command::SREM("s".to_string(), vec![b"missing".to_vec()]),
+            Command::ZADD("z".to_string(), vec![(1.0, b"a".to_vec())]),
+            Command::INCRBY(key(), 2),
+            Command::EXPIRE(key(), 100),
+            Command::PERSIST(key()),
+            Command::PERSIST(key()),
+            Command::EXPIRE(key(), -1),
+        ] {
+            execute_command(&mut conn, &mut StoreRef::Locked(&mut store), &pubsub, cmd).await;
+        }
+
+        let mut events = vec![];
+        while let Ok(message) = messages.try_recv() {
+            events.push((message.channel.trim_start_matches("__keyevent@0__:").to_string(), String::from_utf8(message.payload).unwrap()));
+        }
+        let expected = [("lpush", "l"), ("hset", "h"), ("sadd", "s"), ("zadd", "z"), ("incrby", "k"), ("expire", "k"), ("persist", "k"), ("del", "k")];
+        assert_eq!(expected.map(|(event, key)| (event.to_string(), key.to_string())).to_vec(), events);
     }
 }
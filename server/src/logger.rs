@@ -0,0 +1,40 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, Log, Metadata, Record};
+
+use super::config::LogLevel;
+
+/// Writes log records to stdout, warnings and errors to stderr.
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let line = format!("{}.{:03} {:<5} {}", now.as_secs(), now.subsec_millis(), record.level(), record.args());
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("{}", line),
+            _ => println!("{}", line),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
+
+/// Install the logger, later calls only adjust the level.
+pub fn init(level: LogLevel) {
+    let _ = log::set_logger(&LOGGER);
+    set_level(level);
+}
+
+pub fn set_level(level: LogLevel) {
+    log::set_max_level(level.filter());
+}
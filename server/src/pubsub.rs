@@ -0,0 +1,319 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use rusty_redis_core::pattern::glob_match;
+use rusty_redis_core::pubsub::Message;
+
+pub type SubscriberId = u64;
+
+/// Kinds of key change that can be notified, each switched on by its `notify-keyspace-events` flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventClass {
+    /// Type independent commands such as DEL and EXPIRE.
+    Generic,
+    String,
+    List,
+    Set,
+    Hash,
+    SortedSet,
+    Expired,
+    Evicted,
+}
+
+impl EventClass {
+    /// In the order the flags are listed by CONFIG GET, which is also what `A` stands for.
+    const ALL: [EventClass; 8] = [
+        EventClass::Generic, EventClass::String, EventClass::List, EventClass::Set,
+        EventClass::Hash, EventClass::SortedSet, EventClass::Expired, EventClass::Evicted,
+    ];
+
+    fn flag(self) -> char {
+        match self {
+            EventClass::Generic => 'g',
+            EventClass::String => '$',
+            EventClass::List => 'l',
+            EventClass::Set => 's',
+            EventClass::Hash => 'h',
+            EventClass::SortedSet => 'z',
+            EventClass::Expired => 'x',
+            EventClass::Evicted => 'e',
+        }
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// Which keyspace notifications are published, parsed from flags as in Redis' `notify-keyspace-events`.
+///
+/// `K` publishes to `__keyspace@0__:<key>` and `E` to `__keyevent@0__:<event>`, one of them
+/// has to be given along with the classes of event, e.g. `Ex` for expired key events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents {
+    keyspace: bool,
+    keyevent: bool,
+    classes: u16,
+}
+
+impl KeyspaceEvents {
+    fn all_classes() -> u16 {
+        EventClass::ALL.iter().fold(0, |classes, class| classes | class.bit())
+    }
+
+    pub fn enabled(&self, class: EventClass) -> bool {
+        (self.keyspace || self.keyevent) && self.classes & class.bit() != 0
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = KeyspaceEvents::default();
+        for flag in s.chars() {
+            match flag {
+                'K' => events.keyspace = true,
+                'E' => events.keyevent = true,
+                'A' => events.classes |= KeyspaceEvents::all_classes(),
+                _ => match EventClass::ALL.iter().find(|class| class.flag() == flag) {
+                    Some(class) => events.classes |= class.bit(),
+                    None => return Err(Error::new(ErrorKind::InvalidInput, format!("invalid keyspace event flag '{}'", flag))),
+                },
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut flags = if self.classes == KeyspaceEvents::all_classes() {
+            String::from("A")
+        } else {
+            EventClass::ALL.iter().filter(|class| self.classes & class.bit() != 0).map(|class| class.flag()).collect()
+        };
+        if self.keyspace {
+            flags.push('K');
+        }
+        if self.keyevent {
+            flags.push('E');
+        }
+        write!(f, "{}", flags)
+    }
+}
+
+/// Channels and patterns each subscribed connection listens on, along with
+/// where to send the messages published to them.
+#[derive(Default)]
+pub struct PubSub {
+    next_id: SubscriberId,
+    subscribers: HashMap<SubscriberId, Subscriber>,
+    channels: HashMap<String, HashSet<SubscriberId>>,
+    patterns: HashMap<String, HashSet<SubscriberId>>,
+    keyspace_events: KeyspaceEvents,
+}
+
+struct Subscriber {
+    sender: UnboundedSender<Message>,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+}
+
+impl Subscriber {
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        PubSub::default()
+    }
+
+    pub fn set_keyspace_events(&mut self, events: KeyspaceEvents) {
+        self.keyspace_events = events;
+    }
+
+    /// Add a connection that is about to subscribe, returning its id
+    /// and the receiver its messages arrive on.
+    pub fn register(&mut self) -> (SubscriberId, UnboundedReceiver<Message>) {
+        let (sender, receiver) = unbounded_channel();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscribers.insert(id, Subscriber { sender, channels: HashSet::new(), patterns: HashSet::new() });
+        (id, receiver)
+    }
+
+    /// Drop every subscription `id` holds, once it has left subscribed mode or disconnected.
+    pub fn unregister(&mut self, id: SubscriberId) {
+        let Some(subscriber) = self.subscribers.remove(&id) else {
+            return;
+        };
+        for channel in subscriber.channels {
+            remove_from(&mut self.channels, &channel, id);
+        }
+        for pattern in subscriber.patterns {
+            remove_from(&mut self.patterns, &pattern, id);
+        }
+    }
+
+    /// Subscribe `id` to a channel, or a pattern when `pattern` is set.
+    /// Returns how many subscriptions it holds afterwards.
+    pub fn subscribe(&mut self, id: SubscriberId, name: &str, pattern: bool) -> usize {
+        let Some(subscriber) = self.subscribers.get_mut(&id) else {
+            return 0;
+        };
+        let (names, registry) = if pattern {
+            (&mut subscriber.patterns, &mut self.patterns)
+        } else {
+            (&mut subscriber.channels, &mut self.channels)
+        };
+        if names.insert(name.to_string()) {
+            registry.entry(name.to_string()).or_default().insert(id);
+        }
+        subscriber.count()
+    }
+
+    /// Returns how many subscriptions `id` holds afterwards.
+    pub fn unsubscribe(&mut self, id: SubscriberId, name: &str, pattern: bool) -> usize {
+        let Some(subscriber) = self.subscribers.get_mut(&id) else {
+            return 0;
+        };
+        let (names, registry) = if pattern {
+            (&mut subscriber.patterns, &mut self.patterns)
+        } else {
+            (&mut subscriber.channels, &mut self.channels)
+        };
+        if names.remove(name) {
+            remove_from(registry, name, id);
+        }
+        subscriber.count()
+    }
+
+    /// How many channels and patterns `id` is subscribed to.
+    pub fn count(&self, id: SubscriberId) -> usize {
+        self.subscribers.get(&id).map_or(0, Subscriber::count)
+    }
+
+    /// Every channel, or every pattern when `pattern` is set, that `id` is subscribed to.
+    pub fn subscriptions(&self, id: SubscriberId, pattern: bool) -> Vec<String> {
+        match self.subscribers.get(&id) {
+            Some(subscriber) if pattern => subscriber.patterns.iter().cloned().collect(),
+            Some(subscriber) => subscriber.channels.iter().cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    /// Send `payload` to everyone subscribed to `channel` or to a pattern matching it,
+    /// returning how many messages were sent.
+    pub fn publish(&self, channel: &str, payload: &[u8]) -> usize {
+        let mut sent = 0;
+        let mut send = |id: &SubscriberId, pattern: Option<&str>| {
+            let Some(subscriber) = self.subscribers.get(id) else {
+                return;
+            };
+            let message = Message { channel: channel.to_string(), pattern: pattern.map(String::from), payload: payload.to_vec() };
+            // A send only fails once the connection has gone, it unregisters itself shortly after.
+            if subscriber.sender.send(message).is_ok() {
+                sent += 1;
+            }
+        };
+        for id in self.channels.get(channel).into_iter().flatten() {
+            send(id, None);
+        }
+        for (pattern, ids) in &self.patterns {
+            if glob_match(pattern.as_bytes(), channel.as_bytes()) {
+                ids.iter().for_each(|id| send(id, Some(pattern)));
+            }
+        }
+        sent
+    }
+
+    /// Publish that `event` happened to `key`, if notifications of its class are switched on.
+    pub fn notify(&self, class: EventClass, event: &str, key: &str) {
+        if !self.keyspace_events.enabled(class) {
+            return;
+        }
+        if self.keyspace_events.keyspace {
+            self.publish(&format!("__keyspace@0__:{}", key), event.as_bytes());
+        }
+        if self.keyspace_events.keyevent {
+            self.publish(&format!("__keyevent@0__:{}", event), key.as_bytes());
+        }
+    }
+}
+
+fn remove_from(registry: &mut HashMap<String, HashSet<SubscriberId>>, name: &str, id: SubscriberId) {
+    if let Some(ids) = registry.get_mut(name) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            registry.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_publish() {
+        let mut pubsub = PubSub::new();
+        let (a, mut a_messages) = pubsub.register();
+        let (b, mut b_messages) = pubsub.register();
+        assert_eq!(1, pubsub.subscribe(a, "news.uk", false));
+        assert_eq!(2, pubsub.subscribe(a, "news.*", true));
+        assert_eq!(1, pubsub.subscribe(b, "sport", false));
+
+        assert_eq!(2, pubsub.publish("news.uk", b"hello"));
+        assert_eq!(0, pubsub.publish("weather", b"rain"));
+        let direct = a_messages.try_recv().unwrap();
+        assert_eq!(("news.uk", None), (direct.channel.as_str(), direct.pattern.as_deref()));
+        assert_eq!(Some("news.*".to_string()), a_messages.try_recv().unwrap().pattern);
+        assert!(b_messages.try_recv().is_err());
+
+        assert_eq!(1, pubsub.unsubscribe(a, "news.uk", false));
+        assert_eq!(vec!["news.*".to_string()], pubsub.subscriptions(a, true));
+        assert_eq!(1, pubsub.count(a));
+        pubsub.unregister(a);
+        assert_eq!(0, pubsub.publish("news.uk", b"again"));
+        assert!(!pubsub.channels.contains_key("news.uk"));
+        assert!(pubsub.patterns.is_empty());
+    }
+
+    #[test]
+    fn test_notify() {
+        let mut pubsub = PubSub::new();
+        let (id, mut messages) = pubsub.register();
+        pubsub.subscribe(id, "__keyevent@0__:*", true);
+        pubsub.subscribe(id, "__keyspace@0__:user:1", false);
+
+        pubsub.notify(EventClass::Expired, "expired", "user:1");
+        assert!(messages.try_recv().is_err());
+
+        pubsub.set_keyspace_events("KEx".parse().unwrap());
+        pubsub.notify(EventClass::String, "set", "user:1");
+        assert!(messages.try_recv().is_err());
+        pubsub.notify(EventClass::Expired, "expired", "user:1");
+        let keyspace = messages.try_recv().unwrap();
+        assert_eq!(("__keyspace@0__:user:1", b"expired".to_vec()), (keyspace.channel.as_str(), keyspace.payload));
+        let keyevent = messages.try_recv().unwrap();
+        assert_eq!(("__keyevent@0__:expired", b"user:1".to_vec()), (keyevent.channel.as_str(), keyevent.payload));
+    }
+
+    #[test]
+    fn test_keyspace_events() {
+        for (flags, expected) in [("", ""), ("Ex", "xE"), ("g$K", "g$K"), ("AKE", "AKE"), ("Kglshz$xe", "AK")] {
+            let events: KeyspaceEvents = flags.parse().unwrap();
+            assert_eq!(expected, events.to_string());
+        }
+        let events: KeyspaceEvents = "A".parse().unwrap();
+        assert!(!events.enabled(EventClass::Generic));
+        assert!("Eq".parse::<KeyspaceEvents>().is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use std::any::TypeId;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Map, Position, Scope, AST};
+use sha1_smol::Sha1;
+use tokio::runtime::Handle;
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+
+use rusty_redis_core::command::Command;
+use rusty_redis_core::frame::DEFAULT_MAX_FRAME_SIZE;
+use rusty_redis_core::response::Response;
+
+use super::connection::RedisServerConnection;
+use super::pubsub::PubSub;
+use super::store::DataStore;
+use super::transaction::StoreRef;
+
+/// Most arguments `redis.call` takes after the command name.
+const MAX_CALL_ARGS: usize = 32;
+
+/// The function behind `redis.call`. `call` is Rhai's keyword for calling a function pointer,
+/// so `redis` is a pointer to this and `redis.pcall` a method on it.
+const CALL_FN: &str = "redis_call";
+
+/// How many operations a script runs between checks for SCRIPT KILL and the time limit.
+const PROGRESS_CHECK_OPS: u64 = 1024;
+
+/// Scripts compiled by EVAL or SCRIPT LOAD, keyed by the SHA1 of their source,
+/// and the context of the one running now, if any.
+pub struct Scripts {
+    engine: Engine,
+    cache: Mutex<HashMap<String, Arc<AST>>>,
+    running: Arc<Mutex<Option<RunContext>>>,
+    /// Milliseconds a script may run for, 0 for no limit.
+    time_limit: AtomicU64,
+}
+
+/// What the functions registered on the engine work on while a script runs.
+struct RunContext {
+    store: OwnedRwLockWriteGuard<DataStore>,
+    pubsub: Arc<RwLock<PubSub>>,
+    runtime: Handle,
+    started: Instant,
+    time_limit: Duration,
+    killed: bool,
+    /// Once a script has written it can't be stopped without leaving the store half updated.
+    wrote: bool,
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        let running = Arc::new(Mutex::new(None));
+        Scripts { engine: engine(&running), cache: Mutex::new(HashMap::new()), running, time_limit: AtomicU64::new(0) }
+    }
+
+    /// Stop scripts that run for longer than `millis`, or never when 0.
+    pub fn set_time_limit(&self, millis: u64) {
+        self.time_limit.store(millis, Ordering::Relaxed);
+    }
+
+    /// Compile `source` and cache it, returning its SHA1 along with the compiled script.
+    pub fn load(&self, source: &str) -> io::Result<(String, Arc<AST>)> {
+        let sha = Sha1::from(source).digest().to_string();
+        if let Some(ast) = self.cache.lock().unwrap().get(&sha) {
+            return Ok((sha, ast.clone()));
+        }
+        let ast = self.engine.compile(source)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("ERR Error compiling script: {}", e)))?;
+        let ast = Arc::new(ast);
+        self.cache.lock().unwrap().insert(sha.clone(), ast.clone());
+        Ok((sha, ast))
+    }
+
+    pub fn get(&self, sha: &str) -> io::Result<Arc<AST>> {
+        self.cache.lock().unwrap().get(&sha.to_ascii_lowercase()).cloned()
+            .ok_or(Error::new(ErrorKind::NotFound, "NOSCRIPT No matching script. Please use EVAL."))
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.cache.lock().unwrap().contains_key(&sha.to_ascii_lowercase())
+    }
+
+    pub fn flush(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Stop the running script, unless it has already written to the store.
+    pub fn kill(&self) -> io::Result<()> {
+        let mut running = self.running.lock().unwrap();
+        let Some(context) = running.as_mut() else {
+            return Err(Error::new(ErrorKind::NotFound, "NOTBUSY No scripts in execution right now."));
+        };
+        if context.wrote {
+            return Err(Error::new(ErrorKind::PermissionDenied, "UNKILLABLE Sorry the script already executed write commands against the dataset."));
+        }
+        context.killed = true;
+        Ok(())
+    }
+
+    /// Run a compiled script with the store locked throughout, so it is applied as a whole,
+    /// handing the lock back afterwards for EXEC to carry on with.
+    ///
+    /// Blocks on the commands the script calls, so has to be run off the async workers with
+    /// `spawn_blocking`. A script still running after the time limit is stopped, unless it has
+    /// already written, as stopping it then would leave the store half updated.
+    pub fn run(&self, ast: &AST, store: OwnedRwLockWriteGuard<DataStore>, pubsub: Arc<RwLock<PubSub>>,
+               keys: Vec<String>, args: Vec<Vec<u8>>) -> (Response, OwnedRwLockWriteGuard<DataStore>) {
+        let time_limit = Duration::from_millis(self.time_limit.load(Ordering::Relaxed));
+        *self.running.lock().unwrap() = Some(RunContext {
+            store, pubsub, runtime: Handle::current(), started: Instant::now(), time_limit, killed: false, wrote: false,
+        });
+
+        let mut scope = Scope::new();
+        scope.push_constant("redis", FnPtr::new(CALL_FN).expect("CALL_FN is a valid function name"));
+        scope.push_constant("KEYS", keys.into_iter().map(Dynamic::from).collect::<Array>());
+        scope.push_constant("ARGV", args.iter().map(|arg| Dynamic::from(String::from_utf8_lossy(arg).into_owned())).collect::<Array>());
+        let response = match self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, ast) {
+            Ok(value) => to_response(value),
+            Err(e) => Response::Error(error_message(&e)),
+        };
+
+        let context = self.running.lock().unwrap().take().expect("Script context cleared while it ran");
+        (response, context.store)
+    }
+}
+
+/// The engine scripts are compiled and run with, its `redis.call` and `redis.pcall` working on
+/// whatever `running` holds at the time.
+fn engine(running: &Arc<Mutex<Option<RunContext>>>) -> Engine {
+    let mut engine = Engine::new();
+    for arity in 1..=MAX_CALL_ARGS + 1 {
+        let call_types: Vec<_> = std::iter::repeat_n(TypeId::of::<Dynamic>(), arity).collect();
+        let pcall_types: Vec<_> = std::iter::once(TypeId::of::<FnPtr>()).chain(call_types.iter().copied()).collect();
+        // pcall hands errors back to the script as `#{err: message}` rather than stopping it.
+        for (name, arg_types, protected) in [(CALL_FN, call_types, false), ("pcall", pcall_types, true)] {
+            let running = running.clone();
+            let skip = usize::from(protected);
+            engine.register_raw_fn(name, &arg_types, move |_, args| {
+                match call(&running, &args[skip..])? {
+                    Response::Error(e) if protected => Ok(error_map(e)),
+                    Response::Error(e) => Err(EvalAltResult::ErrorRuntime(e.into(), Position::NONE).into()),
+                    response => Ok(to_dynamic(response)),
+                }
+            });
+        }
+    }
+
+    let running = running.clone();
+    engine.on_progress(move |ops| {
+        if ops % PROGRESS_CHECK_OPS != 0 {
+            return None;
+        }
+        let running = running.lock().unwrap();
+        let context = running.as_ref()?;
+        if context.killed {
+            Some("ERR Script killed by user with SCRIPT KILL".into())
+        } else if !context.wrote && !context.time_limit.is_zero() && context.started.elapsed() > context.time_limit {
+            Some("ERR Script timed out".into())
+        } else {
+            None
+        }
+    });
+    engine
+}
+
+/// Run one command for a script through the same dispatch as a client's, returning its response.
+fn call(running: &Mutex<Option<RunContext>>, args: &[&mut Dynamic]) -> Result<Response, Box<EvalAltResult>> {
+    // Held until the command is done, so SCRIPT KILL can't slip in between it writing and `wrote` being set.
+    let mut running = running.lock().unwrap();
+    let Some(context) = running.as_mut() else {
+        return Err(EvalAltResult::ErrorRuntime("ERR redis.call used outside of a script".into(), Position::NONE).into());
+    };
+    if context.killed {
+        return Err(EvalAltResult::ErrorTerminated("ERR Script killed by user with SCRIPT KILL".into(), Position::NONE).into());
+    }
+    let args = args.iter().map(|arg| to_bytes(arg)).collect::<Result<VecDeque<_>, _>>()?;
+    let cmd = match Command::parse_resp(args) {
+        Ok(cmd) if super::is_data_command(&cmd) => cmd,
+        Ok(_) => return Ok(Response::Error(String::from("ERR This command is not allowed from script"))),
+        Err(e) => return Ok(Response::Error(e.to_string())),
+    };
+
+    let dirty = context.store.dirty();
+    let mut conn = RedisServerConnection::new(tokio::io::duplex(1).0, DEFAULT_MAX_FRAME_SIZE);
+    conn.capture_responses();
+    let pubsub = context.pubsub.clone();
+    context.runtime.clone().block_on(super::execute_command(&mut conn, &mut StoreRef::Locked(&mut context.store), &pubsub, cmd));
+    context.wrote |= context.store.dirty() != dirty;
+    Ok(conn.take_captured().pop().unwrap_or(Response::Empty))
+}
+
+fn to_bytes(arg: &Dynamic) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    if arg.is_blob() {
+        Ok(arg.clone().into_blob().unwrap_or_default())
+    } else if arg.is_string() || arg.is_int() || arg.is_float() || arg.is_char() {
+        Ok(arg.to_string().into_bytes())
+    } else {
+        let message = format!("ERR Command arguments must be strings or numbers, not {}", arg.type_name());
+        Err(EvalAltResult::ErrorRuntime(message.into(), Position::NONE).into())
+    }
+}
+
+fn error_map(message: String) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("err".into(), message.into());
+    map.into()
+}
+
+/// A command's response as the script sees it, bulk strings become strings and arrays become arrays.
+fn to_dynamic(response: Response) -> Dynamic {
+    match response {
+        Response::Empty => Dynamic::UNIT,
+        Response::Error(e) => error_map(e),
+        Response::String(s) => s.into(),
+        Response::Int(i) => i.into(),
+        Response::Float(f) => (f as f64).into(),
+        Response::Array(values) => values.iter().map(|value| Dynamic::from(value.clone())).collect::<Array>().into(),
+        Response::Data(data) => String::from_utf8_lossy(&data).into_owned().into(),
+        Response::Multi(responses) | Response::Push(responses) => responses.into_iter().map(to_dynamic).collect::<Array>().into(),
+        Response::Map(pairs) => pairs.into_iter()
+            .map(|(field, value)| (String::from_utf8_lossy(&field).into(), String::from_utf8_lossy(&value).into_owned().into()))
+            .collect::<Map>()
+            .into(),
+    }
+}
+
+/// What a script returns as a reply, following Redis' Lua conversions: `true` is 1, `false` is nil,
+/// a float is truncated to an integer and a map with `err` or `ok` is an error or status reply.
+fn to_response(value: Dynamic) -> Response {
+    if value.is_unit() {
+        Response::Empty
+    } else if let Ok(b) = value.as_bool() {
+        if b { Response::Int(1) } else { Response::Empty }
+    } else if let Ok(i) = value.as_int() {
+        Response::Int(i)
+    } else if let Ok(f) = value.as_float() {
+        // Lua numbers come back from Redis scripts as integers, dropping any fraction.
+        Response::Int(f as i64)
+    } else if value.is_array() {
+        Response::Multi(value.cast::<Array>().into_iter().map(to_response).collect())
+    } else if value.is_blob() {
+        Response::Data(value.into_blob().unwrap_or_default())
+    } else if value.is_map() {
+        let map = value.cast::<Map>();
+        if let Some(e) = map.get("err") {
+            Response::Error(e.to_string())
+        } else if let Some(status) = map.get("ok") {
+            Response::String(status.to_string())
+        } else {
+            Response::Map(map.into_iter().map(|(field, value)| (field.as_bytes().to_vec(), value.to_string().into_bytes())).collect())
+        }
+    } else {
+        Response::Data(value.to_string().into_bytes())
+    }
+}
+
+/// The message a script failed with, as thrown or raised by `redis.call`, without Rhai's wrapping.
+fn error_message(e: &EvalAltResult) -> String {
+    match e {
+        EvalAltResult::ErrorRuntime(value, _) | EvalAltResult::ErrorTerminated(value, _) => value.to_string(),
+        EvalAltResult::ErrorInFunctionCall(_, _, inner, _) => error_message(inner),
+        e => format!("ERR Error running script: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn eval(scripts: &Arc<Scripts>, store: &Arc<RwLock<DataStore>>, source: &str, keys: &[&str], args: &[&str]) -> Response {
+        let (_, ast) = scripts.load(source).unwrap();
+        let (scripts, guard) = (scripts.clone(), store.clone().write_owned().await);
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+        let args = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        let pubsub = Arc::new(RwLock::new(PubSub::new()));
+        tokio::task::spawn_blocking(move || scripts.run(&ast, guard, pubsub, keys, args).0)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run() {
+        let scripts = Arc::new(Scripts::new());
+        scripts.set_time_limit(100);
+        let store = Arc::new(RwLock::new(DataStore::new(16)));
+
+        let source = r#"redis.call("SET", KEYS[0], ARGV[0]); redis.call("INCR", KEYS[0])"#;
+        assert_eq!(Response::Int(42), eval(&scripts, &store, source, &["n"], &["41"]).await);
+        assert_eq!(Some(&b"42"[..]), store.read().await.get("n").unwrap());
+        let (sha, _) = scripts.load(source).unwrap();
+        assert_eq!(40, sha.len());
+        assert!(scripts.exists(&sha.to_ascii_uppercase()));
+
+        let source = r#"let reply = redis.pcall("INCR", "missing", "extra"); [reply.err != (), redis.call("GET", "none")]"#;
+        assert_eq!(Response::Multi(vec![Response::Int(1), Response::Empty]), eval(&scripts, &store, source, &[], &[]).await);
+        assert!(matches!(eval(&scripts, &store, r#"redis.call("HGET", "n", "f")"#, &[], &[]).await, Response::Error(_)));
+        assert!(matches!(eval(&scripts, &store, r#"redis.call("MULTI")"#, &[], &[]).await, Response::Error(_)));
+        assert_eq!(Response::Int(-3), eval(&scripts, &store, "-3.7", &[], &[]).await);
+        assert_eq!(Response::Error("ERR Script timed out".to_string()), eval(&scripts, &store, "loop {}", &[], &[]).await);
+        let source = r#"redis.call("SET", "w", "1"); let t = timestamp(); while t.elapsed < 0.3 {} redis.call("INCR", "w")"#;
+        assert_eq!(Response::Int(2), eval(&scripts, &store, source, &[], &[]).await);
+        assert_eq!(Some(&b"2"[..]), store.read().await.get("w").unwrap());
+
+        assert!(scripts.load("let = ;").is_err());
+        assert!(scripts.kill().is_err());
+        scripts.flush();
+        assert!(scripts.get(&sha).is_err());
+    }
+}
@@ -0,0 +1,371 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{error, info};
+use tokio::sync::RwLock;
+
+use super::sorted_set::SortedSet;
+use super::store::DataStore;
+use super::value::Value;
+
+const MAGIC: &[u8; 4] = b"RRDB";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const TYPE_STRING: u8 = 0x00;
+const TYPE_LIST: u8 = 0x01;
+const TYPE_HASH: u8 = 0x02;
+const TYPE_SET: u8 = 0x03;
+const TYPE_ZSET: u8 = 0x04;
+const OP_EOF: u8 = 0xFF;
+
+/// Save whenever at least `changes` writes happened within the last `seconds`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// A single key as it is written to or read from a snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotEntry {
+    pub key: String,
+    pub value: Arc<Value>,
+    /// Unix time in milliseconds at which the key expires.
+    pub expire_at: Option<u128>,
+}
+
+/// Writes the DataStore to disk and restores it on startup.
+pub struct Snapshotter {
+    path: PathBuf,
+    in_progress: AtomicBool,
+}
+
+impl Snapshotter {
+    pub fn new(path: PathBuf) -> Self {
+        Snapshotter { path, in_progress: AtomicBool::new(false) }
+    }
+
+    /// Populate the store from the snapshot file, if there is one.
+    /// Returns the number of keys restored.
+    pub fn load(&self, store: &mut DataStore) -> io::Result<usize> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let entries = decode(BufReader::new(file))?;
+        let now = now_millis();
+        let mut restored = 0;
+        for entry in entries.into_iter().filter(|e| e.expire_at.is_none_or(|at| at > now)) {
+            store.restore(entry);
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    /// Whether any save rule has been met.
+    pub fn should_save(&self, store: &DataStore, save_rules: &[SaveRule]) -> bool {
+        let elapsed = store.last_save().elapsed().unwrap_or_default();
+        save_rules.iter()
+            .any(|rule| store.dirty() >= rule.changes && elapsed >= Duration::from_secs(rule.seconds))
+    }
+
+    /// Write a snapshot, resolving once it is on disk.
+    ///
+    /// The store is only locked while entries are collected, values are shared
+    /// through their Arc so the file is written without holding the lock.
+    pub async fn save(&self, store: &RwLock<DataStore>) -> io::Result<()> {
+        if self.in_progress.swap(true, Ordering::SeqCst) {
+            return Err(Error::new(ErrorKind::WouldBlock, "Background save already in progress"));
+        }
+        let result = self.write(store).await;
+        self.in_progress.store(false, Ordering::SeqCst);
+        result
+    }
+
+    /// Start a snapshot in the background.
+    /// Returns false if a save is already running.
+    pub fn bgsave(self: &Arc<Self>, store: Arc<RwLock<DataStore>>) -> bool {
+        if self.in_progress.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let snapshotter = self.clone();
+        tokio::spawn(async move {
+            match snapshotter.write(&store).await {
+                Ok(_) => info!("Background saving terminated with success"),
+                Err(e) => error!("Background saving failed: {}", e),
+            }
+            snapshotter.in_progress.store(false, Ordering::SeqCst);
+        });
+        true
+    }
+
+    async fn write(&self, store: &RwLock<DataStore>) -> io::Result<()> {
+        let (entries, dirty) = {
+            let store_read = store.read().await;
+            (store_read.entries(), store_read.dirty())
+        };
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || write_snapshot(&path, &entries))
+            .await
+            .map_err(Error::other)??;
+        store.write().await.saved(dirty);
+        Ok(())
+    }
+}
+
+/// Write the snapshot to a temporary file first, so a crash mid-save never clobbers the last good snapshot.
+pub fn write_snapshot(path: &Path, entries: &[SnapshotEntry]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    encode(&mut writer, entries)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// [Magic][Version]([Type][ExpireAt][LNK][K][Value])*[EOF]
+///
+/// A string value is [LNV][V], a list is [Count]([LNV][V])* a hash is [Count]([LNF][F][LNV][V])*
+/// a set is [Count]([LNV][V])* and a sorted set is [Count]([LNV][V][Score])*.
+pub fn encode<W: Write>(writer: &mut W, entries: &[SnapshotEntry]) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    for entry in entries {
+        let value_type = match *entry.value {
+            Value::String(_) => TYPE_STRING,
+            Value::List(_) => TYPE_LIST,
+            Value::Hash(_) => TYPE_HASH,
+            Value::Set(_) => TYPE_SET,
+            Value::SortedSet(_) => TYPE_ZSET,
+        };
+        writer.write_all(&[value_type])?;
+        writer.write_all(&(entry.expire_at.unwrap_or(0) as u64).to_le_bytes())?;
+        write_bytes(writer, entry.key.as_bytes())?;
+        match &*entry.value {
+            Value::String(data) => write_bytes(writer, data)?,
+            Value::List(list) => {
+                writer.write_all(&(list.len() as u32).to_le_bytes())?;
+                for item in list {
+                    write_bytes(writer, item)?;
+                }
+            },
+            Value::Hash(hash) => {
+                writer.write_all(&(hash.len() as u32).to_le_bytes())?;
+                for (field, value) in hash {
+                    write_bytes(writer, field)?;
+                    write_bytes(writer, value)?;
+                }
+            },
+            Value::Set(set) => {
+                writer.write_all(&(set.len() as u32).to_le_bytes())?;
+                for member in set {
+                    write_bytes(writer, member)?;
+                }
+            },
+            Value::SortedSet(zset) => {
+                writer.write_all(&(zset.len() as u32).to_le_bytes())?;
+                for (member, score) in zset.iter() {
+                    write_bytes(writer, member)?;
+                    writer.write_all(&score.to_le_bytes())?;
+                }
+            },
+        }
+    }
+    writer.write_all(&[OP_EOF])
+}
+
+pub fn decode<R: Read>(mut reader: R) -> io::Result<Vec<SnapshotEntry>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a rusty-redis snapshot"));
+    }
+    let version = read_u32(&mut reader)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported snapshot version {}", version)));
+    }
+
+    let mut entries = vec![];
+    loop {
+        let mut op = [0u8; 1];
+        reader.read_exact(&mut op)?;
+        let value_type = match op[0] {
+            OP_EOF => return Ok(entries),
+            t @ (TYPE_STRING | TYPE_LIST | TYPE_HASH | TYPE_SET | TYPE_ZSET) => t,
+            t => return Err(Error::new(ErrorKind::InvalidData, format!("Unknown snapshot entry type {}", t))),
+        };
+        let expire_at = read_u64(&mut reader)?;
+        let key = String::from_utf8(read_bytes(&mut reader)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let value = match value_type {
+            TYPE_STRING => Value::String(read_bytes(&mut reader)?),
+            TYPE_LIST => {
+                let len = read_u32(&mut reader)?;
+                Value::List((0..len).map(|_| read_bytes(&mut reader)).collect::<io::Result<_>>()?)
+            },
+            TYPE_HASH => {
+                let len = read_u32(&mut reader)?;
+                Value::Hash((0..len)
+                    .map(|_| Ok((read_bytes(&mut reader)?, read_bytes(&mut reader)?)))
+                    .collect::<io::Result<_>>()?)
+            },
+            TYPE_SET => {
+                let len = read_u32(&mut reader)?;
+                Value::Set((0..len).map(|_| read_bytes(&mut reader)).collect::<io::Result<_>>()?)
+            },
+            _ => {
+                let len = read_u32(&mut reader)?;
+                let mut zset = SortedSet::new();
+                for _ in 0..len {
+                    let member = read_bytes(&mut reader)?;
+                    zset.insert(member, f64::from_bits(read_u64(&mut reader)?));
+                }
+                Value::SortedSet(zset)
+            },
+        };
+        entries.push(SnapshotEntry {
+            key,
+            value: Arc::new(value),
+            expire_at: (expire_at != 0).then_some(expire_at as u128),
+        });
+    }
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u32(reader)? as usize;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet, VecDeque};
+
+    use super::*;
+
+    fn string(value: &[u8]) -> Arc<Value> {
+        Arc::new(Value::String(value.to_vec()))
+    }
+
+    fn zset() -> SortedSet {
+        let mut zset = SortedSet::new();
+        zset.insert(b"low".to_vec(), f64::NEG_INFINITY);
+        zset.insert(b"mid".to_vec(), 0.5);
+        zset.insert(b"high".to_vec(), 1e10);
+        zset
+    }
+
+    fn entries() -> Vec<SnapshotEntry> {
+        vec![
+            SnapshotEntry { key: String::from("no-ttl"), value: string(b"value1"), expire_at: None },
+            SnapshotEntry { key: String::from("ttl"), value: string(b"value2"), expire_at: Some(now_millis() + 60_000) },
+            SnapshotEntry { key: String::from("empty"), value: string(b""), expire_at: None },
+            SnapshotEntry {
+                key: String::from("list"),
+                value: Arc::new(Value::List(VecDeque::from([b"a".to_vec(), vec![], b"c".to_vec()]))),
+                expire_at: None,
+            },
+            SnapshotEntry {
+                key: String::from("hash"),
+                value: Arc::new(Value::Hash(HashMap::from([(b"f".to_vec(), b"v".to_vec()), (vec![], vec![0, 255])]))),
+                expire_at: Some(now_millis() + 60_000),
+            },
+            SnapshotEntry {
+                key: String::from("set"),
+                value: Arc::new(Value::Set(HashSet::from([b"a".to_vec(), b"b".to_vec()]))),
+                expire_at: None,
+            },
+            SnapshotEntry { key: String::from("zset"), value: Arc::new(Value::SortedSet(zset())), expire_at: None },
+        ]
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut buf = vec![];
+        // Built once, as each call computes its expiries from the current time.
+        let entries = entries();
+        encode(&mut buf, &entries).unwrap();
+        assert_eq!(entries, decode(buf.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let mut buf = vec![];
+        encode(&mut buf, &entries()).unwrap();
+
+        assert!(decode(&buf[..buf.len() - 1]).is_err());
+
+        let mut bad_magic = buf.clone();
+        bad_magic[0] = b'X';
+        assert!(decode(bad_magic.as_slice()).is_err());
+
+        let mut bad_version = buf.clone();
+        bad_version[4..8].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(decode(bad_version.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_load_drops_expired() {
+        let path = std::env::temp_dir().join(format!("rusty-redis-test-{}.rrdb", std::process::id()));
+        let mut expired = entries();
+        expired.push(SnapshotEntry { key: String::from("expired"), value: string(b"old"), expire_at: Some(1) });
+        write_snapshot(&path, &expired).unwrap();
+
+        let snapshotter = Snapshotter::new(path.clone());
+        let mut store = DataStore::new(4);
+        assert_eq!(7, snapshotter.load(&mut store).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert!(store.get("expired").unwrap().is_none());
+        assert_eq!(b"value2".to_vec(), store.get("ttl").unwrap().unwrap());
+        assert_eq!(3, store.llen("list").unwrap());
+        assert_eq!(Some(b"v".to_vec()), store.hget("hash", b"f").unwrap());
+        assert!(store.sismember("set", b"b").unwrap());
+        assert_eq!(Some(1), store.zrank("zset", b"mid").unwrap());
+        assert_eq!(0, store.dirty());
+    }
+
+    #[test]
+    fn test_should_save() {
+        let snapshotter = Snapshotter::new(PathBuf::from("unused.rrdb"));
+        let rules = vec![SaveRule { seconds: 0, changes: 2 }];
+        let mut store = DataStore::new(4);
+        store.insert("a", b"1".to_vec(), 0);
+        assert!(!snapshotter.should_save(&store, &rules));
+        store.insert("b", b"2".to_vec(), 0);
+        assert!(snapshotter.should_save(&store, &rules));
+        store.saved(2);
+        assert!(!snapshotter.should_save(&store, &rules));
+        assert!(!snapshotter.should_save(&store, &[]));
+    }
+}
@@ -0,0 +1,158 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use rusty_redis_core::command::ScoreBound;
+
+use super::tree::AVLTree;
+
+/// Members ordered by score, ties broken by comparing the members themselves.
+///
+/// Scores are looked up by member through the map, while the tree keeps
+/// the ordering used for ranks and ranges.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    tree: AVLTree<ScoredMember>,
+}
+
+#[derive(Clone, Debug)]
+struct ScoredMember {
+    score: f64,
+    member: Vec<u8>,
+}
+
+impl Ord for ScoredMember {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.total_cmp(&other.score).then_with(|| self.member.cmp(&other.member))
+    }
+}
+
+impl PartialOrd for ScoredMember {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ScoredMember {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScoredMember {}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        SortedSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Add `member` or move it to a new score. Returns true if it was not already in the set.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.tree.delete(&ScoredMember { score: previous, member: member.clone() });
+        }
+        self.tree.insert(ScoredMember { score, member });
+        previous.is_none()
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.tree.delete(&ScoredMember { score, member: member.to_vec() });
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Zero based position of `member`, ordered from the lowest score.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.tree.rank(&ScoredMember { score, member: member.to_vec() })
+    }
+
+    /// Members between the `start` and `stop` ranks inclusive, with their scores.
+    pub fn range(&self, start: usize, stop: usize) -> Vec<(&[u8], f64)> {
+        self.tree.range(start, stop)
+            .into_iter()
+            .map(|entry| (entry.member.as_slice(), entry.score))
+            .collect()
+    }
+
+    /// Members with a score between `min` and `max`, with their scores.
+    pub fn range_by_score(&self, min: ScoreBound, max: ScoreBound) -> Vec<(&[u8], f64)> {
+        let start = self.tree.partition_point(|entry| match min {
+            ScoreBound::Inclusive(min) => entry.score < min,
+            ScoreBound::Exclusive(min) => entry.score <= min,
+        });
+        let end = self.tree.partition_point(|entry| match max {
+            ScoreBound::Inclusive(max) => entry.score <= max,
+            ScoreBound::Exclusive(max) => entry.score < max,
+        });
+        if start >= end {
+            return vec![];
+        }
+        self.range(start, end - 1)
+    }
+
+    /// Every member with its score, from the lowest score.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], f64)> {
+        self.tree.iter().map(|entry| (entry.member.as_slice(), entry.score))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn members<'a>(entries: &[(&'a [u8], f64)]) -> Vec<&'a [u8]> {
+        entries.iter().map(|(member, _)| *member).collect()
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut set = SortedSet::new();
+        assert!(set.insert(b"b".to_vec(), 2.0));
+        assert!(set.insert(b"a".to_vec(), 2.0));
+        assert!(set.insert(b"c".to_vec(), 1.0));
+        assert!(!set.insert(b"c".to_vec(), 3.0));
+
+        assert_eq!(vec![b"a".as_slice(), b"b", b"c"], members(&set.iter().collect::<Vec<_>>()));
+        assert_eq!(Some(3.0), set.score(b"c"));
+        assert_eq!(Some(2), set.rank(b"c"));
+        assert_eq!(None, set.rank(b"d"));
+
+        assert!(set.remove(b"a"));
+        assert!(!set.remove(b"a"));
+        assert_eq!(2, set.len());
+        assert_eq!(Some(0), set.rank(b"b"));
+    }
+
+    #[test]
+    fn test_range_by_score() {
+        let mut set = SortedSet::new();
+        for (i, member) in [b"a", b"b", b"c", b"d", b"e"].iter().enumerate() {
+            set.insert(member.to_vec(), i as f64);
+        }
+        assert_eq!(vec![b"b".as_slice(), b"c", b"d"],
+            members(&set.range_by_score(ScoreBound::Inclusive(1.0), ScoreBound::Inclusive(3.0))));
+        assert_eq!(vec![b"c".as_slice()],
+            members(&set.range_by_score(ScoreBound::Exclusive(1.0), ScoreBound::Exclusive(3.0))));
+        assert_eq!(5, set.range_by_score(ScoreBound::Inclusive(f64::NEG_INFINITY), ScoreBound::Inclusive(f64::INFINITY)).len());
+        assert!(set.range_by_score(ScoreBound::Inclusive(3.0), ScoreBound::Inclusive(1.0)).is_empty());
+        assert_eq!(vec![(b"d".as_slice(), 3.0), (b"e".as_slice(), 4.0)], set.range(3, 10));
+    }
+}
@@ -1,23 +1,43 @@
+mod evict;
+mod hash;
+mod list;
+mod set;
+mod zset;
+
+pub use self::evict::EvictionPolicy;
+pub use self::set::SetOp;
+
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Error, ErrorKind};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH, Duration};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{error, trace};
+use rusty_redis_core::command::{format_score, Command, Expiry, KeyValue, SetCondition, SetOptions};
+use rusty_redis_core::pattern::glob_match;
 
+use super::aof::AppendOnlyFile;
+use super::snapshot::SnapshotEntry;
 use super::table::HTable;
+use super::value::{wrong_type, Value};
+
+/// Keys expired between looks at the clock during an expire cycle, as Redis' ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP.
+const EXPIRE_KEYS_PER_LOOP: usize = 20;
 
 #[derive(Eq, Clone)]
-struct TTL {
+struct Ttl {
     expire: u128,
     id: u128
 }
 
-impl PartialEq for TTL {
+impl PartialEq for Ttl {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
     }
 }
 
-impl Ord for TTL {
+impl Ord for Ttl {
     fn cmp(&self, other: &Self) -> Ordering {
         match self.expire.cmp(&other.expire) {
             Ordering::Less => Ordering::Less,
@@ -27,16 +47,33 @@ impl Ord for TTL {
     }
 }
 
-impl PartialOrd for TTL {
+impl PartialOrd for Ttl {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.expire.partial_cmp(&other.expire)
+        Some(self.cmp(other))
     }
 }
 
+/// A key some connection has WATCHed, bumped on every write so EXEC can tell it changed.
+struct Watched {
+    watchers: usize,
+    version: u64,
+}
+
 pub struct DataStore {
-    cache: HTable,
-    ttls: BTreeMap<TTL, String>,
-    cache_ttls: HashMap<String, TTL>
+    cache: HTable<Value>,
+    ttls: BTreeMap<Ttl, String>,
+    /// The TTL of each key that has one, and where the key sits in `volatile`.
+    cache_ttls: HashMap<String, (Ttl, usize)>,
+    /// Every key with a TTL in no particular order, so eviction can pick one uniformly at random.
+    volatile: Vec<String>,
+    next_ttl_id: u128,
+    dirty: u64,
+    last_save: SystemTime,
+    aof: Option<AppendOnlyFile>,
+    watched: HashMap<String, Watched>,
+    /// Approximate bytes the store may hold before keys are evicted, 0 for no limit.
+    maxmemory: u64,
+    eviction_policy: EvictionPolicy,
 }
 
 impl DataStore {
@@ -45,60 +82,610 @@ impl DataStore {
         let ttls = BTreeMap::new();
         let cache_ttls = HashMap::new();
 
-        DataStore{cache, ttls, cache_ttls}
+        DataStore{cache, ttls, cache_ttls, volatile: vec![], next_ttl_id: 0, dirty: 0, last_save: SystemTime::now(), aof: None, watched: HashMap::new(),
+            maxmemory: 0, eviction_policy: EvictionPolicy::default()}
     }
 
-    pub fn keys(&self) -> &Vec<String> {
+    /// Every key matching the glob `pattern`, or every key when there is none, leaving out any past their TTL.
+    pub fn keys(&self, pattern: Option<&str>) -> Vec<String> {
         self.cache.keys()
+            .filter(|key| !self.is_due(key))
+            .filter(|key| pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), key.as_bytes())))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Continue a SCAN from `cursor`, looking at roughly `count` keys and returning those
+    /// that match `pattern` and `kind`, along with the cursor to continue from.
+    pub fn scan(&self, cursor: u64, pattern: Option<&str>, count: u64, kind: Option<&str>) -> (u64, Vec<String>) {
+        let mut keys = vec![];
+        let mut cursor = cursor as usize;
+        // Bound the work done on a sparse table, which may have many empty buckets in a row.
+        let mut max_iterations = count.max(1).saturating_mul(10);
+        loop {
+            cursor = self.cache.scan(cursor, |key, value| {
+                if !self.is_due(key) && kind.is_none_or(|kind| kind.eq_ignore_ascii_case(value.type_name())) {
+                    keys.push(key.to_string());
+                }
+            });
+            max_iterations -= 1;
+            if cursor == 0 || max_iterations == 0 || keys.len() as u64 >= count {
+                break;
+            }
+        }
+        if let Some(pattern) = pattern {
+            keys.retain(|key| glob_match(pattern.as_bytes(), key.as_bytes()));
+        }
+        (cursor as u64, keys)
     }
 
-    pub fn get(&self, key: &str) -> Option<Arc<Vec<u8>>> {
-        self.cache.get(key)
+    /// The string stored at `key`, or WRONGTYPE if it holds another type of value.
+    pub fn get(&self, key: &str) -> io::Result<Option<&[u8]>> {
+        match self.cache.get(key).map(|value| &**value) {
+            Some(Value::String(data)) => Ok(Some(data)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
     }
 
+    /// A plain SET expiring `ttl` milliseconds from now, or never when 0.
+    #[cfg(test)]
     pub fn insert(&mut self, key: &str, value: Vec<u8>, ttl: u64) {
-        if ttl != 0 {
-            let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-            let expire = current_time.checked_add(Duration::from_millis(ttl)).unwrap();
-            let ttl = TTL{expire: expire.as_millis(), id: current_time.as_nanos()};
-            println!("Inserted ({},{})", ttl.expire, ttl.id);
-            self.ttls.insert(ttl.clone(), key.to_string());
-            self.cache_ttls.insert(key.to_string(), ttl);
+        let expire_at = (ttl != 0).then(|| expire_in(ttl));
+        self.write_string(key, value, expire_at);
+    }
+
+    /// SET with its conditions and expiry. Returns whether the value was written,
+    /// along with the previous value when `options.get` asks for it.
+    pub fn insert_with(&mut self, key: &str, value: Vec<u8>, options: &SetOptions) -> io::Result<(bool, Option<Vec<u8>>)> {
+        let previous = if options.get {
+            self.get(key)?.map(<[u8]>::to_vec)
+        } else {
+            None
+        };
+        let written = match options.condition {
+            Some(SetCondition::NotExists) => !self.cache.contains_key(key),
+            Some(SetCondition::Exists) => self.cache.contains_key(key),
+            None => true,
+        };
+        if !written {
+            return Ok((false, previous));
+        }
+        let expire_at = match options.expiry {
+            Some(Expiry::In(ttl)) => Some(expire_in(ttl)),
+            Some(Expiry::At(at)) => Some(at as u128),
+            Some(Expiry::Keep) => self.cache_ttls.get(key).map(|(ttl, _)| ttl.expire),
+            None => None,
+        };
+        match expire_at {
+            // An expiry already in the past removes the key rather than storing it.
+            Some(expire_at) if expire_at <= now_millis() => { self.delete(key); },
+            _ => self.write_string(key, value, expire_at),
         }
-        self.cache.insert(key, value);
-        println!("Insert: cache_ttls:{}, ttls:{}, cache:{}", self.cache_ttls.len(), self.ttls.len(), self.cache.len());
+        Ok((true, previous))
     }
 
-    pub fn delete(&mut self, key: &str) {
-        if let Some(ttl) = self.cache_ttls.remove(key) {
-            self.ttls.remove(&ttl);
+    /// Store a string, replacing any TTL with `expire_at`. The write is logged with
+    /// an absolute expiry so a replay does not depend on when the log was written.
+    fn write_string(&mut self, key: &str, value: Vec<u8>, expire_at: Option<u128>) {
+        if self.aof.is_some() {
+            let options = match expire_at {
+                Some(expire_at) => SetOptions::new().pxat(expire_at as u64),
+                None => SetOptions::new(),
+            };
+            self.log(&Command::SET(key.to_string(), value.clone(), options));
+        }
+        self.store_string(key, value, expire_at);
+    }
+
+    /// Store a string without logging it.
+    fn store_string(&mut self, key: &str, value: Vec<u8>, expire_at: Option<u128>) {
+        self.clear_ttl(key);
+        if let Some(expire_at) = expire_at {
+            self.set_expire_at(key, expire_at);
+        }
+        self.cache.insert(key, Value::String(value));
+        self.touch(key);
+        trace!("Insert: cache_ttls:{}, ttls:{}, cache:{}", self.cache_ttls.len(), self.ttls.len(), self.cache.len());
+    }
+
+    /// The string at each of `keys`, None for a missing key or one holding another type of value.
+    pub fn mget(&self, keys: &[String]) -> Vec<Option<Vec<u8>>> {
+        keys.iter()
+            .map(|key| self.get(key).ok().flatten().map(<[u8]>::to_vec))
+            .collect()
+    }
+
+    /// Store every pair as a SET without expiry would, logged as a single record
+    /// so a replay never applies half of them.
+    pub fn mset(&mut self, pairs: Vec<KeyValue>) {
+        if self.aof.is_some() {
+            self.log(&Command::MSET(pairs.clone()));
+        }
+        for (key, value) in pairs {
+            self.store_string(&key, value, None);
+        }
+    }
+
+    /// MSET only when none of the keys exist. Returns whether the pairs were stored.
+    pub fn msetnx(&mut self, pairs: Vec<KeyValue>) -> bool {
+        if pairs.iter().any(|(key, _)| self.cache.contains_key(key)) {
+            return false;
+        }
+        self.mset(pairs);
+        true
+    }
+
+    /// Add `increment` to the integer stored at `key`, a missing key counts as 0.
+    /// Any TTL on the key is kept. Returns the value afterwards.
+    pub fn incr_by(&mut self, key: &str, increment: i64) -> io::Result<i64> {
+        let current = match self.get(key)? {
+            Some(data) => std::str::from_utf8(data).ok()
+                .and_then(|s| s.parse::<i64>().ok())
+                .ok_or(Error::new(ErrorKind::InvalidData, "value is not an integer or out of range"))?,
+            None => 0,
+        };
+        let updated = current.checked_add(increment)
+            .ok_or(Error::new(ErrorKind::InvalidInput, "increment or decrement would overflow"))?;
+        self.log(&Command::INCRBY(key.to_string(), increment));
+        self.set_keeping_ttl(key, updated.to_string().into_bytes());
+        Ok(updated)
+    }
+
+    pub fn decr_by(&mut self, key: &str, decrement: i64) -> io::Result<i64> {
+        let increment = decrement.checked_neg()
+            .ok_or(Error::new(ErrorKind::InvalidInput, "decrement would overflow"))?;
+        self.incr_by(key, increment)
+    }
+
+    /// Add `increment` to the number stored at `key`, a missing key counts as 0.
+    /// Any TTL on the key is kept. Returns the value afterwards.
+    pub fn incr_by_float(&mut self, key: &str, increment: f64) -> io::Result<f64> {
+        let current = match self.get(key)? {
+            Some(data) => std::str::from_utf8(data).ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|value| value.is_finite())
+                .ok_or(Error::new(ErrorKind::InvalidData, "value is not a valid float"))?,
+            None => 0.0,
+        };
+        let updated = current + increment;
+        if !updated.is_finite() {
+            return Err(Error::new(ErrorKind::InvalidInput, "increment would produce NaN or Infinity"));
+        }
+        // Logged as the value it came to, so replaying it can't round differently.
+        let value = format_score(updated).into_bytes();
+        self.log(&Command::SET(key.to_string(), value.clone(), SetOptions::new().keep_ttl()));
+        self.set_keeping_ttl(key, value);
+        Ok(updated)
+    }
+
+    /// Replace the string at `key` without logging it or touching its TTL.
+    fn set_keeping_ttl(&mut self, key: &str, value: Vec<u8>) {
+        match self.cache.get_mut(key).map(Arc::make_mut) {
+            Some(Value::String(data)) => *data = value,
+            _ => self.cache.insert(key, Value::String(value)),
+        }
+        self.touch(key);
+    }
+
+    /// Insert a key loaded from a snapshot, keeping its absolute expiry.
+    pub fn restore(&mut self, entry: SnapshotEntry) {
+        self.clear_ttl(&entry.key);
+        if let Some(expire_at) = entry.expire_at {
+            self.set_expire_at(&entry.key, expire_at);
         }
+        self.cache.insert(&entry.key, Arc::unwrap_or_clone(entry.value));
+    }
+
+    /// Every key not yet past its TTL, with its value and absolute expiry, as written to a snapshot.
+    pub fn entries(&self) -> Vec<SnapshotEntry> {
+        self.cache.iter()
+            .filter(|(key, _)| !self.is_due(key))
+            .map(|(key, value)| SnapshotEntry {
+                key: key.to_string(),
+                value: value.clone(),
+                expire_at: self.cache_ttls.get(key).map(|(ttl, _)| ttl.expire),
+            })
+            .collect()
+    }
+
+    /// Number of writes since the last successful save.
+    pub fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    pub fn last_save(&self) -> SystemTime {
+        self.last_save
+    }
+
+    /// Record a completed save that captured `dirty` changes,
+    /// writes that landed while the save was running still count towards the next one.
+    pub fn saved(&mut self, dirty: u64) {
+        self.dirty = self.dirty.saturating_sub(dirty);
+        self.last_save = SystemTime::now();
+    }
+
+    /// Start recording every write to the append only file.
+    pub fn attach_aof(&mut self, aof: AppendOnlyFile) {
+        self.aof = Some(aof);
+    }
+
+    pub fn aof(&self) -> Option<&AppendOnlyFile> {
+        self.aof.as_ref()
+    }
+
+    pub fn aof_mut(&mut self) -> Option<&mut AppendOnlyFile> {
+        self.aof.as_mut()
+    }
+
+    /// Start tracking writes to `key`, returning its current version.
+    pub fn watch(&mut self, key: &str) -> u64 {
+        let watched = self.watched.entry(key.to_string()).or_insert(Watched { watchers: 0, version: 0 });
+        watched.watchers += 1;
+        watched.version
+    }
+
+    /// Stop tracking `key` for one of the connections watching it.
+    pub fn unwatch(&mut self, key: &str) {
+        if let Some(watched) = self.watched.get_mut(key) {
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// Version of a watched key, compared with the one `watch` returned to tell whether it was written since.
+    pub fn version(&self, key: &str) -> u64 {
+        self.watched.get(key).map_or(0, |watched| watched.version)
+    }
+
+    /// Count a write to `key` towards the next save, count its size again
+    /// and invalidate anyone watching it.
+    fn touch(&mut self, key: &str) {
+        self.dirty += 1;
+        self.cache.refresh(key);
+        if let Some(watched) = self.watched.get_mut(key) {
+            watched.version += 1;
+        }
+    }
+
+    fn log(&mut self, cmd: &Command) {
+        if let Some(aof) = self.aof.as_mut() {
+            if let Err(e) = aof.append(cmd) {
+                error!("Failed to append to append only file: {}", e);
+            }
+        }
+    }
+
+    /// Drop a key whose value was emptied by the command just logged, so it is not logged again.
+    fn remove_empty(&mut self, key: &str) {
+        self.clear_ttl(key);
         self.cache.delete(key);
-        println!("Delete: cache_ttls:{}, ttls:{}, cache:{}", self.cache_ttls.len(), self.ttls.len(), self.cache.len());
     }
 
-    pub fn expire(&mut self) -> Option<Vec<String>> {
-        let mut expired_keys = vec![];
-        while let Some(key) = self.try_expire() {
-            expired_keys.push(key);
+    /// Milliseconds until `key` expires, `Some(None)` if it never does and `None` if there is no such key.
+    pub fn pttl(&self, key: &str) -> Option<Option<u64>> {
+        if !self.cache.contains_key(key) {
+            return None;
+        }
+        Some(self.cache_ttls.get(key).map(|(ttl, _)| ttl.expire.saturating_sub(now_millis()) as u64))
+    }
+
+    /// Expire `key` `millis` milliseconds from now, see [`DataStore::expire_at`].
+    pub fn expire_in(&mut self, key: &str, millis: i64) -> bool {
+        let at = (now_millis() as i64).saturating_add(millis);
+        self.expire_at(key, at)
+    }
+
+    /// Expire `key` at `at` milliseconds since the unix epoch, replacing any TTL it had.
+    /// A time already past deletes the key. Returns false if there is no such key.
+    pub fn expire_at(&mut self, key: &str, at: i64) -> bool {
+        if !self.cache.contains_key(key) {
+            return false;
+        }
+        if at <= 0 || at as u128 <= now_millis() {
+            self.delete(key);
+            return true;
+        }
+        self.log(&Command::PEXPIREAT(key.to_string(), at));
+        self.set_expire_at(key, at as u128);
+        self.touch(key);
+        true
+    }
+
+    /// Remove the TTL from `key`. Returns false if it has none or there is no such key.
+    pub fn persist(&mut self, key: &str) -> bool {
+        if !self.cache_ttls.contains_key(key) {
+            return false;
         }
-        if expired_keys.len() > 0 {
-            return Some(expired_keys)
+        self.log(&Command::PERSIST(key.to_string()));
+        self.clear_ttl(key);
+        self.touch(key);
+        true
+    }
+
+    /// Expire `key` at `expire`, dropping any TTL it had so `ttls`, `cache_ttls` and `volatile` stay in step.
+    fn set_expire_at(&mut self, key: &str, expire: u128) {
+        self.clear_ttl(key);
+        let ttl = Ttl{expire, id: self.next_ttl_id};
+        self.next_ttl_id += 1;
+        trace!("Inserted ({},{})", ttl.expire, ttl.id);
+        self.ttls.insert(ttl.clone(), key.to_string());
+        self.cache_ttls.insert(key.to_string(), (ttl, self.volatile.len()));
+        self.volatile.push(key.to_string());
+    }
+
+    fn clear_ttl(&mut self, key: &str) {
+        if let Some((ttl, slot)) = self.cache_ttls.remove(key) {
+            self.ttls.remove(&ttl);
+            // The last key takes the removed one's place.
+            self.volatile.swap_remove(slot);
+            if let Some(moved) = self.volatile.get(slot) {
+                self.cache_ttls.get_mut(moved).expect("Volatile key without a TTL").1 = slot;
+            }
         }
-        None
     }
 
-    pub fn try_expire(&mut self) -> Option<String> {
-        let entry = self.ttls.first_entry()?;
-        let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        if entry.key().expire > current_time.as_millis() {
+    /// Remove `key`, returning whether it existed.
+    pub fn delete(&mut self, key: &str) -> bool {
+        self.unlink(key).is_some()
+    }
+
+    /// Remove `key` and hand back its value, so a large one can be dropped by the caller
+    /// somewhere it won't hold up the store.
+    pub fn unlink(&mut self, key: &str) -> Option<Arc<Value>> {
+        if !self.cache.contains_key(key) {
             return None;
         }
-        let ttl = self.ttls.pop_first()?;
-        self.cache_ttls.remove(&ttl.1).unwrap();
-        self.cache.delete(&ttl.1);
-        println!("Expire: cache_ttls:{}, ttls:{}, cache:{}", self.cache_ttls.len(), self.ttls.len(), self.cache.len());
-        Some(ttl.1)
+        self.log(&Command::DELETE(vec![key.to_string()]));
+        self.clear_ttl(key);
+        let value = self.cache.delete(key);
+        self.touch(key);
+        trace!("Delete: cache_ttls:{}, ttls:{}, cache:{}", self.cache_ttls.len(), self.ttls.len(), self.cache.len());
+        value
+    }
+
+    /// How many of `keys` exist, a key given twice is counted twice.
+    pub fn exists(&self, keys: &[String]) -> usize {
+        keys.iter().filter(|key| self.cache.contains_key(key)).count()
+    }
+
+    /// Whether `key` has a TTL that has already passed, so should be treated as gone.
+    pub fn is_due(&self, key: &str) -> bool {
+        self.cache_ttls.get(key).is_some_and(|(ttl, _)| ttl.expire <= now_millis())
+    }
+
+    /// Remove `key` if its TTL has passed, returning whether it did. Run before a command
+    /// touches a key so it never sees one waiting on the next expire cycle.
+    pub fn expire_if_due(&mut self, key: &str) -> bool {
+        if !self.is_due(key) {
+            return false;
+        }
+        self.remove_expired(key);
+        true
+    }
+
+    /// Remove keys whose TTL has passed, soonest first, until none are left or `budget` is spent.
+    /// Returns the keys removed and whether any were still due when it stopped, so a mass expiry
+    /// can be worked through in chunks rather than holding the store for all of it.
+    pub fn expire_cycle(&mut self, budget: Duration) -> (Vec<String>, bool) {
+        let start = Instant::now();
+        let now = now_millis();
+        let mut expired = vec![];
+        loop {
+            for _ in 0..EXPIRE_KEYS_PER_LOOP {
+                match self.ttls.first_key_value() {
+                    Some((ttl, key)) if ttl.expire <= now => {
+                        let key = key.clone();
+                        self.remove_expired(&key);
+                        expired.push(key);
+                    },
+                    _ => return (expired, false),
+                }
+            }
+            if start.elapsed() >= budget {
+                let more = self.ttls.first_key_value().is_some_and(|(ttl, _)| ttl.expire <= now);
+                return (expired, more);
+            }
+        }
+    }
+
+    fn remove_expired(&mut self, key: &str) {
+        self.clear_ttl(key);
+        self.cache.delete(key);
+        self.touch(key);
+        self.log(&Command::DELETE(vec![key.to_string()]));
+        trace!("Expire: cache_ttls:{}, ttls:{}, cache:{}", self.cache_ttls.len(), self.ttls.len(), self.cache.len());
+    }
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
+
+/// The absolute expiry `ttl` milliseconds from now.
+fn expire_in(ttl: u64) -> u128 {
+    now_millis() + ttl as u128
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_incr_by() {
+        let mut store = DataStore::new(4);
+        assert_eq!(1, store.incr_by("n", 1).unwrap());
+        assert_eq!(-9, store.decr_by("n", 10).unwrap());
+        assert_eq!(b"-9".to_vec(), store.get("n").unwrap().unwrap());
+
+        store.insert("max", i64::MAX.to_string().into_bytes(), 0);
+        assert!(store.incr_by("max", 1).is_err());
+        assert!(store.decr_by("n", i64::MIN).is_err());
+        assert_eq!(i64::MAX.to_string().as_bytes(), store.get("max").unwrap().unwrap());
+
+        store.insert("text", b"12abc".to_vec(), 0);
+        assert!(store.incr_by("text", 1).is_err());
+        store.push("list", vec![b"1".to_vec()], false).unwrap();
+        assert!(store.incr_by("list", 1).is_err());
+    }
+
+    #[test]
+    fn test_incr_by_float() {
+        let mut store = DataStore::new(4);
+        store.insert("f", b"10.5".to_vec(), 60_000);
+        assert_eq!(10.6, store.incr_by_float("f", 0.1).unwrap());
+        assert_eq!(b"10.6".to_vec(), store.get("f").unwrap().unwrap());
+        assert_eq!(5.0, store.incr_by_float("new", 5.0).unwrap());
+        assert_eq!(b"5".to_vec(), store.get("new").unwrap().unwrap());
+        store.incr_by_float("new", f64::MAX).unwrap();
+        assert!(store.incr_by_float("new", f64::MAX).is_err());
+        assert_eq!(1e300, store.incr_by_float("big", 1e300).unwrap());
+        assert_eq!(b"1e300".to_vec(), store.get("big").unwrap().unwrap());
+
+        // The counter keeps its TTL.
+        assert!(store.entries().iter().find(|entry| entry.key == "f").unwrap().expire_at.is_some());
+    }
+
+    #[test]
+    fn test_set_options() {
+        let mut store = DataStore::new(4);
+        assert_eq!((true, None), store.insert_with("k", b"1".to_vec(), &SetOptions::new().nx().px(60_000)).unwrap());
+        assert_eq!((false, None), store.insert_with("k", b"2".to_vec(), &SetOptions::new().nx()).unwrap());
+        assert_eq!((false, None), store.insert_with("missing", b"2".to_vec(), &SetOptions::new().xx()).unwrap());
+        assert!(store.get("missing").unwrap().is_none());
+
+        // KEEPTTL holds on to the expiry set by the first write.
+        let expire_at = |store: &DataStore| store.entries().iter().find(|entry| entry.key == "k").unwrap().expire_at;
+        let before = expire_at(&store);
+        assert_eq!((true, Some(b"1".to_vec())), store.insert_with("k", b"3".to_vec(), &SetOptions::new().xx().get().keep_ttl()).unwrap());
+        assert_eq!(before, expire_at(&store));
+        store.insert_with("k", b"4".to_vec(), &SetOptions::new()).unwrap();
+        assert_eq!(None, expire_at(&store));
+
+        store.insert_with("at", b"1".to_vec(), &SetOptions::new().pxat(4_102_444_800_000)).unwrap();
+        assert_eq!(Some(4_102_444_800_000), store.entries().iter().find(|entry| entry.key == "at").unwrap().expire_at);
+        store.insert_with("at", b"2".to_vec(), &SetOptions::new().pxat(1)).unwrap();
+        assert!(store.get("at").unwrap().is_none());
+
+        store.push("list", vec![b"a".to_vec()], false).unwrap();
+        assert!(store.insert_with("list", b"v".to_vec(), &SetOptions::new().get()).is_err());
+    }
+
+    #[test]
+    fn test_expire_persist() {
+        let mut store = DataStore::new(4);
+        assert_eq!(None, store.pttl("k"));
+        assert!(!store.expire_in("k", 1000));
+
+        store.insert("k", b"v".to_vec(), 0);
+        assert_eq!(Some(None), store.pttl("k"));
+        assert!(!store.persist("k"));
+
+        assert!(store.expire_in("k", 60_000));
+        let ttl = store.pttl("k").unwrap().unwrap();
+        assert!(ttl > 59_000 && ttl <= 60_000);
+        // Moving the expiry leaves a single entry behind in each map.
+        assert!(store.expire_at("k", 4_102_444_800_000));
+        assert_eq!(1, store.ttls.len());
+        assert_eq!(1, store.cache_ttls.len());
+
+        assert!(store.persist("k"));
+        assert_eq!(Some(None), store.pttl("k"));
+        assert!(store.ttls.is_empty());
+
+        assert!(store.expire_in("k", -1));
+        assert!(store.get("k").unwrap().is_none());
+        assert!(store.cache_ttls.is_empty() && store.volatile.is_empty());
+    }
+
+    #[test]
+    fn test_expire_if_due() {
+        let mut store = DataStore::new(16);
+        store.insert("live", b"v".to_vec(), 60_000);
+        store.insert("due", b"v".to_vec(), 60_000);
+        store.set_expire_at("due", 1);
+        assert!(store.is_due("due"));
+        assert_eq!(vec!["live".to_string()], store.keys(None));
+        assert_eq!(1, store.entries().len());
+
+        let version = store.watch("due");
+        assert!(!store.expire_if_due("live"));
+        assert!(store.expire_if_due("due"));
+        assert!(!store.expire_if_due("due"));
+        assert_eq!(None, store.pttl("due"));
+        assert_ne!(version, store.version("due"));
+        assert!(!store.cache_ttls.contains_key("due"));
+    }
+
+    #[test]
+    fn test_expire_cycle() {
+        let mut store = DataStore::new(64);
+        for i in 0..100 {
+            store.insert(&format!("key{i}"), b"v".to_vec(), 60_000);
+            store.set_expire_at(&format!("key{i}"), 1 + i);
+        }
+        store.insert("live", b"v".to_vec(), 60_000);
+
+        // An exhausted budget still gets through a chunk at a time.
+        let (expired, more) = store.expire_cycle(Duration::ZERO);
+        assert_eq!(EXPIRE_KEYS_PER_LOOP, expired.len());
+        assert_eq!("key0", expired[0]);
+        assert!(more);
+
+        let (expired, more) = store.expire_cycle(Duration::from_secs(60));
+        assert_eq!(100 - EXPIRE_KEYS_PER_LOOP, expired.len());
+        assert!(!more);
+        assert_eq!(vec!["live".to_string()], store.keys(None));
+        assert_eq!((vec![], false), store.expire_cycle(Duration::from_secs(60)));
     }
 
+    #[test]
+    fn test_keys() {
+        let mut store = DataStore::new(16);
+        for key in ["session:1", "session:22", "user:1", "sessions"] {
+            store.insert(key, b"v".to_vec(), 0);
+        }
+        let sorted = |mut keys: Vec<String>| { keys.sort(); keys };
+        assert_eq!(vec!["session:1", "session:22"], sorted(store.keys(Some("session:*"))));
+        assert_eq!(vec!["session:1", "user:1"], sorted(store.keys(Some("*:?"))));
+        assert_eq!(4, store.keys(None).len());
+        assert!(store.keys(Some("nothing*")).is_empty());
+    }
+
+    #[test]
+    fn test_multi_key() {
+        let mut store = DataStore::new(4);
+        store.insert("a", b"old".to_vec(), 60_000);
+        store.mset(vec![("a".to_string(), b"1".to_vec()), ("b".to_string(), b"2".to_vec())]);
+        assert_eq!(Some(None), store.pttl("a"));
+        assert!(!store.msetnx(vec![("b".to_string(), b"3".to_vec()), ("c".to_string(), b"3".to_vec())]));
+        assert!(store.get("c").unwrap().is_none());
+
+        store.push("l", vec![b"x".to_vec()], false).unwrap();
+        let keys = ["a", "l", "missing", "b"].map(String::from);
+        assert_eq!(vec![Some(b"1".to_vec()), None, None, Some(b"2".to_vec())], store.mget(&keys));
+        assert_eq!(3, store.exists(&keys));
+
+        assert!(store.delete("a"));
+        assert!(!store.delete("a"));
+        assert!(matches!(store.unlink("l").as_deref(), Some(Value::List(_))));
+        assert_eq!(1, store.exists(&keys));
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut store = DataStore::new(16);
+        let version = store.watch("a");
+        assert_eq!(version, store.watch("a"));
+        store.insert("b", b"1".to_vec(), 0);
+        assert_eq!(version, store.version("a"));
+        store.hset("a", vec![(b"f".to_vec(), b"1".to_vec())]).unwrap();
+        assert_ne!(version, store.version("a"));
+
+        store.unwatch("a");
+        store.unwatch("a");
+        assert!(store.watched.is_empty());
+    }
 }
@@ -0,0 +1,244 @@
+use std::fmt::{self, Display};
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+use log::debug;
+
+use super::DataStore;
+use crate::table::random;
+
+/// Keys looked at to choose each one evicted by the LRU and LFU policies, as Redis' maxmemory-samples.
+const EVICTION_SAMPLES: usize = 5;
+
+/// Which keys are evicted to get back under maxmemory, using the same names as Redis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict nothing, refuse writes that would add data instead.
+    #[default]
+    NoEviction,
+    /// The least recently used of any key.
+    AllKeysLru,
+    /// The least frequently used of any key.
+    AllKeysLfu,
+    /// The least recently used of the keys with a TTL.
+    VolatileLru,
+    /// The key with the nearest expiry.
+    VolatileTtl,
+    AllKeysRandom,
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "volatile-lru" => Ok(EvictionPolicy::VolatileLru),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            "allkeys-random" => Ok(EvictionPolicy::AllKeysRandom),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("invalid maxmemory policy '{}'", s))),
+        }
+    }
+}
+
+impl Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl DataStore {
+    /// Limit the store to roughly `maxmemory` bytes, or no limit when 0.
+    pub fn set_maxmemory(&mut self, maxmemory: u64, policy: EvictionPolicy) {
+        self.maxmemory = maxmemory;
+        self.eviction_policy = policy;
+    }
+
+    /// Approximate bytes held by every key and value.
+    pub fn used_memory(&self) -> usize {
+        self.cache.memory_usage()
+    }
+
+    pub fn over_maxmemory(&self) -> bool {
+        self.maxmemory != 0 && self.used_memory() as u64 > self.maxmemory
+    }
+
+    /// Seconds since `key` was last read or written, without counting as an access itself.
+    pub fn idle_time(&self, key: &str) -> Option<u64> {
+        self.cache.access(key).map(|access| access.idle() / 1000)
+    }
+
+    /// The logarithmic access counter of `key`, without counting as an access itself.
+    pub fn frequency(&self, key: &str) -> Option<u8> {
+        self.cache.access(key).map(|access| access.frequency())
+    }
+
+    /// Evict keys chosen by the eviction policy until the store is back under maxmemory,
+    /// returning the keys evicted. Stops short when the policy has nothing left to evict.
+    pub fn evict(&mut self) -> Vec<String> {
+        let mut evicted = vec![];
+        while self.over_maxmemory() {
+            let Some(key) = self.eviction_candidate() else {
+                break;
+            };
+            if self.unlink(&key).is_none() {
+                // A TTL left behind by a key that is already gone, drop it so it isn't picked again.
+                if self.cache_ttls.contains_key(&key) {
+                    self.clear_ttl(&key);
+                    continue;
+                }
+                break;
+            }
+            evicted.push(key);
+        }
+        if !evicted.is_empty() {
+            debug!("Evicted {} keys, {} bytes used", evicted.len(), self.used_memory());
+        }
+        evicted
+    }
+
+    /// Like Redis, LRU and LFU are approximated by picking the best of a few sampled keys.
+    fn eviction_candidate(&self) -> Option<String> {
+        let idle = |key: &&str| self.cache.access(key).map_or(0, |access| access.idle());
+        let key = match self.eviction_policy {
+            EvictionPolicy::NoEviction => None,
+            EvictionPolicy::AllKeysRandom => self.cache.random_key(),
+            EvictionPolicy::AllKeysLru => (0..EVICTION_SAMPLES).filter_map(|_| self.cache.random_key()).max_by_key(idle),
+            EvictionPolicy::AllKeysLfu => (0..EVICTION_SAMPLES).filter_map(|_| self.cache.random_key())
+                // The least recently used breaks a tie, as counters start out equal.
+                .min_by_key(|key| (self.cache.access(key).map_or(0, |access| access.frequency()), u64::MAX - idle(key))),
+            EvictionPolicy::VolatileLru => (0..EVICTION_SAMPLES).filter_map(|_| self.random_volatile_key()).max_by_key(idle),
+            EvictionPolicy::VolatileTtl => self.ttls.first_key_value().map(|(_, key)| key.as_str()),
+        };
+        key.map(String::from)
+    }
+
+    /// A key with a TTL picked uniformly at random, whatever its expiry.
+    fn random_volatile_key(&self) -> Option<&str> {
+        if self.volatile.is_empty() {
+            return None;
+        }
+        Some(&self.volatile[random() as usize % self.volatile.len()])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fill(store: &mut DataStore, count: usize, ttl: u64) {
+        for i in 0..count {
+            store.insert(&format!("key{i}"), vec![0; 100], ttl);
+        }
+    }
+
+    #[test]
+    fn test_evict() {
+        let mut store = DataStore::new(16);
+        fill(&mut store, 10, 0);
+        let full = store.used_memory() as u64;
+        assert!(store.evict().is_empty());
+
+        store.set_maxmemory(full / 2, EvictionPolicy::NoEviction);
+        assert!(store.over_maxmemory());
+        assert!(store.evict().is_empty());
+
+        store.set_maxmemory(full / 2, EvictionPolicy::VolatileLru);
+        assert!(store.evict().is_empty());
+
+        for policy in [EvictionPolicy::AllKeysLru, EvictionPolicy::AllKeysLfu, EvictionPolicy::AllKeysRandom] {
+            let mut store = DataStore::new(16);
+            fill(&mut store, 10, 0);
+            store.set_maxmemory(full / 2, policy);
+            let evicted = store.evict();
+            assert_eq!(5, evicted.len(), "{policy}");
+            assert!(!store.over_maxmemory());
+            assert!(evicted.iter().all(|key| store.get(key).unwrap().is_none()));
+        }
+    }
+
+    #[test]
+    fn test_evict_volatile() {
+        let mut store = DataStore::new(16);
+        fill(&mut store, 5, 0);
+        store.insert("soon", vec![0; 100], 1000);
+        store.insert("later", vec![0; 100], 60_000);
+        let full = store.used_memory() as u64;
+
+        store.set_maxmemory(full - 1, EvictionPolicy::VolatileTtl);
+        assert_eq!(vec!["soon".to_string()], store.evict());
+
+        store.set_maxmemory(full / 2, EvictionPolicy::VolatileLru);
+        assert_eq!(vec!["later".to_string()], store.evict());
+        assert!(store.over_maxmemory());
+    }
+
+    #[test]
+    fn test_random_volatile_key() {
+        let mut store = DataStore::new(16);
+        fill(&mut store, 9, 1000);
+        store.insert("distant", vec![0; 100], 86_400_000);
+        // Every key is as likely as the next, however far its expiry is from the others'.
+        let picked = (0..1000).filter(|_| store.random_volatile_key() == Some("distant")).count();
+        assert!((30..200).contains(&picked), "{picked}");
+
+        store.delete("key0");
+        store.persist("key4");
+        assert_eq!(8, store.volatile.len());
+        assert!(store.volatile.iter().enumerate().all(|(slot, key)| store.cache_ttls[key].1 == slot));
+    }
+
+    #[test]
+    fn test_evict_out_of_candidates() {
+        let mut store = DataStore::new(16);
+        fill(&mut store, 5, 0);
+        store.insert("soon", vec![0; 100], 1000);
+        // A TTL with no key behind it is dropped rather than picked forever.
+        store.set_expire_at("ghost", 1);
+        store.set_maxmemory(1, EvictionPolicy::VolatileTtl);
+        assert_eq!(vec!["soon".to_string()], store.evict());
+        assert!(store.ttls.is_empty() && store.cache_ttls.is_empty() && store.volatile.is_empty());
+        assert!(store.over_maxmemory());
+        assert!(store.evict().is_empty());
+    }
+
+    #[test]
+    fn test_idle_time() {
+        let mut store = DataStore::new(16);
+        assert_eq!(None, store.idle_time("k"));
+        store.insert("k", b"v".to_vec(), 0);
+        assert_eq!(Some(0), store.idle_time("k"));
+        store.cache.access("k").unwrap().backdate(10_000);
+        assert_eq!(Some(10), store.idle_time("k"));
+        store.get("k").unwrap();
+        assert_eq!(Some(0), store.idle_time("k"));
+    }
+
+    #[test]
+    fn test_evict_lru() {
+        let mut store = DataStore::new(16);
+        fill(&mut store, 20, 0);
+        store.cache.access("key7").unwrap().backdate(60_000);
+        // Sampling may miss it, but it is always the pick once sampled.
+        let mut evicted = vec![];
+        while !evicted.contains(&"key7".to_string()) {
+            store.set_maxmemory(store.used_memory() as u64 - 1, EvictionPolicy::AllKeysLru);
+            evicted.extend(store.evict());
+        }
+        assert_eq!(Some(&"key7".to_string()), evicted.last());
+
+        assert_eq!("allkeys-lru", EvictionPolicy::AllKeysLru.to_string());
+        assert_eq!(EvictionPolicy::VolatileTtl, "Volatile-TTL".parse().unwrap());
+        assert!("lru".parse::<EvictionPolicy>().is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+use rusty_redis_core::command::Command;
+
+use super::DataStore;
+use crate::value::{wrong_type, Value};
+
+impl DataStore {
+    /// Set fields of the hash at `key`, creating it if needed.
+    /// Any TTL on the key is kept. Returns how many of the fields are new.
+    pub fn hset(&mut self, key: &str, pairs: Vec<(Vec<u8>, Vec<u8>)>) -> io::Result<usize> {
+        if self.hash(key)?.is_none() {
+            self.cache.insert(key, Value::Hash(HashMap::new()));
+        }
+        if self.aof.is_some() {
+            self.log(&Command::HSET(key.to_string(), pairs.clone()));
+        }
+        let hash = self.hash_mut(key)?.unwrap();
+        let added = pairs.into_iter()
+            .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+            .count();
+        self.touch(key);
+        Ok(added)
+    }
+
+    pub fn hget(&self, key: &str, field: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.hash(key)?.and_then(|hash| hash.get(field)).cloned())
+    }
+
+    /// Remove fields from the hash at `key`, returning how many were there.
+    pub fn hdel(&mut self, key: &str, fields: Vec<Vec<u8>>) -> io::Result<usize> {
+        let Some(hash) = self.hash_mut(key)? else {
+            return Ok(0);
+        };
+        let removed = fields.iter().filter(|field| hash.remove(*field).is_some()).count();
+        let emptied = hash.is_empty();
+        if removed > 0 {
+            self.log(&Command::HDEL(key.to_string(), fields));
+            self.touch(key);
+        }
+        if emptied {
+            self.remove_empty(key);
+        }
+        Ok(removed)
+    }
+
+    /// Every field and value of the hash at `key`, in no particular order.
+    pub fn hgetall(&self, key: &str) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.hash(key)?.map_or(vec![], |hash| {
+            hash.iter().map(|(field, value)| (field.clone(), value.clone())).collect()
+        }))
+    }
+
+    /// Add `increment` to the integer held in a field, a missing field counts as 0.
+    /// Returns the value afterwards.
+    pub fn hincrby(&mut self, key: &str, field: &[u8], increment: i64) -> io::Result<i64> {
+        let current = match self.hget(key, field)? {
+            Some(value) => std::str::from_utf8(&value).ok()
+                .and_then(|s| s.parse::<i64>().ok())
+                .ok_or(Error::new(ErrorKind::InvalidData, "hash value is not an integer"))?,
+            None => 0,
+        };
+        let updated = current.checked_add(increment)
+            .ok_or(Error::new(ErrorKind::InvalidInput, "increment or decrement would overflow"))?;
+        if self.hash(key)?.is_none() {
+            self.cache.insert(key, Value::Hash(HashMap::new()));
+        }
+        self.log(&Command::HINCRBY(key.to_string(), field.to_vec(), increment));
+        self.hash_mut(key)?.unwrap().insert(field.to_vec(), updated.to_string().into_bytes());
+        self.touch(key);
+        Ok(updated)
+    }
+
+    pub fn hkeys(&self, key: &str) -> io::Result<Vec<Vec<u8>>> {
+        Ok(self.hash(key)?.map_or(vec![], |hash| hash.keys().cloned().collect()))
+    }
+
+    pub fn hlen(&self, key: &str) -> io::Result<usize> {
+        Ok(self.hash(key)?.map_or(0, |hash| hash.len()))
+    }
+
+    fn hash(&self, key: &str) -> io::Result<Option<&HashMap<Vec<u8>, Vec<u8>>>> {
+        match self.cache.get(key).map(|value| &**value) {
+            Some(Value::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn hash_mut(&mut self, key: &str) -> io::Result<Option<&mut HashMap<Vec<u8>, Vec<u8>>>> {
+        match self.cache.get_mut(key).map(Arc::make_mut) {
+            Some(Value::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::snapshot::SnapshotEntry;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        pairs.iter().map(|(f, v)| (f.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn test_set_get_delete() {
+        let mut store = DataStore::new(4);
+        assert_eq!(2, store.hset("h", pairs(&[("name", "ann"), ("age", "30")])).unwrap());
+        assert_eq!(1, store.hset("h", pairs(&[("age", "31"), ("city", "oslo")])).unwrap());
+        assert_eq!(Some(b"31".to_vec()), store.hget("h", b"age").unwrap());
+        assert_eq!(None, store.hget("h", b"missing").unwrap());
+        assert_eq!(3, store.hlen("h").unwrap());
+
+        let mut all = store.hgetall("h").unwrap();
+        all.sort();
+        assert_eq!(pairs(&[("age", "31"), ("city", "oslo"), ("name", "ann")]), all);
+        let mut keys = store.hkeys("h").unwrap();
+        keys.sort();
+        assert_eq!(vec![b"age".to_vec(), b"city".to_vec(), b"name".to_vec()], keys);
+
+        assert_eq!(2, store.hdel("h", vec![b"age".to_vec(), b"city".to_vec(), b"nope".to_vec()]).unwrap());
+        assert_eq!(1, store.hdel("h", vec![b"name".to_vec()]).unwrap());
+
+        // The key goes away with its last field.
+        assert!(store.keys(None).is_empty());
+        assert_eq!(0, store.hdel("h", vec![b"name".to_vec()]).unwrap());
+    }
+
+    #[test]
+    fn test_hincrby() {
+        let mut store = DataStore::new(4);
+        assert_eq!(5, store.hincrby("h", b"n", 5).unwrap());
+        assert_eq!(-2, store.hincrby("h", b"n", -7).unwrap());
+        assert_eq!(Some(b"-2".to_vec()), store.hget("h", b"n").unwrap());
+
+        store.hset("h", pairs(&[("text", "abc"), ("max", &i64::MAX.to_string())])).unwrap();
+        assert!(store.hincrby("h", b"text", 1).is_err());
+        assert!(store.hincrby("h", b"max", 1).is_err());
+        assert_eq!(Some(i64::MAX.to_string().into_bytes()), store.hget("h", b"max").unwrap());
+    }
+
+    #[test]
+    fn test_keeps_ttl() {
+        let mut store = DataStore::new(4);
+        let expire_at = Some(u128::MAX);
+        let value = Arc::new(Value::Hash(HashMap::from([(b"f".to_vec(), b"v".to_vec())])));
+        store.restore(SnapshotEntry { key: "h".to_string(), value, expire_at });
+
+        store.hset("h", pairs(&[("g", "w")])).unwrap();
+        store.hdel("h", vec![b"f".to_vec()]).unwrap();
+        store.hincrby("h", b"n", 1).unwrap();
+        assert_eq!(expire_at, store.entries()[0].expire_at);
+
+        store.insert("s", b"string".to_vec(), 0);
+        assert!(store.hset("s", pairs(&[("f", "v")])).is_err());
+        assert!(store.hget("s", b"f").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+
+use rusty_redis_core::command::Command;
+
+use super::DataStore;
+use crate::value::{resolve_index, resolve_range, wrong_type, Value};
+
+impl DataStore {
+    /// Push `values` onto the head or tail of the list at `key`, creating it if needed.
+    /// Returns the length of the list afterwards.
+    pub fn push(&mut self, key: &str, values: Vec<Vec<u8>>, front: bool) -> io::Result<usize> {
+        if self.list(key)?.is_none() {
+            self.cache.insert(key, Value::List(VecDeque::new()));
+        }
+        if self.aof.is_some() {
+            let cmd = if front { Command::LPUSH(key.to_string(), values.clone()) } else { Command::RPUSH(key.to_string(), values.clone()) };
+            self.log(&cmd);
+        }
+        let list = self.list_mut(key)?.unwrap();
+        for value in values {
+            if front {
+                list.push_front(value);
+            } else {
+                list.push_back(value);
+            }
+        }
+        let len = list.len();
+        self.touch(key);
+        Ok(len)
+    }
+
+    /// Pop up to `count` values, or one without a count, from the head or tail of the list at `key`.
+    /// Returns None if there is no such list.
+    pub fn pop(&mut self, key: &str, count: Option<u64>, front: bool) -> io::Result<Option<Vec<Vec<u8>>>> {
+        let Some(list) = self.list_mut(key)? else {
+            return Ok(None);
+        };
+        let n = count.unwrap_or(1).min(list.len() as u64);
+        let popped: Vec<Vec<u8>> = (0..n)
+            .filter_map(|_| if front { list.pop_front() } else { list.pop_back() })
+            .collect();
+        let emptied = list.is_empty();
+        if !popped.is_empty() {
+            let cmd = if front { Command::LPOP(key.to_string(), count) } else { Command::RPOP(key.to_string(), count) };
+            self.log(&cmd);
+            self.touch(key);
+        }
+        if emptied {
+            self.remove_empty(key);
+        }
+        Ok(Some(popped))
+    }
+
+    /// Values between the `start` and `stop` indexes inclusive, negative indexes count from the tail.
+    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> io::Result<Vec<Vec<u8>>> {
+        let Some(list) = self.list(key)? else {
+            return Ok(vec![]);
+        };
+        Ok(match resolve_range(start, stop, list.len()) {
+            Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+            None => vec![],
+        })
+    }
+
+    pub fn llen(&self, key: &str) -> io::Result<usize> {
+        Ok(self.list(key)?.map_or(0, |list| list.len()))
+    }
+
+    pub fn lindex(&self, key: &str, index: i64) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.list(key)?
+            .and_then(|list| resolve_index(index, list.len()).and_then(|i| list.get(i)))
+            .cloned())
+    }
+
+    /// Keep only the values between the `start` and `stop` indexes inclusive.
+    pub fn ltrim(&mut self, key: &str, start: i64, stop: i64) -> io::Result<()> {
+        let Some(list) = self.list_mut(key)? else {
+            return Ok(());
+        };
+        match resolve_range(start, stop, list.len()) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            },
+            None => list.clear(),
+        }
+        let emptied = list.is_empty();
+        self.log(&Command::LTRIM(key.to_string(), start, stop));
+        self.touch(key);
+        if emptied {
+            self.remove_empty(key);
+        }
+        Ok(())
+    }
+
+    fn list(&self, key: &str) -> io::Result<Option<&VecDeque<Vec<u8>>>> {
+        match self.cache.get(key).map(|value| &**value) {
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn list_mut(&mut self, key: &str) -> io::Result<Option<&mut VecDeque<Vec<u8>>>> {
+        match self.cache.get_mut(key).map(Arc::make_mut) {
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn values(values: &[&str]) -> Vec<Vec<u8>> {
+        values.iter().map(|v| v.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut store = DataStore::new(4);
+        assert_eq!(2, store.push("l", values(&["b", "a"]), true).unwrap());
+        assert_eq!(4, store.push("l", values(&["c", "d"]), false).unwrap());
+        assert_eq!(values(&["a", "b", "c", "d"]), store.lrange("l", 0, -1).unwrap());
+
+        assert_eq!(Some(values(&["a"])), store.pop("l", None, true).unwrap());
+        assert_eq!(Some(values(&["d", "c"])), store.pop("l", Some(2), false).unwrap());
+        assert_eq!(Some(values(&["b"])), store.pop("l", Some(10), false).unwrap());
+
+        // The key goes away with its last value.
+        assert_eq!(None, store.pop("l", None, true).unwrap());
+        assert!(store.keys(None).is_empty());
+    }
+
+    #[test]
+    fn test_index_and_trim() {
+        let mut store = DataStore::new(4);
+        store.push("l", values(&["a", "b", "c", "d", "e"]), false).unwrap();
+        assert_eq!(Some(b"e".to_vec()), store.lindex("l", -1).unwrap());
+        assert_eq!(None, store.lindex("l", 5).unwrap());
+        assert_eq!(values(&["d", "e"]), store.lrange("l", -2, 100).unwrap());
+
+        store.ltrim("l", 1, -2).unwrap();
+        assert_eq!(values(&["b", "c", "d"]), store.lrange("l", 0, -1).unwrap());
+        assert_eq!(3, store.llen("l").unwrap());
+
+        store.ltrim("l", 5, 10).unwrap();
+        assert_eq!(0, store.llen("l").unwrap());
+        assert!(store.keys(None).is_empty());
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut store = DataStore::new(4);
+        store.insert("s", b"value".to_vec(), 0);
+        store.push("l", values(&["a"]), false).unwrap();
+
+        assert!(store.push("s", values(&["a"]), false).is_err());
+        assert!(store.lrange("s", 0, -1).is_err());
+        assert!(store.get("l").is_err());
+        assert_eq!(b"value".to_vec(), store.get("s").unwrap().unwrap());
+    }
+}
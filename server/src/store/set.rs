@@ -0,0 +1,212 @@
+use std::collections::HashSet;
+use std::io;
+use std::sync::Arc;
+
+use rusty_redis_core::command::Command;
+
+use super::DataStore;
+use crate::value::{wrong_type, Value};
+
+/// How SINTER, SUNION and SDIFF combine the sets at several keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    /// Members of the first set that are in none of the others.
+    Diff,
+}
+
+impl SetOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SetOp::Inter => "SINTER",
+            SetOp::Union => "SUNION",
+            SetOp::Diff => "SDIFF",
+        }
+    }
+
+    fn store_command(&self, destination: &str, keys: &[String]) -> Command {
+        let (destination, keys) = (destination.to_string(), keys.to_vec());
+        match self {
+            SetOp::Inter => Command::SINTERSTORE(destination, keys),
+            SetOp::Union => Command::SUNIONSTORE(destination, keys),
+            SetOp::Diff => Command::SDIFFSTORE(destination, keys),
+        }
+    }
+}
+
+impl DataStore {
+    /// Add members to the set at `key`, creating it if needed.
+    /// Returns how many of them were not already in the set.
+    pub fn sadd(&mut self, key: &str, members: Vec<Vec<u8>>) -> io::Result<usize> {
+        if self.set(key)?.is_none() {
+            self.cache.insert(key, Value::Set(HashSet::new()));
+        }
+        if self.aof.is_some() {
+            self.log(&Command::SADD(key.to_string(), members.clone()));
+        }
+        let set = self.set_mut(key)?.unwrap();
+        let added = members.into_iter().filter(|member| set.insert(member.clone())).count();
+        self.touch(key);
+        Ok(added)
+    }
+
+    /// Remove members from the set at `key`, returning how many were in it.
+    pub fn srem(&mut self, key: &str, members: Vec<Vec<u8>>) -> io::Result<usize> {
+        let Some(set) = self.set_mut(key)? else {
+            return Ok(0);
+        };
+        let removed = members.iter().filter(|member| set.remove(*member)).count();
+        let emptied = set.is_empty();
+        if removed > 0 {
+            self.log(&Command::SREM(key.to_string(), members));
+            self.touch(key);
+        }
+        if emptied {
+            self.remove_empty(key);
+        }
+        Ok(removed)
+    }
+
+    pub fn sismember(&self, key: &str, member: &[u8]) -> io::Result<bool> {
+        Ok(self.set(key)?.is_some_and(|set| set.contains(member)))
+    }
+
+    /// Every member of the set at `key`, in no particular order.
+    pub fn smembers(&self, key: &str) -> io::Result<Vec<Vec<u8>>> {
+        Ok(self.set(key)?.map_or(vec![], |set| set.iter().cloned().collect()))
+    }
+
+    pub fn scard(&self, key: &str) -> io::Result<usize> {
+        Ok(self.set(key)?.map_or(0, |set| set.len()))
+    }
+
+    /// Combine the sets at `keys`, a missing key counts as an empty set.
+    pub fn set_op(&self, op: SetOp, keys: &[String]) -> io::Result<HashSet<Vec<u8>>> {
+        let sets = keys.iter().map(|key| self.set(key)).collect::<io::Result<Vec<_>>>()?;
+        let Some((first, rest)) = sets.split_first() else {
+            return Ok(HashSet::new());
+        };
+        let first = first.cloned().unwrap_or_default();
+        Ok(match op {
+            SetOp::Inter => first.into_iter()
+                .filter(|member| rest.iter().all(|set| set.is_some_and(|set| set.contains(member))))
+                .collect(),
+            SetOp::Union => rest.iter().flatten().fold(first, |mut acc, set| {
+                acc.extend(set.iter().cloned());
+                acc
+            }),
+            SetOp::Diff => first.into_iter()
+                .filter(|member| !rest.iter().flatten().any(|set| set.contains(member)))
+                .collect(),
+        })
+    }
+
+    /// Combine the sets at `keys` into `destination`, replacing whatever it held.
+    /// Returns the size of the new set, an empty result removes `destination`.
+    pub fn set_op_store(&mut self, op: SetOp, destination: &str, keys: &[String]) -> io::Result<usize> {
+        let members = self.set_op(op, keys)?;
+        let len = members.len();
+        self.log(&op.store_command(destination, keys));
+        self.clear_ttl(destination);
+        if members.is_empty() {
+            self.cache.delete(destination);
+        } else {
+            self.cache.insert(destination, Value::Set(members));
+        }
+        self.touch(destination);
+        Ok(len)
+    }
+
+    fn set(&self, key: &str) -> io::Result<Option<&HashSet<Vec<u8>>>> {
+        match self.cache.get(key).map(|value| &**value) {
+            Some(Value::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn set_mut(&mut self, key: &str) -> io::Result<Option<&mut HashSet<Vec<u8>>>> {
+        match self.cache.get_mut(key).map(Arc::make_mut) {
+            Some(Value::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn members(members: &[&str]) -> Vec<Vec<u8>> {
+        members.iter().map(|m| m.as_bytes().to_vec()).collect()
+    }
+
+    fn sorted(set: impl IntoIterator<Item = Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut members: Vec<Vec<u8>> = set.into_iter().collect();
+        members.sort();
+        members
+    }
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|k| k.to_string()).collect()
+    }
+
+    #[test]
+    fn test_add_remove() {
+        let mut store = DataStore::new(4);
+        assert_eq!(2, store.sadd("s", members(&["a", "b"])).unwrap());
+        assert_eq!(1, store.sadd("s", members(&["b", "c", "c"])).unwrap());
+        assert_eq!(3, store.scard("s").unwrap());
+        assert!(store.sismember("s", b"c").unwrap());
+        assert!(!store.sismember("s", b"d").unwrap());
+        assert_eq!(members(&["a", "b", "c"]), sorted(store.smembers("s").unwrap()));
+
+        assert_eq!(2, store.srem("s", members(&["a", "c", "d"])).unwrap());
+        assert_eq!(1, store.srem("s", members(&["b"])).unwrap());
+
+        // The key goes away with its last member.
+        assert!(store.keys(None).is_empty());
+        assert!(!store.sismember("s", b"b").unwrap());
+    }
+
+    #[test]
+    fn test_set_op() {
+        let mut store = DataStore::new(4);
+        store.sadd("a", members(&["1", "2", "3", "4"])).unwrap();
+        store.sadd("b", members(&["2", "3", "5"])).unwrap();
+        store.sadd("c", members(&["3", "6"])).unwrap();
+
+        assert_eq!(members(&["3"]), sorted(store.set_op(SetOp::Inter, &keys(&["a", "b", "c"])).unwrap()));
+        assert_eq!(members(&["1", "2", "3", "4", "5", "6"]), sorted(store.set_op(SetOp::Union, &keys(&["a", "b", "c"])).unwrap()));
+        assert_eq!(members(&["1", "4"]), sorted(store.set_op(SetOp::Diff, &keys(&["a", "b", "c"])).unwrap()));
+
+        // A missing key is an empty set.
+        assert!(store.set_op(SetOp::Inter, &keys(&["a", "missing"])).unwrap().is_empty());
+        assert_eq!(members(&["2", "3", "5"]), sorted(store.set_op(SetOp::Union, &keys(&["missing", "b"])).unwrap()));
+        assert!(store.set_op(SetOp::Diff, &keys(&["missing", "a"])).unwrap().is_empty());
+
+        store.insert("str", b"value".to_vec(), 0);
+        assert!(store.set_op(SetOp::Union, &keys(&["a", "str"])).is_err());
+    }
+
+    #[test]
+    fn test_set_op_store() {
+        let mut store = DataStore::new(4);
+        store.sadd("a", members(&["1", "2", "3"])).unwrap();
+        store.sadd("b", members(&["2", "3", "4"])).unwrap();
+        store.insert("dest", b"replaced".to_vec(), 60_000);
+
+        assert_eq!(2, store.set_op_store(SetOp::Inter, "dest", &keys(&["a", "b"])).unwrap());
+        assert_eq!(members(&["2", "3"]), sorted(store.smembers("dest").unwrap()));
+        assert!(store.entries().iter().all(|entry| entry.expire_at.is_none()));
+
+        // The destination may also be one of the sources.
+        assert_eq!(4, store.set_op_store(SetOp::Union, "a", &keys(&["a", "b"])).unwrap());
+        assert_eq!(0, store.set_op_store(SetOp::Diff, "dest", &keys(&["b", "a"])).unwrap());
+        let mut remaining = store.keys(None);
+        remaining.sort();
+        assert_eq!(vec!["a", "b"], remaining);
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+use rusty_redis_core::command::{Command, ScoreBound};
+
+use super::DataStore;
+use crate::sorted_set::SortedSet;
+use crate::value::{resolve_range, wrong_type, Value};
+
+impl DataStore {
+    /// Add members to the sorted set at `key` or update their scores, creating it if needed.
+    /// Returns how many of them are new.
+    pub fn zadd(&mut self, key: &str, entries: Vec<(f64, Vec<u8>)>) -> io::Result<usize> {
+        if self.zset(key)?.is_none() {
+            self.cache.insert(key, Value::SortedSet(SortedSet::new()));
+        }
+        if self.aof.is_some() {
+            self.log(&Command::ZADD(key.to_string(), entries.clone()));
+        }
+        let zset = self.zset_mut(key)?.unwrap();
+        let added = entries.into_iter().filter(|(score, member)| zset.insert(member.clone(), *score)).count();
+        self.touch(key);
+        Ok(added)
+    }
+
+    /// Remove members from the sorted set at `key`, returning how many were in it.
+    pub fn zrem(&mut self, key: &str, members: Vec<Vec<u8>>) -> io::Result<usize> {
+        let Some(zset) = self.zset_mut(key)? else {
+            return Ok(0);
+        };
+        let removed = members.iter().filter(|member| zset.remove(member)).count();
+        let emptied = zset.is_empty();
+        if removed > 0 {
+            self.log(&Command::ZREM(key.to_string(), members));
+            self.touch(key);
+        }
+        if emptied {
+            self.remove_empty(key);
+        }
+        Ok(removed)
+    }
+
+    pub fn zscore(&self, key: &str, member: &[u8]) -> io::Result<Option<f64>> {
+        Ok(self.zset(key)?.and_then(|zset| zset.score(member)))
+    }
+
+    pub fn zrank(&self, key: &str, member: &[u8]) -> io::Result<Option<usize>> {
+        Ok(self.zset(key)?.and_then(|zset| zset.rank(member)))
+    }
+
+    /// Members between the `start` and `stop` ranks inclusive with their scores,
+    /// negative ranks count back from the highest score.
+    pub fn zrange(&self, key: &str, start: i64, stop: i64) -> io::Result<Vec<(Vec<u8>, f64)>> {
+        let Some(zset) = self.zset(key)? else {
+            return Ok(vec![]);
+        };
+        Ok(match resolve_range(start, stop, zset.len()) {
+            Some((start, stop)) => to_owned(zset.range(start, stop)),
+            None => vec![],
+        })
+    }
+
+    pub fn zrange_by_score(&self, key: &str, min: ScoreBound, max: ScoreBound) -> io::Result<Vec<(Vec<u8>, f64)>> {
+        Ok(self.zset(key)?.map_or(vec![], |zset| to_owned(zset.range_by_score(min, max))))
+    }
+
+    /// Add `increment` to the score of `member`, a missing member starts from 0.
+    /// Returns the new score.
+    pub fn zincrby(&mut self, key: &str, increment: f64, member: &[u8]) -> io::Result<f64> {
+        let score = self.zscore(key, member)?.unwrap_or(0.0) + increment;
+        if score.is_nan() {
+            return Err(Error::new(ErrorKind::InvalidInput, "resulting score is not a number (NaN)"));
+        }
+        if self.zset(key)?.is_none() {
+            self.cache.insert(key, Value::SortedSet(SortedSet::new()));
+        }
+        self.log(&Command::ZINCRBY(key.to_string(), increment, member.to_vec()));
+        self.zset_mut(key)?.unwrap().insert(member.to_vec(), score);
+        self.touch(key);
+        Ok(score)
+    }
+
+    pub fn zcard(&self, key: &str) -> io::Result<usize> {
+        Ok(self.zset(key)?.map_or(0, |zset| zset.len()))
+    }
+
+    fn zset(&self, key: &str) -> io::Result<Option<&SortedSet>> {
+        match self.cache.get(key).map(|value| &**value) {
+            Some(Value::SortedSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn zset_mut(&mut self, key: &str) -> io::Result<Option<&mut SortedSet>> {
+        match self.cache.get_mut(key).map(Arc::make_mut) {
+            Some(Value::SortedSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+}
+
+fn to_owned(entries: Vec<(&[u8], f64)>) -> Vec<(Vec<u8>, f64)> {
+    entries.into_iter().map(|(member, score)| (member.to_vec(), score)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn leaderboard() -> DataStore {
+        let mut store = DataStore::new(4);
+        store.zadd("board", vec![(30.0, b"carol".to_vec()), (10.0, b"alice".to_vec()), (20.0, b"bob".to_vec())]).unwrap();
+        store
+    }
+
+    fn members(entries: Vec<(Vec<u8>, f64)>) -> Vec<Vec<u8>> {
+        entries.into_iter().map(|(member, _)| member).collect()
+    }
+
+    #[test]
+    fn test_add_remove() {
+        let mut store = leaderboard();
+        assert_eq!(1, store.zadd("board", vec![(5.0, b"bob".to_vec()), (40.0, b"dave".to_vec())]).unwrap());
+        assert_eq!(4, store.zcard("board").unwrap());
+        assert_eq!(Some(5.0), store.zscore("board", b"bob").unwrap());
+        assert_eq!(Some(0), store.zrank("board", b"bob").unwrap());
+        assert_eq!(None, store.zrank("board", b"nobody").unwrap());
+
+        assert_eq!(2, store.zrem("board", vec![b"bob".to_vec(), b"dave".to_vec(), b"nobody".to_vec()]).unwrap());
+        assert_eq!(2, store.zrem("board", vec![b"alice".to_vec(), b"carol".to_vec()]).unwrap());
+
+        // The key goes away with its last member.
+        assert!(store.keys(None).is_empty());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let store = leaderboard();
+        assert_eq!(vec![(b"alice".to_vec(), 10.0), (b"bob".to_vec(), 20.0), (b"carol".to_vec(), 30.0)], store.zrange("board", 0, -1).unwrap());
+        assert_eq!(vec![b"carol".to_vec()], members(store.zrange("board", -1, -1).unwrap()));
+        assert!(store.zrange("board", 5, 10).unwrap().is_empty());
+
+        let by_score = store.zrange_by_score("board", ScoreBound::Exclusive(10.0), ScoreBound::Inclusive(f64::INFINITY)).unwrap();
+        assert_eq!(vec![b"bob".to_vec(), b"carol".to_vec()], members(by_score));
+        assert!(store.zrange_by_score("missing", ScoreBound::Inclusive(0.0), ScoreBound::Inclusive(1.0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_zincrby() {
+        let mut store = leaderboard();
+        assert_eq!(35.0, store.zincrby("board", 25.0, b"alice").unwrap());
+        assert_eq!(Some(2), store.zrank("board", b"alice").unwrap());
+        assert_eq!(1.5, store.zincrby("new", 1.5, b"m").unwrap());
+
+        store.zadd("inf", vec![(f64::INFINITY, b"m".to_vec())]).unwrap();
+        assert!(store.zincrby("inf", f64::NEG_INFINITY, b"m").is_err());
+        assert_eq!(Some(f64::INFINITY), store.zscore("inf", b"m").unwrap());
+
+        store.insert("str", b"value".to_vec(), 0);
+        assert!(store.zincrby("str", 1.0, b"m").is_err());
+    }
+}
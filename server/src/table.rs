@@ -1,142 +1,458 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Grow once the average chain is this long.
+const MAX_LOAD_FACTOR: usize = 8;
+/// Shrink once the average chain is shorter than 1 / MIN_LOAD_DIVISOR.
+const MIN_LOAD_DIVISOR: usize = 4;
+/// Most buckets moved from the older table per insert or delete while resizing.
+const REHASH_WORK: usize = 128;
+
+/// Access counter a new key starts with, so it isn't the first to be evicted before it has had a chance to be used.
+const LFU_INIT_VAL: u8 = 5;
+/// How slowly the access counter climbs, as Redis' lfu-log-factor.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// The access counter drops by one for each of these that pass without an access, as Redis' lfu-decay-time.
+const LFU_DECAY_MILLIS: u64 = 60_000;
+
+/// Approximate bytes held by a value, counted against maxmemory.
+pub trait MemoryUsage {
+    fn memory_usage(&self) -> usize;
+}
+
+impl MemoryUsage for Vec<u8> {
+    fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.len()
+    }
+}
+
+/// When a key was last read or written, and a logarithmic count of how often, as reported by
+/// OBJECT IDLETIME and OBJECT FREQ and used to choose keys to evict. Atomic so that reads
+/// can record themselves through a shared reference.
+#[derive(Debug)]
+pub struct Access {
+    /// Milliseconds since the unix epoch.
+    accessed: AtomicU64,
+    frequency: AtomicU8,
+}
+
+impl Access {
+    fn new() -> Self {
+        Access { accessed: AtomicU64::new(clock()), frequency: AtomicU8::new(LFU_INIT_VAL) }
+    }
+
+    /// Count an access, the counter is less likely to go up the higher it already is.
+    fn record(&self) {
+        let now = clock();
+        let mut frequency = self.frequency_at(now);
+        if frequency < u8::MAX {
+            let chance = 1.0 / (frequency.saturating_sub(LFU_INIT_VAL) as f64 * LFU_LOG_FACTOR + 1.0);
+            if (random() as f64) < chance * u64::MAX as f64 {
+                frequency += 1;
+            }
+        }
+        self.frequency.store(frequency, Ordering::Relaxed);
+        self.accessed.store(now, Ordering::Relaxed);
+    }
+
+    /// Milliseconds since the last access.
+    pub fn idle(&self) -> u64 {
+        clock().saturating_sub(self.accessed.load(Ordering::Relaxed))
+    }
+
+    /// The access counter, decayed for the time since the last access.
+    pub fn frequency(&self) -> u8 {
+        self.frequency_at(clock())
+    }
+
+    /// Pretend the last access was `millis` earlier than it was.
+    #[cfg(test)]
+    pub fn backdate(&self, millis: u64) {
+        self.accessed.fetch_sub(millis, Ordering::Relaxed);
+    }
+
+    fn frequency_at(&self, now: u64) -> u8 {
+        let periods = now.saturating_sub(self.accessed.load(Ordering::Relaxed)) / LFU_DECAY_MILLIS;
+        self.frequency.load(Ordering::Relaxed).saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+impl Clone for Access {
+    fn clone(&self) -> Self {
+        Access {
+            accessed: AtomicU64::new(self.accessed.load(Ordering::Relaxed)),
+            frequency: AtomicU8::new(self.frequency.load(Ordering::Relaxed)),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
-struct HNode {
+struct HNode<V> {
+    /// Full hash of the key, so the node can be moved between tables without rehashing.
     code: usize,
     key: String,
-    value: Arc<Vec<u8>>,
+    value: Arc<V>,
+    /// Approximate bytes held by the node, as counted in the table's `memory`.
+    size: usize,
+    access: Access,
 }
 
+impl<V: MemoryUsage> HNode<V> {
+    fn new(code: usize, key: &str, value: V) -> Self {
+        let size = node_size(key, &value);
+        HNode { code, key: key.to_string(), value: Arc::new(value), size, access: Access::new() }
+    }
+}
+
+/// The node itself, the Arc's counts, the key and the value.
+fn node_size<V: MemoryUsage>(key: &str, value: &V) -> usize {
+    size_of::<HNode<V>>() + 2 * size_of::<usize>() + key.len() + value.memory_usage()
+}
+
+/// Fixed size array of chained buckets.
 #[derive(Clone, Debug)]
-pub struct HTable {
-    table: Vec<Vec<Arc<HNode>>>,
-    keys: Vec<String>,
-    size: usize,
+struct HTab<V> {
+    table: Vec<Vec<HNode<V>>>,
     mask: usize,
+    size: usize,
+}
+
+impl<V> HTab<V> {
+    fn new(capacity: usize) -> Self {
+        assert!(capacity.is_power_of_two());
+        HTab { table: (0..capacity).map(|_| vec![]).collect(), mask: capacity - 1, size: 0 }
+    }
+
+    fn capacity(&self) -> usize {
+        self.table.len()
+    }
+
+    fn insert(&mut self, node: HNode<V>) {
+        self.table[node.code & self.mask].push(node);
+        self.size += 1;
+    }
+
+    fn lookup(&self, code: usize, key: &str) -> Option<&HNode<V>> {
+        find_matching_node(key, &self.table[code & self.mask])
+    }
+
+    fn lookup_mut(&mut self, code: usize, key: &str) -> Option<&mut HNode<V>> {
+        self.table[code & self.mask].iter_mut().find(|n| n.key == key)
+    }
+
+    fn detach(&mut self, code: usize, key: &str) -> Option<HNode<V>> {
+        let bucket = &mut self.table[code & self.mask];
+        let pos = bucket.iter().position(|n| n.key == key)?;
+        self.size -= 1;
+        Some(bucket.swap_remove(pos))
+    }
 }
 
-impl HTable {
-    /// size must be a power of 2
+/// Hash table that resizes with its load factor.
+///
+/// A resize allocates a new table and moves the nodes across a few buckets
+/// at a time on each insert and delete, rather than all at once, so no single
+/// write stalls on a large table. Lookups check both tables while this happens.
+///
+/// Keys are hashed with `S`, by default SipHash keyed with a random seed so
+/// bucket placement can't be predicted by clients.
+#[derive(Clone, Debug)]
+pub struct HTable<V, S = RandomState> {
+    hasher: S,
+    newer: HTab<V>,
+    older: Option<HTab<V>>,
+    resizing_pos: usize,
+    min_capacity: usize,
+    /// Approximate bytes held by every node.
+    memory: usize,
+}
+
+impl<V: MemoryUsage> HTable<V> {
+    /// size must be a power of 2, the table never shrinks below it.
     pub fn new(size: usize) -> Self {
-        assert!(size.is_power_of_two());
+        Self::with_hasher(size, RandomState::new())
+    }
+}
+
+impl<V: MemoryUsage, S: BuildHasher> HTable<V, S> {
+    /// Like `new`, hashing keys with `hasher` instead of the default SipHash.
+    pub fn with_hasher(size: usize, hasher: S) -> Self {
         Self {
-            table: vec![vec![]; size],
-            size: 0,
-            keys: vec![],
-            mask: size - 1,
-        }
-    }
-
-    pub fn insert(&mut self, key: &str, value: Vec<u8>) {
-        let h_key = hash_key(key) & self.mask;
-
-        if self.table[h_key].is_empty() {
-            let new_node = HNode {
-                code: h_key,
-                key: key.to_string(),
-                value: Arc::new(value),
-            };
-            self.table[h_key].push(Arc::new(new_node));
-        } else {
-            self.delete(key);
-            let new_node = HNode {
-                code: h_key,
-                key: key.to_string(),
-                value: Arc::new(value),
-            };
-            self.table[h_key].push(Arc::new(new_node));
-        }
-        self.keys.push(key.to_string());
-        self.size += 1;
+            hasher,
+            newer: HTab::new(size),
+            older: None,
+            resizing_pos: 0,
+            min_capacity: size,
+            memory: 0,
+        }
     }
 
-    pub fn get(&self, key: &str) -> Option<Arc<Vec<u8>>> {
-        let h_key = hash_key(key) & self.mask;
-        let node = find_matching_node(key, &self.table[h_key]);
+    pub fn insert(&mut self, key: &str, value: V) {
+        let code = self.hash_key(key);
+        self.detach(code, key);
+        let new_node = HNode::new(code, key, value);
+        self.memory += new_node.size;
+        self.newer.insert(new_node);
 
-        if node.is_some() {
-            let n = node.as_deref();
-            Some(n.unwrap().value.clone())
-        } else {
-            None
+        if self.older.is_none() && self.newer.size >= self.newer.capacity() * MAX_LOAD_FACTOR {
+            self.start_resizing(self.newer.capacity() * 2);
         }
+        self.help_resizing();
     }
 
-    pub fn delete(&mut self, key: &str) {
-        let h_key = hash_key(key) & self.mask;
-        let bucket = &mut self.table[h_key];
-        for i in 0..bucket.len() {
-            if let Some(val) = bucket.get(i) {
-                if val.key == key {
-                    bucket.remove(i);
-                    self.size -= 1;
-                }
-            }
+    /// The value at `key`, counted as an access to it.
+    pub fn get(&self, key: &str) -> Option<&Arc<V>> {
+        let node = self.lookup(key)?;
+        node.access.record();
+        Some(&node.value)
+    }
+
+    /// The value at `key`, to be changed in place with `Arc::make_mut`.
+    /// Call `refresh` afterwards so its size is counted again.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Arc<V>> {
+        let node = self.lookup_mut(key)?;
+        node.access.record();
+        Some(&mut node.value)
+    }
+
+    /// Count the size of the value at `key` again, after it was changed through `get_mut`.
+    pub fn refresh(&mut self, key: &str) {
+        let Some(node) = self.lookup_mut(key) else {
+            return;
+        };
+        let (before, after) = (node.size, node_size(&node.key, &*node.value));
+        node.size = after;
+        self.memory = self.memory - before + after;
+    }
+
+    /// When `key` was last accessed and how often, without counting this as an access.
+    pub fn access(&self, key: &str) -> Option<&Access> {
+        self.lookup(key).map(|n| &n.access)
+    }
+
+    /// Approximate bytes held by every key and value.
+    pub fn memory_usage(&self) -> usize {
+        self.memory
+    }
+
+    /// A key picked at random, keys that share a bucket or follow empty ones are a little more likely.
+    pub fn random_key(&self) -> Option<&str> {
+        let len = self.len();
+        if len == 0 {
+            return None;
         }
-        if let Some(to_remove) = self.keys.iter().enumerate().find(|k| *k.1 == key ) {
-            self.keys.swap_remove(to_remove.0);
+        let tab = match &self.older {
+            Some(older) if (random() % len as u64) < older.size as u64 => older,
+            _ => &self.newer,
+        };
+        let start = random() as usize;
+        let bucket = (0..tab.capacity())
+            .map(|i| &tab.table[start.wrapping_add(i) & tab.mask])
+            .find(|bucket| !bucket.is_empty())?;
+        Some(&bucket[random() as usize % bucket.len()].key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.lookup(key).is_some()
+    }
+
+    /// Remove `key`, handing back its value.
+    pub fn delete(&mut self, key: &str) -> Option<Arc<V>> {
+        let code = self.hash_key(key);
+        let node = self.detach(code, key)?;
+
+        let capacity = self.newer.capacity();
+        if self.older.is_none() && capacity > self.min_capacity && self.newer.size * MIN_LOAD_DIVISOR < capacity {
+            let target = (self.newer.size / (MAX_LOAD_FACTOR / 2)).next_power_of_two();
+            self.start_resizing(target.max(self.min_capacity));
         }
+        self.help_resizing();
+        Some(node.value)
+    }
+
+    /// Every key, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|(key, _)| key)
     }
 
-    pub fn keys(&self) -> &Vec<String> {
-        &self.keys
+    /// Every key and value, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<V>)> {
+        self.newer.table.iter()
+            .chain(self.older.iter().flat_map(|older| older.table.iter()))
+            .flatten()
+            .map(|n| (n.key.as_str(), &n.value))
     }
 
     pub fn len(&self) -> usize {
-        self.size
+        self.newer.size + self.older.as_ref().map_or(0, |older| older.size)
+    }
+
+    /// Visit one bucket's worth of nodes starting from `cursor`, returning the cursor
+    /// to continue from, or 0 once the whole table has been visited.
+    ///
+    /// The cursor advances through bucket indexes with their bits reversed, so
+    /// that a key present for the whole scan is returned at least once even if
+    /// the table grows or shrinks between calls, as in Redis' dictScan.
+    pub fn scan<F>(&self, cursor: usize, mut visit: F) -> usize
+    where
+        F: FnMut(&str, &Arc<V>),
+    {
+        let mut visit_bucket = |tab: &HTab<V>, cursor: usize| {
+            for n in &tab.table[cursor & tab.mask] {
+                visit(&n.key, &n.value);
+            }
+        };
+        let mut cursor = cursor;
+        match &self.older {
+            None => {
+                visit_bucket(&self.newer, cursor);
+                cursor = next_cursor(cursor, self.newer.mask);
+            },
+            Some(older) => {
+                let (small, large) = if older.mask < self.newer.mask { (older, &self.newer) } else { (&self.newer, older) };
+                visit_bucket(small, cursor);
+                // Then every bucket of the larger table that the small bucket expands to.
+                loop {
+                    visit_bucket(large, cursor);
+                    cursor = next_cursor(cursor, large.mask);
+                    if cursor & (small.mask ^ large.mask) == 0 {
+                        break;
+                    }
+                }
+            },
+        }
+        cursor
+    }
+
+    fn lookup(&self, key: &str) -> Option<&HNode<V>> {
+        let code = self.hash_key(key);
+        self.newer.lookup(code, key)
+            .or_else(|| self.older.as_ref().and_then(|older| older.lookup(code, key)))
+    }
+
+    fn lookup_mut(&mut self, key: &str) -> Option<&mut HNode<V>> {
+        let code = self.hash_key(key);
+        if self.newer.lookup(code, key).is_some() {
+            return self.newer.lookup_mut(code, key);
+        }
+        self.older.as_mut().and_then(|older| older.lookup_mut(code, key))
+    }
+
+    fn detach(&mut self, code: usize, key: &str) -> Option<HNode<V>> {
+        let node = self.newer.detach(code, key)
+            .or_else(|| self.older.as_mut().and_then(|older| older.detach(code, key)))?;
+        self.memory -= node.size;
+        Some(node)
+    }
+
+    fn hash_key(&self, key: &str) -> usize {
+        self.hasher.hash_one(key) as usize
+    }
+
+    fn start_resizing(&mut self, capacity: usize) {
+        let older = std::mem::replace(&mut self.newer, HTab::new(capacity));
+        self.older = Some(older);
+        self.resizing_pos = 0;
+    }
+
+    /// Move up to REHASH_WORK buckets from the older table into the newer one.
+    fn help_resizing(&mut self) {
+        let Some(older) = self.older.as_mut() else { return };
+        let mut work = 0;
+        while work < REHASH_WORK && older.size > 0 {
+            for node in older.table[self.resizing_pos].drain(..) {
+                older.size -= 1;
+                self.newer.insert(node);
+            }
+            self.resizing_pos += 1;
+            work += 1;
+        }
+        if older.size == 0 {
+            self.older = None;
+            self.resizing_pos = 0;
+        }
     }
 }
 
-fn hash_key(key: &str) -> usize {
-    key.chars().fold(0usize, |acc, val| acc + val as usize)
+/// Milliseconds since the unix epoch.
+fn clock() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
 }
 
-fn find_matching_node<'a, 'b>(key: &'a str, bucket: &'b Vec<Arc<HNode>>) -> Option<&'b Arc<HNode>> {
+/// A cheap pseudo random number for sampling keys and the access counter, xorshift seeded per thread.
+pub fn random() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().hash_one(0u8) | 1);
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
+
+/// Increment the reversed bits of `cursor` that fall within `mask`.
+fn next_cursor(cursor: usize, mask: usize) -> usize {
+    let cursor = cursor | !mask;
+    (cursor.reverse_bits().wrapping_add(1)).reverse_bits()
+}
+
+fn find_matching_node<'b, V>(key: &str, bucket: &'b [HNode<V>]) -> Option<&'b HNode<V>> {
     bucket.iter().find(|n| n.key == key)
 }
 
 #[cfg(test)]
 mod test {
+    use std::hash::{BuildHasherDefault, Hasher};
+
     use super::*;
 
+    /// Sends every key to the same bucket.
+    #[derive(Default)]
+    struct CollidingHasher;
+
+    impl Hasher for CollidingHasher {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _bytes: &[u8]) {}
+    }
+
+    type Colliding = BuildHasherDefault<CollidingHasher>;
+
     #[test]
     fn test_hash_key() {
-        let value = "hi";
-        let hash_key = hash_key(value);
+        let table = HTable::<Vec<u8>>::new(2);
+        assert_eq!(table.hash_key("key"), table.hash_key("key"));
+        assert_ne!(table.hash_key("key"), table.hash_key("yek"));
 
-        // 104 + 105 = 209
-        assert_eq!(209, hash_key);
-        println!("{}", hash_key);
+        // Each table is seeded differently.
+        let other = HTable::<Vec<u8>>::new(2);
+        assert_ne!(table.hash_key("key"), other.hash_key("key"));
     }
 
     #[test]
     fn test_find_matching_node() {
         let bucket = vec!(
-            Arc::new(
-                HNode {
-                    code: 123,
-                    key: String::from("node1"),
-                    value: Arc::new(String::from("val1").into_bytes()),
-                }),
-            Arc::new(
-                HNode {
-                    code: 123,
-                    key: String::from("node2"),
-                    value: Arc::new(String::from("val2").into_bytes()),
-                }),
-            Arc::new(
-                HNode {
-                    code: 123,
-                    key: String::from("node3"),
-                    value: Arc::new(String::from("val3").into_bytes()),
-                })
+            HNode::new(123, "node1", String::from("val1").into_bytes()),
+            HNode::new(123, "node2", String::from("val2").into_bytes()),
+            HNode::new(123, "node3", String::from("val3").into_bytes()),
         );
 
-        assert_eq!(String::from("val3").into_bytes(), *find_matching_node("node3", &bucket).as_deref().unwrap().value);
-        assert_eq!(String::from("val2").into_bytes(), *find_matching_node("node2", &bucket).as_deref().unwrap().value);
-        assert_eq!(String::from("val1").into_bytes(), *find_matching_node("node1", &bucket).as_deref().unwrap().value);
+        assert_eq!(String::from("val3").into_bytes(), *find_matching_node("node3", &bucket).unwrap().value);
+        assert_eq!(String::from("val2").into_bytes(), *find_matching_node("node2", &bucket).unwrap().value);
+        assert_eq!(String::from("val1").into_bytes(), *find_matching_node("node1", &bucket).unwrap().value);
         assert!(find_matching_node("nothing", &bucket).is_none());
-        assert!(find_matching_node("anything", &vec![]).is_none());
+        assert!(find_matching_node::<Vec<u8>>("anything", &[]).is_none());
     }
 
     #[tokio::test]
@@ -151,10 +467,52 @@ mod test {
         table.insert(&key2, b"Value2".to_vec());
         table.insert(&key3, b"Value3".to_vec());
         table.insert(&key4, b"Value4".to_vec());
-        assert_eq!(&vec![key1.clone(), key2.clone(), key3.clone(), key4.clone()], table.keys());
+        assert_eq!(vec![key1.clone(), key2.clone(), key3.clone(), key4.clone()], sorted_keys(&table));
 
         table.delete(&key4);
-        assert_eq!(&vec![key1, key2, key3], table.keys());
+        assert_eq!(vec![key1, key2, key3], sorted_keys(&table));
+        assert!(!table.contains_key(&key4));
+    }
+
+    #[test]
+    fn test_keys_overwrite() {
+        let mut table = HTable::new(2);
+        table.insert("key", b"value1".to_vec());
+        table.insert("key", b"value2".to_vec());
+        table.insert("other", b"value3".to_vec());
+
+        assert_eq!(vec!["key", "other"], sorted_keys(&table));
+        assert_eq!(2, table.len());
+        assert_eq!(b"value2".to_vec(), **table.get("key").unwrap());
+
+        table.delete("key");
+        assert_eq!(vec!["other"], sorted_keys(&table));
+        assert_eq!(1, table.len());
+        assert!(!table.contains_key("key"));
+
+        table.delete("key");
+        assert_eq!(1, table.len());
+    }
+
+    #[test]
+    fn test_keys_while_resizing() {
+        let mut table = HTable::new(2);
+        let mut i = 0;
+        while table.older.is_none() || table.older.as_ref().unwrap().size == 0 {
+            table.insert(&format!("key{i}"), b"value".to_vec());
+            table.insert(&format!("key{i}"), b"overwritten".to_vec());
+            i += 1;
+        }
+        let mut expected: Vec<String> = (0..i).map(|j| format!("key{j}")).collect();
+        expected.sort();
+        assert_eq!(expected, sorted_keys(&table));
+        assert_eq!(i, table.len());
+    }
+
+    fn sorted_keys<V: MemoryUsage, S: BuildHasher>(table: &HTable<V, S>) -> Vec<String> {
+        let mut keys: Vec<String> = table.keys().map(String::from).collect();
+        keys.sort();
+        keys
     }
 
     #[tokio::test]
@@ -166,7 +524,7 @@ mod test {
         assert!(table.get(key).is_none());
 
         table.insert(key, value.into_bytes());
-        assert_eq!(String::from("value").into_bytes(), *table.get(key).unwrap());
+        assert_eq!(String::from("value").into_bytes(), **table.get(key).unwrap());
 
         table.delete(key);
         assert!(table.get(key).is_none());
@@ -177,13 +535,13 @@ mod test {
         let key = "key";
         let yek = "yek";
 
-        let mut table = HTable::new(2);
+        let mut table = HTable::with_hasher(2, Colliding::default());
 
         table.insert(key, String::from("value1").into_bytes());
         table.insert(yek, String::from("value2").into_bytes());
 
-        assert_eq!(String::from("value1").into_bytes(), *table.get(key).unwrap());
-        assert_eq!(String::from("value2").into_bytes(), *table.get(yek).unwrap());
+        assert_eq!(String::from("value1").into_bytes(), **table.get(key).unwrap());
+        assert_eq!(String::from("value2").into_bytes(), **table.get(yek).unwrap());
     }
 
     #[tokio::test]
@@ -191,21 +549,159 @@ mod test {
         let key = "key";
         let yek = "yek";
 
-        let mut table = HTable::new(2);
+        let mut table = HTable::with_hasher(2, Colliding::default());
 
         table.insert(key, String::from("value1").into_bytes());
         table.insert(yek, String::from("value2").into_bytes());
 
-        assert_eq!(String::from("value1").into_bytes(), *table.get(key).unwrap());
-        assert_eq!(String::from("value2").into_bytes(), *table.get(yek).unwrap());
+        assert_eq!(String::from("value1").into_bytes(), **table.get(key).unwrap());
+        assert_eq!(String::from("value2").into_bytes(), **table.get(yek).unwrap());
 
         table.delete(key);
         assert!(table.get(key).is_none());
-        assert_eq!(String::from("value2").into_bytes(), *table.get(yek).unwrap());
+        assert_eq!(String::from("value2").into_bytes(), **table.get(yek).unwrap());
 
         table.delete(yek);
         assert!(table.get(key).is_none());
         assert!(table.get(yek).is_none());
     }
 
+    #[test]
+    fn test_grow_progressively() {
+        let mut table = HTable::new(2);
+        let count = 2 * MAX_LOAD_FACTOR;
+        for i in 0..count {
+            table.insert(&format!("key{i}"), format!("value{i}").into_bytes());
+        }
+        assert_eq!(count, table.len());
+        assert!(table.newer.capacity() > 2);
+
+        // Keep inserting until a resize is caught part way through.
+        let mut i = count;
+        while table.older.is_none() || table.older.as_ref().unwrap().size == 0 {
+            table.insert(&format!("key{i}"), format!("value{i}").into_bytes());
+            i += 1;
+        }
+        assert!(table.older.is_some());
+        for j in 0..i {
+            assert_eq!(format!("value{j}").into_bytes(), **table.get(&format!("key{j}")).unwrap());
+        }
+        assert_eq!(i, table.len());
+    }
+
+    #[test]
+    fn test_shrink() {
+        let mut table = HTable::new(2);
+        for i in 0..1000 {
+            table.insert(&format!("key{i}"), b"value".to_vec());
+        }
+        let grown = table.newer.capacity();
+        assert!(grown >= 1000 / MAX_LOAD_FACTOR);
+
+        for i in 0..995 {
+            table.delete(&format!("key{i}"));
+        }
+        assert_eq!(5, table.len());
+        assert!(table.newer.capacity() < grown);
+        for i in 995..1000 {
+            assert!(table.get(&format!("key{i}")).is_some());
+        }
+
+        for i in 995..1000 {
+            table.delete(&format!("key{i}"));
+        }
+        assert_eq!(0, table.len());
+        assert!(table.newer.capacity() >= 2);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut table = HTable::new(2);
+        table.insert("a", vec![0; 100]);
+        let entry = table.memory_usage();
+        assert!(entry > 100);
+        table.insert("b", vec![0; 100]);
+        assert_eq!(2 * entry, table.memory_usage());
+
+        Arc::make_mut(table.get_mut("a").unwrap()).extend_from_slice(&[0; 50]);
+        table.refresh("a");
+        assert_eq!(2 * entry + 50, table.memory_usage());
+        table.insert("b", vec![]);
+        assert_eq!(2 * entry - 50, table.memory_usage());
+
+        table.delete("a");
+        table.delete("b");
+        assert_eq!(0, table.memory_usage());
+    }
+
+    #[test]
+    fn test_access() {
+        let mut table = HTable::new(2);
+        assert!(table.random_key().is_none());
+        for i in 0..100 {
+            table.insert(&format!("key{i}"), b"value".to_vec());
+        }
+        let sampled: std::collections::HashSet<&str> = (0..1000).filter_map(|_| table.random_key()).collect();
+        assert!(sampled.len() > 50);
+
+        let access = table.access("key0").unwrap();
+        assert_eq!(LFU_INIT_VAL, access.frequency());
+        assert!(access.idle() < 1000);
+        for _ in 0..100 {
+            table.get("key0");
+        }
+        assert!(table.access("key0").unwrap().frequency() > LFU_INIT_VAL);
+        assert_eq!(LFU_INIT_VAL, table.access("key1").unwrap().frequency());
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut table = HTable::new(2);
+        for i in 0..100 {
+            table.insert(&format!("key{i}"), b"value".to_vec());
+        }
+        let mut seen = vec![];
+        let mut cursor = 0;
+        loop {
+            cursor = table.scan(cursor, |key, _| seen.push(key.to_string()));
+            if cursor == 0 {
+                break;
+            }
+        }
+        seen.sort();
+        assert_eq!(sorted_keys(&table), seen);
+    }
+
+    #[test]
+    fn test_scan_across_resize() {
+        let mut table = HTable::new(2);
+        for i in 0..100 {
+            table.insert(&format!("key{i}"), b"value".to_vec());
+        }
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = 0;
+        let mut added = 100;
+        let mut removed = 0;
+        loop {
+            cursor = table.scan(cursor, |key, _| { seen.insert(key.to_string()); });
+            if cursor == 0 {
+                break;
+            }
+            // Grow the table part way through, then shrink it back down.
+            if added < 2000 {
+                for _ in 0..100 {
+                    table.insert(&format!("added{added}"), b"value".to_vec());
+                    added += 1;
+                }
+            } else if removed < 1900 {
+                for _ in 0..100 {
+                    table.delete(&format!("added{}", 100 + removed));
+                    removed += 1;
+                }
+            }
+        }
+        for i in 0..100 {
+            assert!(seen.contains(&format!("key{i}")), "key{i} was not returned");
+        }
+    }
 }
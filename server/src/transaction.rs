@@ -0,0 +1,174 @@
+use std::io::{self, Error, ErrorKind};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use rusty_redis_core::command::Command;
+
+use super::store::DataStore;
+
+/// The data store as a command sees it, either shared with every other connection
+/// or already write locked for the whole of an EXEC.
+pub enum StoreRef<'a> {
+    Shared(Arc<RwLock<DataStore>>),
+    Locked(&'a mut DataStore),
+}
+
+impl StoreRef<'_> {
+    pub async fn read(&self) -> StoreReadGuard<'_> {
+        match self {
+            StoreRef::Shared(store) => StoreReadGuard::Shared(store.read().await),
+            StoreRef::Locked(store) => StoreReadGuard::Locked(store),
+        }
+    }
+
+    pub async fn write(&mut self) -> StoreWriteGuard<'_> {
+        match self {
+            StoreRef::Shared(store) => StoreWriteGuard::Shared(store.write().await),
+            StoreRef::Locked(store) => StoreWriteGuard::Locked(store),
+        }
+    }
+}
+
+pub enum StoreReadGuard<'a> {
+    Shared(RwLockReadGuard<'a, DataStore>),
+    Locked(&'a DataStore),
+}
+
+impl Deref for StoreReadGuard<'_> {
+    type Target = DataStore;
+
+    fn deref(&self) -> &DataStore {
+        match self {
+            StoreReadGuard::Shared(guard) => guard,
+            StoreReadGuard::Locked(store) => store,
+        }
+    }
+}
+
+pub enum StoreWriteGuard<'a> {
+    Shared(RwLockWriteGuard<'a, DataStore>),
+    Locked(&'a mut DataStore),
+}
+
+impl Deref for StoreWriteGuard<'_> {
+    type Target = DataStore;
+
+    fn deref(&self) -> &DataStore {
+        match self {
+            StoreWriteGuard::Shared(guard) => guard,
+            StoreWriteGuard::Locked(store) => store,
+        }
+    }
+}
+
+impl DerefMut for StoreWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut DataStore {
+        match self {
+            StoreWriteGuard::Shared(guard) => guard,
+            StoreWriteGuard::Locked(store) => store,
+        }
+    }
+}
+
+/// Commands a connection has queued since MULTI, and the keys it WATCHes along
+/// with the version each had at the time.
+#[derive(Default)]
+pub struct Transaction {
+    queued: Option<Vec<Command>>,
+    /// A command was rejected while queueing, so EXEC has to discard the lot.
+    failed: bool,
+    watched: Vec<(String, u64)>,
+}
+
+impl Transaction {
+    /// Start queueing commands, returns false if a transaction is already open.
+    pub fn begin(&mut self) -> bool {
+        if self.queued.is_some() {
+            return false;
+        }
+        self.queued = Some(vec![]);
+        self.failed = false;
+        true
+    }
+
+    pub fn is_queueing(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    pub fn queue(&mut self, cmd: Command) {
+        if let Some(queued) = self.queued.as_mut() {
+            queued.push(cmd);
+        }
+    }
+
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// Drop the queued commands, returns false if no transaction was open.
+    pub fn discard(&mut self) -> bool {
+        self.queued.take().is_some()
+    }
+
+    /// Close the transaction, handing back its commands unless one was rejected while queueing.
+    pub fn exec(&mut self) -> io::Result<Vec<Command>> {
+        let queued = self.queued.take()
+            .ok_or(Error::new(ErrorKind::InvalidInput, "ERR EXEC without MULTI"))?;
+        if self.failed {
+            return Err(Error::new(ErrorKind::InvalidInput, "EXECABORT Transaction discarded because of previous errors."));
+        }
+        Ok(queued)
+    }
+
+    pub fn watch(&mut self, store: &mut DataStore, keys: Vec<String>) {
+        for key in keys {
+            let version = store.watch(&key);
+            self.watched.push((key, version));
+        }
+    }
+
+    pub fn unwatch(&mut self, store: &mut DataStore) {
+        for (key, _) in self.watched.drain(..) {
+            store.unwatch(&key);
+        }
+    }
+
+    pub fn is_watching(&self) -> bool {
+        !self.watched.is_empty()
+    }
+
+    /// Whether none of the watched keys have been written since WATCH.
+    pub fn unchanged(&self, store: &DataStore) -> bool {
+        self.watched.iter().all(|(key, version)| store.version(key) == *version)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transaction() {
+        let mut store = DataStore::new(16);
+        let mut transaction = Transaction::default();
+        assert!(transaction.exec().is_err());
+
+        transaction.watch(&mut store, vec!["a".to_string()]);
+        assert!(transaction.begin());
+        assert!(!transaction.begin());
+        transaction.queue(Command::GET("a".to_string()));
+        assert_eq!(vec![Command::GET("a".to_string())], transaction.exec().unwrap());
+        assert!(transaction.unchanged(&store));
+        store.insert("a", b"1".to_vec(), 0);
+        assert!(!transaction.unchanged(&store));
+        transaction.unwatch(&mut store);
+        assert!(!transaction.is_watching());
+
+        transaction.begin();
+        transaction.fail();
+        assert!(transaction.exec().is_err());
+        assert!(!transaction.discard());
+    }
+}
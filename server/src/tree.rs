@@ -1,38 +1,113 @@
-#![allow(dead_code)]
-use std::cmp;
-use std::fmt::Display;
+use std::cmp::{self, Ordering};
+use std::fmt::{self, Debug};
 
-#[derive(Debug)]
-struct AVLTree<T> {
-    root: Option<Box<Node<T>>>,
+type Link<T> = Option<Box<Node<T>>>;
+
+/// A self balancing binary search tree holding unique, ordered values.
+///
+/// Every node tracks the size of its subtree, which makes it an order statistic tree:
+/// the rank of a value and the value at an index are found in O(log n).
+#[derive(Clone)]
+pub struct AVLTree<T> {
+    root: Link<T>,
 }
 
-impl<T> AVLTree<T> where T: Ord + Display {
-    fn new() -> Self {
+impl<T> AVLTree<T> where T: Ord {
+    pub fn new() -> Self {
         AVLTree { root: None }
     }
 
-    fn insert(&mut self, data: T) {
-        if let Some(mut root_node) = self.root.take() {
-            root_node.insert(data);
-            let balance = root_node.balance_factor();
-            self.root = match balance {
-                x if x > 1 => Some(root_node.right_rotation(0)),
-                x if x < -1 => Some(root_node.left_rotation(0)),
-                _ => Some(root_node)
-            };
-        } else {
-            self.root = Some(Box::new(Node {
-                data,
-                left: None,
-                right: None,
-            }));
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    /// Returns false, leaving the tree unchanged, if an equal value is already present.
+    pub fn insert(&mut self, data: T) -> bool {
+        let mut inserted = false;
+        self.root = Some(Node::insert(self.root.take(), data, &mut inserted));
+        inserted
+    }
+
+    /// Remove and return the value equal to `data`.
+    pub fn delete(&mut self, data: &T) -> Option<T> {
+        let (root, removed) = Node::delete(self.root.take(), data);
+        self.root = root;
+        removed
+    }
+
+    /// Zero based position of `data` in the tree's ordering.
+    pub fn rank(&self, data: &T) -> Option<usize> {
+        let mut rank = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            match data.cmp(&node.data) {
+                Ordering::Less => link = &node.left,
+                Ordering::Greater => {
+                    rank += size(&node.left) + 1;
+                    link = &node.right;
+                },
+                Ordering::Equal => return Some(rank + size(&node.left)),
+            }
         }
+        None
+    }
+
+    /// Number of values for which `pred` holds, where `pred` holds for every value
+    /// before some point in the ordering and for none after it, like `slice::partition_point`.
+    pub fn partition_point<P>(&self, pred: P) -> usize
+        where P: Fn(&T) -> bool
+    {
+        let mut count = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            if pred(&node.data) {
+                count += size(&node.left) + 1;
+                link = &node.right;
+            } else {
+                link = &node.left;
+            }
+        }
+        count
+    }
+
+    /// Values between the `start` and `stop` positions inclusive, in order.
+    pub fn range(&self, start: usize, stop: usize) -> Vec<&T> {
+        let mut values = vec![];
+        if start <= stop {
+            Node::collect_range(&self.root, 0, start, stop, &mut values);
+        }
+        values
+    }
+
+    /// Every value in order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        let mut iter = Iter { stack: vec![] };
+        iter.push_left(&self.root);
+        iter
+    }
+}
+
+impl<T> Default for AVLTree<T> where T: Ord {
+    fn default() -> Self {
+        AVLTree::new()
+    }
+}
+
+/// Trees holding the same values are equal, whatever their shape.
+impl<T> PartialEq for AVLTree<T> where T: Ord {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
     }
+}
 
+impl<T> Debug for AVLTree<T> where T: Ord + Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
 }
 
-impl<T> AVLTree<T> where T: Display + Ord {
+#[cfg(test)]
+impl<T> AVLTree<T> where T: fmt::Display + Ord {
     fn print_data(&self) {
         if let Some(n) = &self.root {
             n.print_tree()
@@ -40,98 +115,186 @@ impl<T> AVLTree<T> where T: Display + Ord {
     }
 }
 
-#[derive(Debug)]
+/// In order iterator over an AVLTree.
+pub struct Iter<'a, T> {
+    stack: Vec<&'a Node<T>>,
+}
+
+impl<'a, T> Iter<'a, T> {
+    fn push_left(&mut self, mut link: &'a Link<T>) {
+        while let Some(node) = link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        Some(&node.data)
+    }
+}
+
+#[derive(Clone, Debug)]
 struct Node<T> {
     data: T,
-    left: Option<Box<Node<T>>>,
-    right: Option<Box<Node<T>>>,
+    left: Link<T>,
+    right: Link<T>,
+    height: i32,
+    /// Number of values in the subtree rooted at this node, itself included.
+    size: usize,
 }
 
-impl<T> Node<T> where T: Ord + Display {
-    fn new(data: T) -> Self {
-        Node {
+impl<T> Node<T> where T: Ord {
+    fn new(data: T) -> Box<Self> {
+        Box::new(Node {
             data,
             left: None,
             right: None,
+            height: 0,
+            size: 1,
+        })
+    }
+
+    fn insert(link: Link<T>, data: T, inserted: &mut bool) -> Box<Node<T>> {
+        let Some(mut node) = link else {
+            *inserted = true;
+            return Node::new(data);
+        };
+        match data.cmp(&node.data) {
+            Ordering::Less => node.left = Some(Node::insert(node.left.take(), data, inserted)),
+            Ordering::Greater => node.right = Some(Node::insert(node.right.take(), data, inserted)),
+            Ordering::Equal => return node,
+        }
+        node.rebalance()
+    }
+
+    fn delete(link: Link<T>, data: &T) -> (Link<T>, Option<T>) {
+        let Some(mut node) = link else {
+            return (None, None);
+        };
+        match data.cmp(&node.data) {
+            Ordering::Less => {
+                let (left, removed) = Node::delete(node.left.take(), data);
+                node.left = left;
+                (Some(node.rebalance()), removed)
+            },
+            Ordering::Greater => {
+                let (right, removed) = Node::delete(node.right.take(), data);
+                node.right = right;
+                (Some(node.rebalance()), removed)
+            },
+            Ordering::Equal => match (node.left.take(), node.right.take()) {
+                (None, child) | (child, None) => (child, Some(node.data)),
+                (Some(left), Some(right)) => {
+                    // Replace the value with its successor, the smallest value on the right.
+                    let (right, successor) = Node::delete_min(right);
+                    let removed = std::mem::replace(&mut node.data, successor);
+                    node.left = Some(left);
+                    node.right = right;
+                    (Some(node.rebalance()), Some(removed))
+                },
+            },
+        }
+    }
+
+    fn delete_min(mut node: Box<Node<T>>) -> (Link<T>, T) {
+        match node.left.take() {
+            None => (node.right.take(), node.data),
+            Some(left) => {
+                let (left, min) = Node::delete_min(left);
+                node.left = left;
+                (Some(node.rebalance()), min)
+            },
         }
     }
 
-    fn insert(&mut self, data: T) {
-        if data < self.data {
-            self.left = if let Some(mut node) = self.left.take() {
-                node.insert(data);
-                let balance = node.balance_factor();
-                match balance {
-                    x if x > 1 => Some(node.right_rotation(0)),
-                    x if x < -1 => Some(node.left_rotation(0)),
-                    _ => Some(node)
+    /// Refresh the cached height and size, then rotate if either side became too tall.
+    fn rebalance(mut self: Box<Self>) -> Box<Self> {
+        self.update();
+        match self.balance_factor() {
+            x if x > 1 => {
+                if self.left.as_ref().is_some_and(|l| l.balance_factor() < 0) {
+                    self.left = self.left.take().map(|l| l.left_rotation());
                 }
-            } else {
-                Some(Box::new(Node::new(data)))
-            }
-        } else {
-            self.right = if let Some(mut node) = self.right.take() {
-                node.insert(data);
-                let balance = node.balance_factor();
-                match balance {
-                    x if x > 1 => Some(node.right_rotation(0)),
-                    x if x < -1 => Some(node.left_rotation(0)),
-                    _ => Some(node)
+                self.right_rotation()
+            },
+            x if x < -1 => {
+                if self.right.as_ref().is_some_and(|r| r.balance_factor() > 0) {
+                    self.right = self.right.take().map(|r| r.right_rotation());
                 }
-            } else {
-                Some(Box::new(Node::new(data)))
-            }
+                self.left_rotation()
+            },
+            _ => self,
         }
     }
 
-    fn right_rotation(mut self, depth: u8) -> Box<Node<T>> {
-        let mut l = self.left.take().unwrap();
-        if l.balance_factor() < 0 && depth < 2 {
-                l = l.left_rotation(depth+1);
+    fn collect_range<'a>(link: &'a Link<T>, offset: usize, start: usize, stop: usize, values: &mut Vec<&'a T>) {
+        let Some(node) = link else {
+            return;
+        };
+        let index = offset + size(&node.left);
+        if start < index {
+            Node::collect_range(&node.left, offset, start, stop, values);
         }
-        if l.right.is_some() {
-            let _ = self.left.insert(l.right.take().unwrap());
+        if (start..=stop).contains(&index) {
+            values.push(&node.data);
         }
-        let _ = l.right.insert(Box::new(self));
+        if stop > index {
+            Node::collect_range(&node.right, index + 1, start, stop, values);
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn right_rotation(mut self: Box<Self>) -> Box<Self> {
+        let mut l = self.left.take().unwrap();
+        self.left = l.right.take();
+        self.update();
+        l.right = Some(self);
+        l.update();
         l
     }
 
-    fn left_rotation(mut self, depth: u8) -> Box<Node<T>> {
+    fn left_rotation(mut self: Box<Self>) -> Box<Self> {
         let mut r = self.right.take().unwrap();
-        if r.balance_factor() > 0 && depth < 2 {
-            r = r.right_rotation(depth+1);
-        }
-        if r.left.is_some() {
-            let _ = self.right.insert(r.left.take().unwrap());
-        }
-        let _ = r.left.insert(Box::new(self));
+        self.right = r.left.take();
+        self.update();
+        r.left = Some(self);
+        r.update();
         r
     }
 
-    fn balance_factor(&self) -> i32 {
-        self.left_height() - self.right_height()
+    fn update(&mut self) {
+        self.height = cmp::max(self.left_height(), self.right_height()) + 1;
+        self.size = size(&self.left) + size(&self.right) + 1;
     }
 
-    fn height(&self) -> i32 {
-        cmp::max(self.left_height(), self.right_height()) + 1
+    fn balance_factor(&self) -> i32 {
+        self.left_height() - self.right_height()
     }
 
-    fn left_height(&self) -> i32  {
+    fn left_height(&self) -> i32 {
         match &self.left {
-            Some(n) => n.height(),
+            Some(n) => n.height,
             None => -1,
         }
     }
 
-    fn right_height(&self) -> i32  {
+    fn right_height(&self) -> i32 {
         match &self.right {
-            Some(n) => n.height(),
+            Some(n) => n.height,
             None => -1,
         }
     }
 }
 
-impl<T> Node<T> where T: Display + Ord {
+#[cfg(test)]
+impl<T> Node<T> where T: fmt::Display + Ord {
     fn print_tree(&self) {
         if let Some(l) = &self.left {
             l.print_tree();
@@ -150,7 +313,7 @@ impl<T> Node<T> where T: Display + Ord {
 
         println!("{}, {}, {}, l:{}, r:{}",
             self.data,
-            self.height(),
+            self.height,
             self.balance_factor(),
             l,
             r
@@ -162,9 +325,28 @@ impl<T> Node<T> where T: Display + Ord {
     }
 }
 
+fn size<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
 #[cfg(test)]
 mod test {
-    use super::AVLTree;
+    use super::{AVLTree, Link};
+
+    /// Check the AVL invariants and cached fields of every node, returning the height.
+    fn assert_valid<T: Ord>(link: &Link<T>) -> i32 {
+        let Some(node) = link else {
+            return -1;
+        };
+        let left = assert_valid(&node.left);
+        let right = assert_valid(&node.right);
+        assert!((left - right).abs() <= 1, "unbalanced node");
+        assert_eq!(left.max(right) + 1, node.height);
+        assert_eq!(super::size(&node.left) + super::size(&node.right) + 1, node.size);
+        assert!(node.left.as_ref().is_none_or(|l| l.data < node.data));
+        assert!(node.right.as_ref().is_none_or(|r| r.data > node.data));
+        node.height
+    }
 
     #[test]
     fn test_insert() {
@@ -179,6 +361,10 @@ mod test {
         tree.insert(3);
         tree.insert(7);
         tree.print_data();
+        assert_valid(&tree.root);
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8, 9], tree.iter().copied().collect::<Vec<_>>());
+        assert!(!tree.insert(4));
+        assert_eq!(9, tree.len());
     }
 
     #[test]
@@ -190,6 +376,7 @@ mod test {
         tree.insert(5);
         tree.insert(4);
         tree.print_data();
+        assert_valid(&tree.root);
     }
 
     #[test]
@@ -198,6 +385,7 @@ mod test {
         tree.insert(5);
         tree.insert(7);
         tree.print_data();
+        assert_eq!(1, assert_valid(&tree.root));
     }
 
     #[test]
@@ -206,5 +394,58 @@ mod test {
         tree.insert("Hello");
         tree.insert("Stream");
         tree.print_data();
+        assert_eq!(Some(1), tree.rank(&"Stream"));
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut tree = AVLTree::new();
+        for i in 0..100 {
+            tree.insert((i * 37) % 100);
+        }
+        assert_valid(&tree.root);
+        for i in (0..100).step_by(3) {
+            assert_eq!(Some(i), tree.delete(&i));
+            assert_valid(&tree.root);
+        }
+        assert_eq!(None, tree.delete(&0));
+        assert_eq!(66, tree.len());
+        assert_eq!(None, tree.rank(&3));
+        assert!(tree.rank(&4).is_some());
+
+        for i in 0..100 {
+            tree.delete(&i);
+        }
+        assert_eq!(0, tree.len());
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut tree = AVLTree::new();
+        for i in (0..50).rev() {
+            tree.insert(i * 2);
+        }
+        for i in 0..50 {
+            assert_eq!(Some(i), tree.rank(&(i * 2)));
+            assert_eq!(vec![&(i * 2)], tree.range(i, i));
+        }
+        assert_eq!(None, tree.rank(&3));
+        assert!(tree.range(50, 50).is_empty());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut tree = AVLTree::new();
+        for i in 0..20 {
+            tree.insert(i);
+        }
+        assert_eq!(vec![&5, &6, &7], tree.range(5, 7));
+        assert_eq!(vec![&18, &19], tree.range(18, 100));
+        assert!(tree.range(7, 5).is_empty());
+        assert!(tree.range(20, 25).is_empty());
+
+        assert_eq!(5, tree.partition_point(|&v| v < 5));
+        assert_eq!(0, tree.partition_point(|&v| v < 0));
+        assert_eq!(20, tree.partition_point(|_| true));
     }
 }
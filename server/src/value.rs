@@ -0,0 +1,103 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Error, ErrorKind};
+use std::mem::size_of;
+
+use super::sorted_set::SortedSet;
+use super::table::MemoryUsage;
+
+/// Elements looked at to estimate the size of a collection.
+const MEMORY_SAMPLES: usize = 5;
+
+/// A value held by the DataStore, one variant per Redis data type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Set(HashSet<Vec<u8>>),
+    SortedSet(SortedSet),
+}
+
+impl Value {
+    /// Name of the type as reported by TYPE and matched by SCAN's TYPE option.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+        }
+    }
+}
+
+impl MemoryUsage for Value {
+    /// Collections are estimated from their first few elements, so that a write to a large
+    /// one doesn't have to walk every element to count it again.
+    fn memory_usage(&self) -> usize {
+        let element = size_of::<Vec<u8>>();
+        size_of::<Value>() + match self {
+            Value::String(data) => data.len(),
+            Value::List(items) => estimate(items.len(), items.iter().map(|item| element + item.len())),
+            Value::Hash(fields) => estimate(fields.len(), fields.iter().map(|(field, value)| 2 * element + field.len() + value.len())),
+            Value::Set(members) => estimate(members.len(), members.iter().map(|member| element + member.len())),
+            // Each member is held by both the score map and the tree.
+            Value::SortedSet(set) => estimate(set.len(), set.iter().map(|(member, _)| 2 * (element + member.len() + size_of::<f64>()))),
+        }
+    }
+}
+
+/// Scale the average size of the first few of `sizes` up to `len` elements.
+fn estimate(len: usize, sizes: impl Iterator<Item = usize>) -> usize {
+    let (sampled, total) = sizes.take(MEMORY_SAMPLES).fold((0, 0), |(sampled, total), size| (sampled + 1, total + size));
+    if sampled == 0 {
+        return 0;
+    }
+    total * len / sampled
+}
+
+pub fn wrong_type() -> Error {
+    Error::new(ErrorKind::InvalidInput, "WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+/// Resolve Redis style `start` and `stop` indexes, where negative values count
+/// back from the end, into a range over a collection of `len` items.
+pub fn resolve_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
+
+/// Resolve a single Redis style index, where negative values count back from the end.
+pub fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_range() {
+        assert_eq!(Some((0, 4)), resolve_range(0, -1, 5));
+        assert_eq!(Some((3, 4)), resolve_range(-2, -1, 5));
+        assert_eq!(Some((0, 4)), resolve_range(-100, 100, 5));
+        assert_eq!(Some((1, 2)), resolve_range(1, 2, 5));
+        assert_eq!(None, resolve_range(3, 1, 5));
+        assert_eq!(None, resolve_range(5, 10, 5));
+        assert_eq!(None, resolve_range(0, -1, 0));
+    }
+
+    #[test]
+    fn test_resolve_index() {
+        assert_eq!(Some(0), resolve_index(0, 3));
+        assert_eq!(Some(2), resolve_index(-1, 3));
+        assert_eq!(None, resolve_index(3, 3));
+        assert_eq!(None, resolve_index(-4, 3));
+    }
+}